@@ -0,0 +1,18 @@
+// Native capability providers hand the host a `CapabilityProvider` trait object, whose layout
+// only holds for the same wascc-codec and the same compiler. The provider ABI version names
+// both, so that the host can refuse a provider built with anything else before using it. The
+// codec reports its own version, while the compiler version is found out here
+
+use std::process::Command;
+
+fn main() {
+    let rustc = std::env::var("RUSTC").expect("cargo sets RUSTC for build scripts");
+    let output = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .expect("failed to run rustc --version");
+    let compiler = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    println!("cargo:rustc-env=WASCC_RUSTC_VERSION={}", compiler);
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
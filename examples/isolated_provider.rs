@@ -0,0 +1,112 @@
+// A native capability provider meant to be loaded with `NativeCapability::from_file_isolated`.
+// Its descriptor reports the ID of the process it runs in, and it appends a line with that
+// process ID and the actor to the file named by the `log` value of every binding, which shows
+// when a restarted provider process re-establishes its bindings. Removing a binding appends
+// a line with `unbind` before the actor. A binding with a `callback` value calls that
+// operation on the actor before it is accepted, adding a line with `callback` before the actor
+// if the call succeeds, and one with a `pid` value other than the provider's process ID is
+// refused. Build it with `cargo build --example isolated_provider`
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::RwLock;
+use wascc_codec::capabilities::{
+    CapabilityDescriptor, CapabilityProvider, Dispatcher, OP_GET_CAPABILITY_DESCRIPTOR,
+};
+use wascc_codec::core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+use wascc_codec::{capability_provider, deserialize, serialize};
+
+#[derive(Default)]
+struct IsolatedProvider {
+    dispatcher: RwLock<Option<Box<dyn Dispatcher>>>,
+    // The log file of each bound actor
+    logs: RwLock<HashMap<String, String>>,
+}
+
+capability_provider!(IsolatedProvider, IsolatedProvider::default);
+
+// Hosts refuse providers that don't report the provider ABI version they support. The version
+// is exported by hand, as a provider that only depends on wascc-codec would (the rustc version
+// comes from the build script)
+/// Reports the provider ABI version this library was built with
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn __capability_provider_abi(buf: *mut u8, len: usize) -> usize {
+    let abi = format!(
+        "wascc-codec {}, {}",
+        wascc_codec::VERSION,
+        env!("WASCC_RUSTC_VERSION")
+    );
+    std::ptr::copy_nonoverlapping(abi.as_ptr(), buf, abi.len().min(len));
+    abi.len()
+}
+
+impl CapabilityProvider for IsolatedProvider {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        *self.dispatcher.write().unwrap() = Some(dispatcher);
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        _actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match op {
+            OP_GET_CAPABILITY_DESCRIPTOR => serialize(
+                CapabilityDescriptor::builder()
+                    .id("wascc:keyvalue")
+                    .name("Isolated Provider")
+                    .long_description(&std::process::id().to_string())
+                    .build(),
+            ),
+            OP_BIND_ACTOR => {
+                let cfg: CapabilityConfiguration = deserialize(msg)?;
+                let own_pid = std::process::id().to_string();
+                if matches!(cfg.values.get("pid"), Some(pid) if *pid != own_pid) {
+                    return Err(format!("Refusing to bind {}", cfg.module).into());
+                }
+                if let Some(log) = cfg.values.get("log") {
+                    if let (Some(op), Some(d)) = (
+                        cfg.values.get("callback"),
+                        self.dispatcher.read().unwrap().as_ref(),
+                    ) {
+                        if d.dispatch(&cfg.module, op, &[]).is_ok() {
+                            append(log, &format!("callback {}", cfg.module))?;
+                        }
+                    }
+                    append(log, &cfg.module)?;
+                    self.logs
+                        .write()
+                        .unwrap()
+                        .insert(cfg.module.to_string(), log.to_string());
+                }
+                Ok(vec![])
+            }
+            OP_REMOVE_ACTOR => {
+                let cfg: CapabilityConfiguration = deserialize(msg)?;
+                if let Some(log) = self.logs.write().unwrap().remove(&cfg.module) {
+                    append(&log, &format!("unbind {}", cfg.module))?;
+                }
+                Ok(vec![])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+fn append(log: &str, line: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)?;
+    writeln!(file, "{} {}", std::process::id(), line)
+}
@@ -1,16 +1,33 @@
 use crate::authz;
 use crate::Result;
+use crate::{ResourceLimits, RestartPolicy};
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use wascap::jwt::Token;
 
+/// Where the signed module of an actor was obtained from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorSource {
+    /// Read from a file
+    File(PathBuf),
+    /// Downloaded from a Gantry repository
+    Gantry { revision: u32 },
+    /// Supplied to the host as bytes
+    Slice,
+}
+
 /// An actor is a WebAssembly module that conforms to the waSCC protocols and can securely
 /// consume capabilities exposed by native or portable capability providers
 #[derive(Debug)]
 pub struct Actor {
     pub(crate) token: Token<wascap::jwt::Actor>,
     pub(crate) bytes: Vec<u8>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) limits: ResourceLimits,
+    pub(crate) restart_policy: RestartPolicy,
+    pub(crate) source: ActorSource,
 }
 
 impl Actor {
@@ -21,16 +38,50 @@ impl Actor {
         Ok(Actor {
             token,
             bytes: buf.to_vec(),
+            timeout: None,
+            limits: ResourceLimits::default(),
+            restart_policy: RestartPolicy::default(),
+            source: ActorSource::Slice,
         })
     }
 
     /// Create an actor from a signed WebAssembly (`.wasm`) file
     pub fn from_file(path: impl AsRef<Path>) -> Result<Actor> {
-        let mut file = File::open(path)?;
+        let mut file = File::open(path.as_ref())?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
 
-        Actor::from_slice(&buf)
+        Ok(Actor {
+            source: ActorSource::File(path.as_ref().to_path_buf()),
+            ..Actor::from_slice(&buf)?
+        })
+    }
+
+    /// Sets the maximum amount of time the actor may spend handling a single invocation. Guest
+    /// calls that run past this deadline are interrupted and answered with a timeout error.
+    ///
+    /// Only the `wasmtime` engine can interrupt a guest call. Under the `wasm3` engine feature
+    /// the timeout is not enforced, and a guest call runs for as long as it takes
+    pub fn with_timeout(self, timeout: Duration) -> Actor {
+        Actor {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the limits on the memory, tables, and instructions the actor may use. Limits are
+    /// enforced by every running instance of the actor
+    pub fn with_limits(self, limits: ResourceLimits) -> Actor {
+        Actor { limits, ..self }
+    }
+
+    /// Sets the policy under which the actor's instances are restarted after a panic or
+    /// repeated traps. Each instance keeps its own restart count
+    pub fn with_restart_policy(self, policy: RestartPolicy) -> Actor {
+        Actor {
+            restart_policy: policy,
+            ..self
+        }
     }
 
     /// Obtain the actor's public key (The `sub` field of a JWT). This can be treated as a globally unique identifier
@@ -38,6 +89,11 @@ impl Actor {
         self.token.claims.subject.to_string()
     }
 
+    /// Where the actor's module was obtained from
+    pub fn source(&self) -> &ActorSource {
+        &self.source
+    }
+
     /// The actor's human-friendly display name
     pub fn name(&self) -> String {
         match self.token.claims.metadata.as_ref().unwrap().name {
@@ -0,0 +1,172 @@
+// Call aliases are human-friendly names under which actors can invoke one another, so that an
+// actor doesn't have to hard-code the public key of the actor it calls. An actor declares an
+// alias for itself with a `call_alias:<alias>` tag in its claims, and further aliases can be
+// registered through the host. Aliases are resolved to public keys at invocation time
+
+use crate::errors::{self, ErrorKind};
+use crate::Result;
+use std::collections::HashMap;
+use wascap::jwt::Claims;
+
+/// The prefix of the claims tags with which an actor declares its call aliases
+pub const CALL_ALIAS_TAG_PREFIX: &str = "call_alias:";
+
+/// A call alias and the actor it resolves to
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallAlias {
+    /// The alias used as the namespace of an actor-to-actor call
+    pub alias: String,
+    /// The public key of the actor the alias resolves to
+    pub actor: String,
+    /// Whether the alias was declared in the actor's claims, rather than registered on the host
+    pub declared: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct CallAliases {
+    // the key to this map is the alias
+    aliases: HashMap<String, CallAlias>,
+}
+
+impl CallAliases {
+    pub(crate) fn new() -> CallAliases {
+        CallAliases::default()
+    }
+
+    /// Resolves an alias to the public key of an actor
+    pub(crate) fn resolve(&self, alias: &str) -> Option<String> {
+        self.aliases.get(alias).map(|a| a.actor.to_string())
+    }
+
+    /// Registers an alias for an actor, failing if the alias is taken by another actor
+    pub(crate) fn register(&mut self, alias: &str, actor: &str) -> Result<()> {
+        self.check(alias, actor)?;
+        self.insert(alias, actor, false);
+        Ok(())
+    }
+
+    /// Removes an alias, returning the public key of the actor it resolved to
+    pub(crate) fn remove(&mut self, alias: &str) -> Option<String> {
+        self.aliases.remove(alias).map(|a| a.actor)
+    }
+
+    /// Checks that the aliases declared in an actor's claims are valid and not taken by
+    /// another actor
+    pub(crate) fn check_declared(&self, claims: &Claims<wascap::jwt::Actor>) -> Result<()> {
+        declared_aliases(claims)
+            .iter()
+            .try_for_each(|alias| self.check(alias, &claims.subject))
+    }
+
+    /// Registers the aliases declared in an actor's claims, replacing the ones declared by
+    /// a previous revision of the actor. Nothing changes if any of them is in conflict
+    pub(crate) fn declare(&mut self, claims: &Claims<wascap::jwt::Actor>) -> Result<()> {
+        self.check_declared(claims)?;
+        self.forget(&claims.subject);
+        for alias in declared_aliases(claims) {
+            self.insert(&alias, &claims.subject, true);
+        }
+        Ok(())
+    }
+
+    /// Removes the aliases declared by an actor
+    pub(crate) fn forget(&mut self, actor: &str) {
+        self.aliases
+            .retain(|_, a| !(a.declared && a.actor == actor));
+    }
+
+    pub(crate) fn list(&self) -> Vec<CallAlias> {
+        let mut aliases: Vec<_> = self.aliases.values().cloned().collect();
+        aliases.sort_by(|a, b| a.alias.cmp(&b.alias));
+        aliases
+    }
+
+    fn check(&self, alias: &str, actor: &str) -> Result<()> {
+        if alias.is_empty()
+            || alias.contains(|c: char| c == ':' || c == '.' || c.is_whitespace())
+            || is_actor_key(alias)
+        {
+            return Err(errors::new(ErrorKind::MiscHost(format!(
+                "'{}' is not a valid call alias. Aliases cannot contain ':', '.' or whitespace, and cannot be a public key",
+                alias
+            ))));
+        }
+        match self.aliases.get(alias) {
+            Some(a) if a.actor != actor => Err(errors::new(ErrorKind::MiscHost(format!(
+                "Call alias '{}' cannot be used by actor {}, it already resolves to actor {}",
+                alias, actor, a.actor
+            )))),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, alias: &str, actor: &str, declared: bool) {
+        self.aliases.insert(
+            alias.to_string(),
+            CallAlias {
+                alias: alias.to_string(),
+                actor: actor.to_string(),
+                declared,
+            },
+        );
+    }
+}
+
+/// Indicates whether the namespace of a call is an actor's public key
+pub(crate) fn is_actor_key(ns: &str) -> bool {
+    ns.len() == 56 && ns.starts_with('M')
+}
+
+fn declared_aliases(claims: &Claims<wascap::jwt::Actor>) -> Vec<String> {
+    claims
+        .metadata
+        .as_ref()
+        .and_then(|m| m.tags.as_ref())
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|t| t.strip_prefix(CALL_ALIAS_TAG_PREFIX))
+                .map(|a| a.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::CallAliases;
+    use crate::authz::test_claims;
+    use wascap::jwt::{Actor, Claims};
+
+    fn claims(subject: &str, tags: &[&str]) -> Claims<Actor> {
+        test_claims(subject, None, &[], tags)
+    }
+
+    #[test]
+    fn aliases_resolve_and_conflict() {
+        let mut aliases = CallAliases::new();
+        aliases
+            .declare(&claims("MA", &["call_alias:echo", "other"]))
+            .unwrap();
+        aliases.register("greeter", "MA").unwrap();
+        assert_eq!(Some("MA".to_string()), aliases.resolve("echo"));
+        assert_eq!(2, aliases.list().len());
+
+        assert!(aliases.register("echo", "MB").is_err());
+        assert!(aliases
+            .declare(&claims("MB", &["call_alias:fresh", "call_alias:greeter"]))
+            .is_err());
+        assert_eq!(None, aliases.resolve("fresh"));
+        assert!(aliases.register("wascc:keyvalue", "MB").is_err());
+
+        // A new revision's declarations replace the old ones, registered aliases stay
+        aliases
+            .declare(&claims("MA", &["call_alias:echo2"]))
+            .unwrap();
+        assert_eq!(None, aliases.resolve("echo"));
+        assert_eq!(Some("MA".to_string()), aliases.resolve("echo2"));
+        aliases.forget("MA");
+        assert_eq!(None, aliases.resolve("echo2"));
+        assert_eq!(Some("MA".to_string()), aliases.remove("greeter"));
+        assert!(aliases.list().is_empty());
+    }
+}
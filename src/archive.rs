@@ -0,0 +1,287 @@
+// Signed provider archives bundle the builds of a native capability provider for several
+// targets (e.g. `x86_64-linux`) with a wascap-style JWT. The JWT is signed by the account that
+// issued the provider and records the capability ID, the vendor, the revision and the hash of
+// every library, so an archive whose signature or libraries don't check out is rejected.
+//
+// Archive layout (all integers little-endian):
+//   magic "WASCCPAR" | u32 JWT length | JWT | u32 library count |
+//   for each library: u16 target length | target | u64 library length | library
+
+use crate::errors::{self, ErrorKind};
+use crate::Result;
+use data_encoding::HEXUPPER;
+use ring::digest::{digest, SHA256};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use wascap::jwt::{validate_token, Claims, ClaimsBuilder, WascapEntity};
+use wascap::prelude::KeyPair;
+
+const MAGIC: &[u8] = b"WASCCPAR";
+
+/// The metadata of a signed provider archive, embedded in the archive's JWT
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProviderMetadata {
+    /// The capability ID of the provider, e.g. `wascc:keyvalue`
+    pub capid: String,
+    /// A descriptive name for the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The vendor of the provider
+    pub vendor: String,
+    /// The revision number of the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<i32>,
+    /// The version string of the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ver: Option<String>,
+    /// The SHA-256 hash (upper-case hex) of the library for each target
+    pub target_hashes: HashMap<String, String>,
+}
+
+impl WascapEntity for ProviderMetadata {
+    fn name(&self) -> String {
+        self.name
+            .as_ref()
+            .unwrap_or(&"Anonymous".to_string())
+            .to_string()
+    }
+}
+
+/// A native capability provider's libraries for one or more targets, along with signed claims
+/// describing them. Load one into a host with `Host::add_provider_archive`
+#[derive(Debug, Clone)]
+pub struct ProviderArchive {
+    metadata: ProviderMetadata,
+    libraries: HashMap<String, Vec<u8>>,
+    claims: Option<Claims<ProviderMetadata>>,
+    pub(crate) path: Option<PathBuf>,
+}
+
+impl ProviderArchive {
+    /// Creates an empty, unsigned archive for the provider of the given capability
+    pub fn new(capid: &str, name: &str, vendor: &str) -> ProviderArchive {
+        ProviderArchive {
+            metadata: ProviderMetadata {
+                capid: capid.to_string(),
+                name: Some(name.to_string()),
+                vendor: vendor.to_string(),
+                ..Default::default()
+            },
+            libraries: HashMap::new(),
+            claims: None,
+            path: None,
+        }
+    }
+
+    /// Sets the revision and version of the provider
+    pub fn with_revision(self, rev: i32, ver: &str) -> ProviderArchive {
+        ProviderArchive {
+            metadata: ProviderMetadata {
+                rev: Some(rev),
+                ver: Some(ver.to_string()),
+                ..self.metadata
+            },
+            ..self
+        }
+    }
+
+    /// Adds the provider's library for a target, named `<arch>-<os>` after the `hostcore.arch`
+    /// and `hostcore.os` host labels (e.g. `x86_64-linux`)
+    pub fn with_library(mut self, target: &str, library: &[u8]) -> ProviderArchive {
+        self.metadata
+            .target_hashes
+            .insert(target.to_string(), hash(library));
+        self.libraries.insert(target.to_string(), library.to_vec());
+        self.claims = None;
+        self
+    }
+
+    /// Signs the archive's claims with the issuer's (account) key and serializes the archive.
+    /// The subject key identifies the provider
+    pub fn sign(&self, issuer: &KeyPair, subject: &KeyPair) -> Result<Vec<u8>> {
+        let claims = ClaimsBuilder::<ProviderMetadata>::new()
+            .issuer(&issuer.public_key())
+            .subject(&subject.public_key())
+            .with_metadata(self.metadata.clone())
+            .build();
+        let jwt = claims.encode(issuer)?;
+
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&(jwt.len() as u32).to_le_bytes());
+        buf.extend_from_slice(jwt.as_bytes());
+        buf.extend_from_slice(&(self.libraries.len() as u32).to_le_bytes());
+        let mut targets: Vec<_> = self.libraries.keys().collect();
+        targets.sort();
+        for target in targets {
+            let library = &self.libraries[target];
+            buf.extend_from_slice(&(target.len() as u16).to_le_bytes());
+            buf.extend_from_slice(target.as_bytes());
+            buf.extend_from_slice(&(library.len() as u64).to_le_bytes());
+            buf.extend_from_slice(library);
+        }
+        Ok(buf)
+    }
+
+    /// Reads an archive, verifying its signature and the hashes of its libraries. Archives that
+    /// are unsigned, expired, tampered with or carry libraries not listed in their claims are
+    /// rejected
+    pub fn from_slice(buf: &[u8]) -> Result<ProviderArchive> {
+        let mut reader = Reader { buf, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a provider archive"));
+        }
+        let len = u32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let jwt = std::str::from_utf8(reader.take(len)?)
+            .map_err(|_| invalid("the archive's token is not valid UTF-8"))?;
+        let claims = verify(jwt)?;
+        let metadata = claims
+            .metadata
+            .clone()
+            .ok_or_else(|| invalid("the archive's token has no provider metadata"))?;
+
+        let count = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+        let mut libraries = HashMap::new();
+        for _ in 0..count {
+            let len = u16::from_le_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+            let target = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| invalid("a target name is not valid UTF-8"))?;
+            let len = u64::from_le_bytes(reader.take(8)?.try_into().unwrap()) as usize;
+            let library = reader.take(len)?;
+            if metadata.target_hashes.get(&target) != Some(&hash(library)) {
+                return Err(invalid(&format!(
+                    "the library for target {} does not match the archive's claims",
+                    target
+                )));
+            }
+            libraries.insert(target, library.to_vec());
+        }
+        if reader.pos != buf.len() || libraries.len() != metadata.target_hashes.len() {
+            return Err(invalid("the archive's contents do not match its claims"));
+        }
+        Ok(ProviderArchive {
+            metadata,
+            libraries,
+            claims: Some(claims),
+            path: None,
+        })
+    }
+
+    /// Reads and verifies an archive from a file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ProviderArchive> {
+        let buf = std::fs::read(path.as_ref())?;
+        Ok(ProviderArchive {
+            path: Some(path.as_ref().to_path_buf()),
+            ..ProviderArchive::from_slice(&buf)?
+        })
+    }
+
+    /// The verified claims of an archive that was read with `from_slice` or `from_file`
+    pub fn claims(&self) -> Option<&Claims<ProviderMetadata>> {
+        self.claims.as_ref()
+    }
+
+    /// The capability ID of the provider
+    pub fn capid(&self) -> &str {
+        &self.metadata.capid
+    }
+
+    /// The targets for which the archive contains a library, sorted by name
+    pub fn targets(&self) -> Vec<String> {
+        let mut targets: Vec<_> = self.libraries.keys().cloned().collect();
+        targets.sort();
+        targets
+    }
+
+    /// The library for a target, if the archive contains one
+    pub fn library(&self, target: &str) -> Option<&[u8]> {
+        self.libraries.get(target).map(|l| l.as_slice())
+    }
+}
+
+/// The name of the target for the given `hostcore.arch` and `hostcore.os` label values
+pub(crate) fn target_name(arch: &str, os: &str) -> String {
+    format!("{}-{}", arch, os)
+}
+
+pub(crate) fn hash(library: &[u8]) -> String {
+    HEXUPPER.encode(digest(&SHA256, library).as_ref())
+}
+
+fn verify(jwt: &str) -> Result<Claims<ProviderMetadata>> {
+    let v = validate_token::<ProviderMetadata>(jwt)?;
+    if !v.signature_valid {
+        Err(invalid("the archive's signature is not valid"))
+    } else if v.expired {
+        Err(errors::new(ErrorKind::Authorization(
+            "Expired provider archive".to_string(),
+        )))
+    } else if v.cannot_use_yet {
+        Err(errors::new(ErrorKind::Authorization(format!(
+            "Provider archive cannot be used before {}",
+            v.not_before_human
+        ))))
+    } else {
+        Ok(Claims::<ProviderMetadata>::decode(jwt)?)
+    }
+}
+
+fn invalid(reason: &str) -> errors::Error {
+    errors::new(ErrorKind::CapabilityProvider(format!(
+        "Invalid provider archive: {}",
+        reason
+    )))
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| invalid("unexpected end of archive"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProviderArchive;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn archives_are_verified() {
+        let (issuer, subject) = (KeyPair::new_account(), KeyPair::new_module());
+        let buf = ProviderArchive::new("wascc:test", "Test", "Acme")
+            .with_revision(2, "0.2.0")
+            .with_library("x86_64-linux", b"linux library")
+            .with_library("aarch64-macos", b"macos library")
+            .sign(&issuer, &subject)
+            .unwrap();
+
+        let archive = ProviderArchive::from_slice(&buf).unwrap();
+        assert_eq!("wascc:test", archive.capid());
+        assert_eq!(vec!["aarch64-macos", "x86_64-linux"], archive.targets());
+        assert_eq!(Some(&b"linux library"[..]), archive.library("x86_64-linux"));
+        let claims = archive.claims().unwrap();
+        assert_eq!(issuer.public_key(), claims.issuer);
+        assert_eq!(Some(2), claims.metadata.as_ref().unwrap().rev);
+
+        // Tampering with a library or with the signed claims is detected
+        let mut tampered = buf.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(ProviderArchive::from_slice(&tampered).is_err());
+        let mut tampered = buf.clone();
+        tampered[20] ^= 1;
+        assert!(ProviderArchive::from_slice(&tampered).is_err());
+        assert!(ProviderArchive::from_slice(&buf[..buf.len() - 1]).is_err());
+        assert!(ProviderArchive::from_slice(b"not an archive").is_err());
+    }
+}
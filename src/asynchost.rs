@@ -0,0 +1,192 @@
+// An asynchronous facade over the host runtime. Host operations block on message bus round
+// trips and wait groups, so each one runs on tokio's blocking thread pool rather than on one
+// of the runtime's worker threads
+
+use crate::errors::{self, ErrorKind};
+use crate::{
+    Actor, CanaryOptions, CanaryStatus, Host, NativeCapability, ReplaceOptions, ReplaceReport,
+    Result, ShutdownReport, WasiParams,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A handle to a waSCC host runtime whose operations return futures. Every operation is
+/// performed by the underlying `Host` on tokio's blocking thread pool, so awaiting one does
+/// not tie up a runtime worker thread. Futures must be polled from within a tokio runtime
+#[derive(Clone)]
+pub struct AsyncHost {
+    host: Host,
+}
+
+impl AsyncHost {
+    /// Wraps a host runtime so that it can be driven asynchronously
+    pub fn new(host: Host) -> AsyncHost {
+        AsyncHost { host }
+    }
+
+    /// Obtains the underlying host, for operations that don't block (such as queries)
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    /// Adds an actor to the host. See `Host::add_actor`
+    pub async fn add_actor(&self, actor: Actor) -> Result<()> {
+        let host = self.host.clone();
+        blocking(move || host.add_actor(actor)).await
+    }
+
+    /// Adds a portable capability provider to the host. See `Host::add_capability`
+    pub async fn add_capability(
+        &self,
+        actor: Actor,
+        binding: Option<String>,
+        wasi: WasiParams,
+    ) -> Result<()> {
+        let host = self.host.clone();
+        blocking(move || host.add_capability(actor, binding.as_deref(), wasi)).await
+    }
+
+    /// Adds a native capability provider plugin to the host. See `Host::add_native_capability`
+    pub async fn add_native_capability(&self, capability: NativeCapability) -> Result<()> {
+        let host = self.host.clone();
+        blocking(move || host.add_native_capability(capability)).await
+    }
+
+    /// Removes an actor from the host. See `Host::remove_actor`
+    pub async fn remove_actor(&self, pk: &str) -> Result<()> {
+        let (host, pk) = (self.host.clone(), pk.to_string());
+        blocking(move || host.remove_actor(&pk)).await
+    }
+
+    /// Drains an actor and removes it from the host. See `Host::drain_actor`
+    pub async fn drain_actor(&self, pk: &str, timeout: Duration) -> Result<()> {
+        let (host, pk) = (self.host.clone(), pk.to_string());
+        blocking(move || host.drain_actor(&pk, timeout)).await
+    }
+
+    /// Replaces the module of a running actor. See `Host::replace_actor_with`
+    pub async fn replace_actor(
+        &self,
+        new_actor: Actor,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        let host = self.host.clone();
+        blocking(move || host.replace_actor_with(new_actor, options)).await
+    }
+
+    /// Starts a canary revision of a running actor. See `Host::start_canary`
+    pub async fn start_canary(
+        &self,
+        new_actor: Actor,
+        options: CanaryOptions,
+    ) -> Result<CanaryStatus> {
+        let host = self.host.clone();
+        blocking(move || host.start_canary(new_actor, options)).await
+    }
+
+    /// Promotes an actor's canary to be its current revision. See `Host::promote_canary`
+    pub async fn promote_canary(&self, pk: &str, options: ReplaceOptions) -> Result<ReplaceReport> {
+        let (host, pk) = (self.host.clone(), pk.to_string());
+        blocking(move || host.promote_canary(&pk, options)).await
+    }
+
+    /// Puts a previous revision of a running actor back in place. See `Host::rollback_actor`
+    pub async fn rollback_actor(
+        &self,
+        pk: &str,
+        rev: i32,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        let (host, pk) = (self.host.clone(), pk.to_string());
+        blocking(move || host.rollback_actor(&pk, rev, options)).await
+    }
+
+    /// Changes the number of running instances of an actor. See `Host::scale_actor`
+    pub async fn scale_actor(&self, pk: &str, instances: usize) -> Result<()> {
+        let (host, pk) = (self.host.clone(), pk.to_string());
+        blocking(move || host.scale_actor(&pk, instances)).await
+    }
+
+    /// Binds an actor to a capability provider. See `Host::set_binding`
+    pub async fn set_binding(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+        config: HashMap<String, String>,
+    ) -> Result<()> {
+        let (host, actor, capid) = (self.host.clone(), actor.to_string(), capid.to_string());
+        blocking(move || host.set_binding(&actor, &capid, binding_name, config)).await
+    }
+
+    /// Removes a binding between an actor and a capability provider. See `Host::remove_binding`
+    pub async fn remove_binding(
+        &self,
+        actor: &str,
+        capid: &str,
+        binding_name: Option<String>,
+    ) -> Result<()> {
+        let (host, actor, capid) = (self.host.clone(), actor.to_string(), capid.to_string());
+        blocking(move || host.remove_binding(&actor, &capid, binding_name)).await
+    }
+
+    /// Invokes an operation handler on an actor. See `Host::call_actor`
+    pub async fn call_actor(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
+        let (host, actor, operation, msg) = (
+            self.host.clone(),
+            actor.to_string(),
+            operation.to_string(),
+            msg.to_vec(),
+        );
+        blocking(move || host.call_actor(&actor, &operation, &msg)).await
+    }
+
+    /// Invokes an operation handler on an actor with a deadline. See `Host::call_actor_with_timeout`
+    pub async fn call_actor_with_timeout(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let (host, actor, operation, msg) = (
+            self.host.clone(),
+            actor.to_string(),
+            operation.to_string(),
+            msg.to_vec(),
+        );
+        blocking(move || host.call_actor_with_timeout(&actor, &operation, &msg, timeout)).await
+    }
+
+    /// Shuts down the host. See `Host::shutdown`
+    pub async fn shutdown(&self) -> Result<()> {
+        let host = self.host.clone();
+        blocking(move || host.shutdown()).await
+    }
+
+    /// Shuts down the host, waiting for it to stop. See `Host::shutdown_graceful`
+    pub async fn shutdown_graceful(&self, timeout: Duration) -> Result<ShutdownReport> {
+        let host = self.host.clone();
+        blocking(move || host.shutdown_graceful(timeout)).await
+    }
+}
+
+impl From<Host> for AsyncHost {
+    fn from(host: Host) -> AsyncHost {
+        AsyncHost::new(host)
+    }
+}
+
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(r) => r,
+        Err(e) => Err(errors::new(ErrorKind::MiscHost(format!(
+            "Host operation did not complete: {}",
+            e
+        )))),
+    }
+}
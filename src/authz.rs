@@ -1,10 +1,14 @@
+use crate::aliases::CallAliases;
+use crate::bus::MessageBus;
 use crate::errors;
-use crate::{Host, Result, WasccEntity};
-use std::collections::HashMap;
+use crate::revocation::RevocationList;
+use crate::{Host, HostEvent, ProviderProvenance, Result, RouteKey, WasccEntity};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::sync::RwLock;
 use wascap::jwt::Token;
 use wascap::prelude::*;
+use wascc_codec::capabilities::CapabilityDescriptor;
 
 pub(crate) type ClaimsMap = Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>;
 
@@ -21,6 +25,24 @@ pub trait Authorizer: Sync + Send {
     /// including the operation that occurs during `bind_actor`. Developers should be aware of this because
     /// if `set_authorizer` is done _after_ actor binding, it could potentially allow an unauthorized binding.
     fn can_invoke(&self, claims: &Claims<Actor>, target: &WasccEntity, operation: &str) -> bool;
+    /// This check is performed before a capability provider, native or portable, is added to the host.
+    /// The descriptor is the one reported by the provider itself, so checks on the provider's library
+    /// or module should be made against the provenance. Providers are allowed to load by default
+    fn can_load_provider(
+        &self,
+        _descriptor: &CapabilityDescriptor,
+        _provenance: &ProviderProvenance,
+    ) -> bool {
+        true
+    }
+    /// This check is performed before a native capability provider's library is opened by
+    /// `Host::add_native_capability_file` or `Host::add_provider_archive`, so a library refused
+    /// here never gets to run any code. Only the provenance is known at this point; the
+    /// descriptor is checked with `can_load_provider` once the library is loaded. Provider
+    /// files are allowed to load by default
+    fn can_load_provider_file(&self, _provenance: &ProviderProvenance) -> bool {
+        true
+    }
 }
 
 pub(crate) struct DefaultAuthorizer {}
@@ -47,6 +69,96 @@ impl Authorizer for DefaultAuthorizer {
     }
 }
 
+/// The host's authorization state consulted by actor instances on every host call: the
+/// authorizer, the call aliases through which actors address each other, the policies for
+/// actor-to-actor calls and capability operations, and the descriptors of the loaded
+/// providers (which list the operations they support)
+#[derive(Clone)]
+pub(crate) struct SecurityContext {
+    pub authorizer: Arc<RwLock<Box<dyn Authorizer>>>,
+    pub aliases: Arc<RwLock<CallAliases>>,
+    pub call_policy: Arc<RwLock<ActorCallPolicy>>,
+    pub operations: Arc<RwLock<OperationPolicy>>,
+    pub descriptors: Arc<RwLock<HashMap<RouteKey, CapabilityDescriptor>>>,
+}
+
+/// Decides which actors may invoke other actors. Whatever the policy, an actor may only invoke
+/// another actor if its claims list the target's public key among its capabilities, and if the
+/// host's authorizer agrees. The default policy is open and requires nothing more. Under a
+/// restricted policy the call must also be permitted, either by a `call:<target>` tag in the
+/// caller's claims (see `CALL_TAG_PREFIX`) or by the policy's allowlist
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActorCallPolicy {
+    restricted: bool,
+    // (caller, target) public keys
+    allowed: HashSet<(String, String)>,
+}
+
+impl ActorCallPolicy {
+    /// A policy that lets an actor invoke any actor its claims attest to
+    pub fn open() -> ActorCallPolicy {
+        ActorCallPolicy::default()
+    }
+
+    /// A policy that also requires actor-to-actor calls to be permitted by the caller's
+    /// claims or by the allowlist
+    pub fn restricted() -> ActorCallPolicy {
+        ActorCallPolicy {
+            restricted: true,
+            ..ActorCallPolicy::default()
+        }
+    }
+
+    /// Adds an entry to the allowlist, permitting the caller to invoke the target
+    pub fn with_allowed_call(mut self, caller: &str, target: &str) -> ActorCallPolicy {
+        self.allow(caller, target);
+        self
+    }
+
+    /// Indicates whether actor-to-actor calls need to be permitted
+    pub fn is_restricted(&self) -> bool {
+        self.restricted
+    }
+
+    pub(crate) fn allow(&mut self, caller: &str, target: &str) {
+        self.allowed
+            .insert((caller.to_string(), target.to_string()));
+    }
+
+    pub(crate) fn revoke(&mut self, caller: &str, target: &str) -> bool {
+        self.allowed
+            .remove(&(caller.to_string(), target.to_string()))
+    }
+
+    /// Decides whether an actor may invoke the target actor. The caller's claims must attest
+    /// to the target under any policy; a restricted policy adds a call tag or the allowlist
+    /// on top
+    pub(crate) fn permits(&self, claims: &Claims<Actor>, target: &str) -> bool {
+        attests(claims, target)
+            && (!self.restricted
+                || claims.subject == target
+                || claims_permit_call(claims, target)
+                || self
+                    .allowed
+                    .contains(&(claims.subject.to_string(), target.to_string())))
+    }
+}
+
+/// The prefix of the claims tags with which an actor is permitted to invoke another actor
+/// under a restricted `ActorCallPolicy`, in the form `call:<target public key>`
+pub const CALL_TAG_PREFIX: &str = "call:";
+
+fn claims_permit_call(claims: &Claims<Actor>, target: &str) -> bool {
+    claims
+        .metadata
+        .as_ref()
+        .and_then(|m| m.tags.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t.strip_prefix(CALL_TAG_PREFIX))
+        .any(|t| t == target)
+}
+
 pub(crate) fn get_all_claims(map: ClaimsMap) -> Vec<(String, Claims<wascap::jwt::Actor>)> {
     map.read()
         .unwrap()
@@ -55,13 +167,96 @@ pub(crate) fn get_all_claims(map: ClaimsMap) -> Vec<(String, Claims<wascap::jwt:
         .collect()
 }
 
-// We don't (yet) support per-operation security constraints, but when we do, this
-// function will be ready to support that without breaking everyone else's calls
+/// Restricts the operations that actors may invoke on capability providers. A capability
+/// with no rules is unrestricted. Once operations are listed for a capability, either for
+/// every actor or for a particular actor, an invocation must be permitted by each rule that
+/// applies to it. Actors can restrict themselves further with `op:<capid>:<operation>` tags
+/// in their claims (see `OPERATION_TAG_PREFIX`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OperationPolicy {
+    // (actor public key, or `None` for every actor; capability ID) -> permitted operations
+    rules: HashMap<(Option<String>, String), HashSet<String>>,
+}
+
+impl OperationPolicy {
+    pub fn new() -> OperationPolicy {
+        OperationPolicy::default()
+    }
+
+    /// Permits every actor to invoke only the given operations of a capability
+    pub fn with_operations(mut self, capid: &str, operations: &[&str]) -> OperationPolicy {
+        self.rules
+            .insert((None, capid.to_string()), to_set(operations));
+        self
+    }
+
+    /// Permits an actor to invoke only the given operations of a capability
+    pub fn with_actor_operations(
+        mut self,
+        actor: &str,
+        capid: &str,
+        operations: &[&str],
+    ) -> OperationPolicy {
+        self.rules.insert(
+            (Some(actor.to_string()), capid.to_string()),
+            to_set(operations),
+        );
+        self
+    }
+
+    pub(crate) fn permits(&self, claims: &Claims<Actor>, capid: &str, operation: &str) -> bool {
+        [None, Some(claims.subject.to_string())]
+            .iter()
+            .filter_map(|actor| self.rules.get(&(actor.clone(), capid.to_string())))
+            .all(|ops| ops.contains(operation))
+    }
+}
+
+fn to_set(operations: &[&str]) -> HashSet<String> {
+    operations.iter().map(|o| o.to_string()).collect()
+}
+
+/// The prefix of the claims tags with which an actor restricts the operations it may invoke
+/// on a capability, in the form `op:<capid>:<operation>`. An actor with no such tags for a
+/// capability may invoke any of its operations
+pub const OPERATION_TAG_PREFIX: &str = "op:";
+
+/// Checks that an actor's claims attest to the capability and permit the operation
 pub(crate) fn can_invoke(
     claims: &Claims<wascap::jwt::Actor>,
     capability_id: &str,
-    _operation: &str,
+    operation: &str,
 ) -> bool {
+    attests(claims, capability_id) && claims_permit_operation(claims, capability_id, operation)
+}
+
+/// Checks that a provider supports an operation invoked by an actor. Providers that don't
+/// describe their operations are assumed to support any operation. The direction of a described
+/// operation isn't considered, existing providers don't label it consistently
+pub(crate) fn supports_operation(descriptor: &CapabilityDescriptor, operation: &str) -> bool {
+    descriptor.supported_operations.is_empty()
+        || descriptor
+            .supported_operations
+            .iter()
+            .any(|o| o.name == operation)
+}
+
+fn claims_permit_operation(claims: &Claims<Actor>, capability_id: &str, operation: &str) -> bool {
+    let mut declared = claims
+        .metadata
+        .as_ref()
+        .and_then(|m| m.tags.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t.strip_prefix(OPERATION_TAG_PREFIX))
+        .filter_map(|t| t.rfind(':').map(|i| (&t[..i], &t[i + 1..])))
+        .filter(|(capid, _)| *capid == capability_id)
+        .peekable();
+    declared.peek().is_none() || declared.any(|(_, op)| op == operation)
+}
+
+/// Checks that an actor's claims attest to a capability
+pub(crate) fn attests(claims: &Claims<wascap::jwt::Actor>, capability_id: &str) -> bool {
     // Edge case - deliver configuration to an actor directly,
     // so "self invocation" needs to be authorized
     if claims.subject == capability_id {
@@ -96,6 +291,52 @@ pub(crate) fn extract_claims(buf: &[u8]) -> Result<wascap::jwt::Token<wascap::jw
     }
 }
 
+/// Rejects an actor that isn't signed by one of the trusted issuers, if any are configured,
+/// and reports the rejection as a host event
+pub(crate) fn enforce_trusted_issuer(
+    trusted: &[String],
+    claims: &Claims<wascap::jwt::Actor>,
+    bus: &MessageBus,
+) -> Result<()> {
+    if trusted.is_empty() || trusted.contains(&claims.issuer) {
+        return Ok(());
+    }
+    let reason = format!(
+        "Actor {} is signed by {}, which is not a trusted issuer",
+        claims.subject, claims.issuer
+    );
+    warn!("{}", reason);
+    bus.emit(HostEvent::ActorRejected {
+        actor: claims.subject.to_string(),
+        issuer: claims.issuer.to_string(),
+        reason: reason.to_string(),
+    });
+    Err(errors::new(errors::ErrorKind::Authorization(reason)))
+}
+
+/// Rejects an actor whose token has been revoked, reporting the rejection as a host event
+pub(crate) fn enforce_revocation(
+    revocations: &RevocationList,
+    claims: &Claims<wascap::jwt::Actor>,
+    bus: &MessageBus,
+) -> Result<()> {
+    let revocation = match revocations.find(claims) {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    let reason = format!(
+        "Token {} of actor {} has been revoked ({:?})",
+        claims.id, claims.subject, revocation.entity
+    );
+    warn!("{}", reason);
+    bus.emit(HostEvent::ActorRejected {
+        actor: claims.subject.to_string(),
+        issuer: claims.issuer.to_string(),
+        reason: reason.to_string(),
+    });
+    Err(errors::new(errors::ErrorKind::Authorization(reason)))
+}
+
 pub(crate) fn enforce_validation(jwt: &str) -> Result<()> {
     let v = validate_token::<wascap::jwt::Actor>(jwt)?;
     if v.expired {
@@ -134,4 +375,130 @@ impl Host {
     pub(crate) fn check_auth(&self, token: &Token<wascap::jwt::Actor>) -> bool {
         self.authorizer.read().unwrap().can_load(&token.claims)
     }
+
+    // Refuses tokens from untrusted issuers and revoked tokens
+    pub(crate) fn check_trust(&self, claims: &Claims<wascap::jwt::Actor>) -> Result<()> {
+        enforce_trusted_issuer(&self.trusted_issuers, claims, &self.bus)?;
+        enforce_revocation(&self.revocations.read().unwrap(), claims, &self.bus)
+    }
+
+    pub(crate) fn security(&self) -> SecurityContext {
+        SecurityContext {
+            authorizer: self.authorizer.clone(),
+            aliases: self.aliases.clone(),
+            call_policy: self.call_policy.clone(),
+            operations: self.operations.clone(),
+            descriptors: self.caps.clone(),
+        }
+    }
+}
+
+/// Builds actor claims for unit tests. The capabilities and tags are left unset when empty
+#[cfg(test)]
+pub(crate) fn test_claims(
+    subject: &str,
+    issuer: Option<&str>,
+    caps: &[&str],
+    tags: &[&str],
+) -> Claims<Actor> {
+    let list = |items: &[&str]| {
+        if items.is_empty() {
+            None
+        } else {
+            Some(items.iter().map(|i| i.to_string()).collect())
+        }
+    };
+    let mut builder = wascap::jwt::ClaimsBuilder::<Actor>::new();
+    if let Some(issuer) = issuer {
+        builder.issuer(issuer);
+    }
+    builder
+        .subject(subject)
+        .with_metadata(Actor {
+            caps: list(caps),
+            tags: list(tags),
+            ..Default::default()
+        })
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{can_invoke, supports_operation, test_claims, ActorCallPolicy, OperationPolicy};
+    use wascc_codec::capabilities::{CapabilityDescriptor, OperationDirection};
+
+    #[test]
+    fn operations_are_restricted_by_claims_and_policy() {
+        let kv = "wascc:keyvalue";
+        let open = test_claims("MOPEN", None, &[kv], &[]);
+        let tagged = test_claims("MTAGGED", None, &[kv], &["op:wascc:keyvalue:Get", "other"]);
+        assert!(can_invoke(&open, kv, "Del"));
+        assert!(can_invoke(&tagged, kv, "Get"));
+        assert!(!can_invoke(&tagged, kv, "Del"));
+        assert!(!can_invoke(&open, "wascc:messaging", "Publish"));
+
+        let policy = OperationPolicy::new()
+            .with_operations(kv, &["Get", "Set"])
+            .with_actor_operations("MTAGGED", kv, &["Get"]);
+        assert!(policy.permits(&open, kv, "Set"));
+        assert!(!policy.permits(&open, kv, "Del"));
+        assert!(!policy.permits(&tagged, kv, "Set"));
+        assert!(policy.permits(&tagged, "wascc:messaging", "Publish"));
+    }
+
+    #[test]
+    fn unknown_operations_are_unsupported() {
+        let descriptor = CapabilityDescriptor::builder()
+            .id("wascc:keyvalue")
+            .with_operation("Get", OperationDirection::ToProvider, "")
+            .with_operation("Changed", OperationDirection::ToActor, "")
+            .build();
+        assert!(supports_operation(&descriptor, "Get"));
+        assert!(supports_operation(&descriptor, "Changed"));
+        assert!(!supports_operation(&descriptor, "Del"));
+        let undescribed = CapabilityDescriptor::builder().id("wascc:other").build();
+        assert!(supports_operation(&undescribed, "Anything"));
+    }
+
+    #[test]
+    fn restricted_actor_calls_need_permission() {
+        let caller = test_claims(
+            "MCALLER",
+            None,
+            &["wascc:keyvalue", "MGRANTED", "MLISTED"],
+            &[],
+        );
+        let open = ActorCallPolicy::open();
+        assert!(open.permits(&caller, "MCALLER"));
+        assert!(open.permits(&caller, "MGRANTED"));
+        assert!(!open.permits(&caller, "MOTHER"));
+
+        let mut policy = ActorCallPolicy::restricted().with_allowed_call("MCALLER", "MLISTED");
+        assert!(policy.permits(&caller, "MCALLER"));
+        assert!(policy.permits(&caller, "MLISTED"));
+        assert!(!policy.permits(&caller, "MGRANTED"));
+        // The allowlist doesn't stand in for the caller's claims
+        policy.allow("MCALLER", "MOTHER");
+        assert!(!policy.permits(&caller, "MOTHER"));
+
+        policy.allow("MCALLER", "MGRANTED");
+        assert!(policy.permits(&caller, "MGRANTED"));
+        assert!(policy.revoke("MCALLER", "MGRANTED"));
+        assert!(!policy.permits(&caller, "MGRANTED"));
+    }
+
+    #[test]
+    fn restricted_actor_calls_can_be_permitted_by_claims() {
+        let policy = ActorCallPolicy::restricted();
+        let tagged = test_claims(
+            "MCALLER",
+            None,
+            &["MGRANTED", "MOTHER"],
+            &["call:MGRANTED", "call:MUNCLAIMED"],
+        );
+        assert!(policy.permits(&tagged, "MGRANTED"));
+        assert!(!policy.permits(&tagged, "MOTHER"));
+        // The tag doesn't stand in for the capability claim either
+        assert!(!policy.permits(&tagged, "MUNCLAIMED"));
+    }
 }
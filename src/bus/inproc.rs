@@ -1,10 +1,32 @@
-use crate::errors;
-use crate::{Invocation, InvocationResponse, Result};
+use crate::events::{EventBroker, HostEvent};
+use crate::{Invocation, InvocationErrorCode, InvocationResponse, Result};
 use crossbeam::{Receiver, Sender};
-use std::{collections::HashMap, sync::RwLock};
+use crossbeam_channel::RecvTimeoutError;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+// How much longer than an invocation's own timeout a caller waits before giving up on
+// the response, allowing the target time to report the timeout itself
+const TIMEOUT_GRACE: Duration = Duration::from_millis(100);
+
+// Each subscriber handles one invocation at a time, so a caller holds the subscription's
+// lock for the duration of a call to keep responses paired with their callers
+#[derive(Clone)]
+struct Subscription {
+    sender: Sender<Invocation>,
+    receiver: Receiver<InvocationResponse>,
+    in_use: Arc<Mutex<()>>,
+}
 
 pub(crate) struct InprocBus {
-    subscriptions: RwLock<HashMap<String, (Sender<Invocation>, Receiver<InvocationResponse>)>>,
+    subscriptions: RwLock<HashMap<String, Vec<Subscription>>>,
+    next: AtomicUsize,
+    events: EventBroker,
+    in_flight: super::InFlight,
 }
 
 impl InprocBus {
@@ -12,11 +34,38 @@ impl InprocBus {
         info!("Initialized Message Bus (internal)");
         InprocBus {
             subscriptions: RwLock::new(HashMap::new()),
+            next: AtomicUsize::new(0),
+            events: EventBroker::new(),
+            in_flight: super::InFlight::default(),
         }
     }
 
     pub fn disconnect(&self) {
-        // No-op
+        self.emit(HostEvent::HostStopped);
+    }
+
+    /// Delivers a host event to the local event subscribers
+    pub fn emit(&self, event: HostEvent) {
+        self.events.publish(event);
+    }
+
+    pub fn events(&self) -> Receiver<HostEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stops accepting new invocations for actors
+    pub fn drain(&self) {
+        self.in_flight.drain();
+    }
+
+    /// The number of invocations currently passing through the bus
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    /// The subject of the bus's own machinery, which lives until the bus disconnects
+    pub fn controlplane_subject(&self) -> Option<String> {
+        None
     }
 
     pub fn subscribe(
@@ -28,7 +77,13 @@ impl InprocBus {
         self.subscriptions
             .write()
             .unwrap()
-            .insert(subject.to_string(), (sender, receiver));
+            .entry(subject.to_string())
+            .or_default()
+            .push(Subscription {
+                sender,
+                receiver,
+                in_use: Arc::new(Mutex::new(())),
+            });
         Ok(())
     }
 
@@ -42,16 +97,50 @@ impl InprocBus {
     }
 
     pub fn invoke(&self, subject: &str, inv: Invocation) -> Result<InvocationResponse> {
-        match self.subscriptions.read().unwrap().get(subject) {
-            Some(s) => {
-                s.0.send(inv).unwrap();
-                let r = s.1.recv().unwrap();
-                Ok(r)
+        let res = match self.in_flight.enter(&inv) {
+            Some(_guard) => self.deliver(subject, &inv),
+            None => Ok(super::draining_response(&inv)),
+        };
+        if let Some(event) = super::invocation_failure(&inv, &res) {
+            self.emit(event);
+        }
+        res
+    }
+
+    fn deliver(&self, subject: &str, inv: &Invocation) -> Result<InvocationResponse> {
+        // An instance that stops, e.g. when an actor is scaled down, leaves the invocations it
+        // hadn't started unanswered. Those are handed to one of the remaining subscribers
+        loop {
+            // Don't hold the lock while waiting on the reply, the target may need to subscribe
+            // or invoke other subjects before it can respond
+            let subs = self.subscriptions.read().unwrap().get(subject).cloned();
+            let subs = match subs {
+                Some(subs) if !subs.is_empty() => subs,
+                _ => {
+                    return Err(super::invocation_error(
+                        inv,
+                        InvocationErrorCode::NotFound,
+                        &format!("Attempted bus call for {} with no subscribers", subject),
+                    ))
+                }
+            };
+            // Prefer an idle subscriber, otherwise wait in line for the next one
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            let idle = (0..subs.len())
+                .map(|i| &subs[(start + i) % subs.len()])
+                .find_map(|s| s.in_use.try_lock().ok().map(|guard| (s, guard)));
+            let (sub, guard) = match idle {
+                Some(s) => s,
+                None => {
+                    let s = &subs[start % subs.len()];
+                    (s, s.in_use.lock().unwrap())
+                }
+            };
+            if let Some(resp) = exchange(sub, inv) {
+                return Ok(resp);
             }
-            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
-                "Attempted bus call for {} with no subscribers",
-                subject
-            )))),
+            drop(guard);
+            self.unsubscribe_instance(subject, &sub.sender)?;
         }
     }
 
@@ -63,6 +152,19 @@ impl InprocBus {
         Ok(())
     }
 
+    /// Removes a single subscriber (identified by its invocation channel) from a subject
+    /// that may have several, such as an actor running multiple instances
+    pub fn unsubscribe_instance(&self, subject: &str, sender: &Sender<Invocation>) -> Result<()> {
+        let mut lock = self.subscriptions.write().unwrap();
+        if let Some(subs) = lock.get_mut(subject) {
+            subs.retain(|s| !s.sender.same_channel(sender));
+            if subs.is_empty() {
+                lock.remove(subject);
+            }
+        }
+        Ok(())
+    }
+
     pub fn actor_subject(&self, actor: &str) -> String {
         super::actor_subject(None, actor)
     }
@@ -88,3 +190,23 @@ impl InprocBus {
         super::provider_subject_bound_actor(None, capid, binding, calling_actor)
     }
 }
+
+// Sends an invocation to a subscriber and waits for its response. Returns `None` if the
+// subscriber stopped before running the invocation
+fn exchange(sub: &Subscription, inv: &Invocation) -> Option<InvocationResponse> {
+    sub.sender.send(inv.clone()).ok()?;
+    loop {
+        let resp = match inv.timeout {
+            Some(t) => match sub.receiver.recv_timeout(t + TIMEOUT_GRACE) {
+                Ok(r) => r,
+                Err(RecvTimeoutError::Timeout) => return Some(InvocationResponse::timeout(inv, t)),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            },
+            None => sub.receiver.recv().ok()?,
+        };
+        // Skip responses that arrived after an earlier caller gave up waiting
+        if resp.invocation_id == inv.id {
+            return Some(resp);
+        }
+    }
+}
@@ -1,5 +1,6 @@
+use crate::events::{EventBroker, HostEvent};
 use crate::{BindingsList, RouteKey};
-use crate::{Invocation, InvocationResponse, Result};
+use crate::{Invocation, InvocationErrorCode, InvocationResponse, Result};
 use crossbeam::{Receiver, Sender};
 use crossbeam_channel as channel;
 use latticeclient::{
@@ -33,21 +34,31 @@ use latticeclient::*;
 use nats::Message;
 use wapc::WasiParams;
 
+// A subscription handler along with the invocation channel that identifies its subscriber
+type Subscription = (Sender<Invocation>, nats::subscription::Handler);
+
+// Revocations are broadcast to every host in the namespace rather than to a single host
+const REVOKE_ACTOR: &str = "actor.revoke";
+
 #[derive(Debug, Clone)]
 pub(crate) enum ControlCommand {
     TerminateActor(TerminateCommand),
     StartActor(LaunchCommand, Message),
+    Revoke(String),
 }
 
 pub(crate) struct DistributedBus {
     nc: Arc<RwLock<Option<nats::Connection>>>,
-    subs: Arc<RwLock<HashMap<String, nats::subscription::Handler>>>,
+    // Several subscribers (e.g. actor instances) can share a subject's queue group
+    subs: Arc<RwLock<HashMap<String, Vec<Subscription>>>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     req_timeout: Duration,
     host_id: String,
     lc: Arc<RwLock<latticeclient::Client>>,
     pub(crate) ns: Option<String>,
     claims: Arc<RwLock<HashMap<String, Claims<Actor>>>>,
+    events: EventBroker,
+    in_flight: Arc<super::InFlight>,
 }
 
 impl DistributedBus {
@@ -113,17 +124,34 @@ impl DistributedBus {
             lc,
             ns: ns.clone(),
             claims,
+            events: EventBroker::new(),
+            in_flight: Arc::new(super::InFlight::default()),
         }
     }
 
-    pub fn disconnect(&self) {
-        // Terminate the control plane command handler
-        let cpsubject = format!(
+    /// Stops accepting new invocations for actors
+    pub fn drain(&self) {
+        self.in_flight.drain();
+    }
+
+    /// The number of invocations currently passing through the bus
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    /// The subject of the control plane command handler, which lives until the bus disconnects
+    pub fn controlplane_subject(&self) -> Option<String> {
+        Some(format!(
             "{}.{}.{}",
             super::nsprefix(self.ns.as_ref().map(String::as_str)),
             latticeclient::controlplane::CPLANE_PREFIX,
             self.host_id
-        );
+        ))
+    }
+
+    pub fn disconnect(&self) {
+        // Terminate the control plane command handler
+        let cpsubject = self.controlplane_subject().unwrap();
         self.terminators.read().unwrap()[&cpsubject]
             .send(true)
             .unwrap();
@@ -134,7 +162,7 @@ impl DistributedBus {
             std::thread::sleep(std::time::Duration::from_millis(TERM_BACKOFF_DELAY_MS));
             backoffcount += 1;
         }
-        let _ = self.publish_event(BusEvent::HostStopped(self.host_id.to_string()));
+        self.emit(HostEvent::HostStopped);
         std::thread::sleep(Duration::from_millis(300));
         let mut lock = self.nc.write().unwrap();
         let conn = lock.take();
@@ -204,11 +232,20 @@ impl DistributedBus {
             .as_ref()
             .unwrap()
             .queue_subscribe(subject, subject)?
-            .with_handler(move |msg| {
-                handle_invocation(&msg, sender.clone(), receiver.clone());
-                Ok(())
+            .with_handler({
+                let sender = sender.clone();
+                let (in_flight, host_id) = (self.in_flight.clone(), self.host_id.clone());
+                move |msg| {
+                    handle_invocation(&msg, sender.clone(), receiver.clone(), &in_flight, &host_id);
+                    Ok(())
+                }
             });
-        self.subs.write().unwrap().insert(subject.to_string(), sub);
+        self.subs
+            .write()
+            .unwrap()
+            .entry(subject.to_string())
+            .or_default()
+            .push((sender, sub));
         Ok(())
     }
 
@@ -225,41 +262,104 @@ impl DistributedBus {
             .as_ref()
             .unwrap()
             .subscribe(subject)?
-            .with_handler(move |msg| {
-                handle_invocation(&msg, sender.clone(), receiver.clone());
-                Ok(())
+            .with_handler({
+                let sender = sender.clone();
+                let (in_flight, host_id) = (self.in_flight.clone(), self.host_id.clone());
+                move |msg| {
+                    handle_invocation(&msg, sender.clone(), receiver.clone(), &in_flight, &host_id);
+                    Ok(())
+                }
             });
-        self.subs.write().unwrap().insert(subject.to_string(), sub);
+        self.subs
+            .write()
+            .unwrap()
+            .entry(subject.to_string())
+            .or_default()
+            .push((sender, sub));
         Ok(())
     }
 
     pub fn invoke(&self, subject: &str, inv: Invocation) -> Result<InvocationResponse> {
+        let res = match self.in_flight.enter(&inv) {
+            Some(_guard) => self.deliver(subject, &inv),
+            None => Ok(super::draining_response(&inv)),
+        };
+        if let Some(event) = super::invocation_failure(&inv, &res) {
+            self.emit(event);
+        }
+        res
+    }
+
+    fn deliver(&self, subject: &str, inv: &Invocation) -> Result<InvocationResponse> {
         if self.nc.read().unwrap().as_ref().is_none() {
             error!(
                 "Attempted bus invoke with no bus connection: {} {:?}->{:?}",
                 inv.operation, inv.origin, inv.target
             );
-            Err(crate::errors::new(crate::errors::ErrorKind::MiscHost(
-                "Attempted a bus invocation without a live bus connection".to_string(),
-            )))
+            Err(super::invocation_error(
+                inv,
+                crate::InvocationErrorCode::HostFailure,
+                "Attempted a bus invocation without a live bus connection",
+            ))
         } else {
-            let resp = self.nc.read().unwrap().as_ref().unwrap().request_timeout(
+            // Invocations with their own deadline get that long (plus the usual round trip)
+            let req_timeout = inv
+                .timeout
+                .map_or(self.req_timeout, |t| t + self.req_timeout);
+            let resp = match self.nc.read().unwrap().as_ref().unwrap().request_timeout(
                 &subject,
                 &serialize(inv)?,
-                self.req_timeout,
-            )?;
+                req_timeout,
+            ) {
+                Ok(r) => r,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut && inv.timeout.is_some() => {
+                    return Ok(InvocationResponse::timeout(inv, req_timeout))
+                }
+                Err(e) => return Err(e.into()),
+            };
             let ir: InvocationResponse = deserialize(&resp.data)?;
             Ok(ir)
         }
     }
 
     pub fn unsubscribe(&self, subject: &str) -> Result<()> {
-        if let Some(sub) = self.subs.write().unwrap().remove(subject) {
-            sub.unsubscribe()?;
+        if let Some(subs) = self.subs.write().unwrap().remove(subject) {
+            for (_, sub) in subs {
+                sub.unsubscribe()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a single subscriber (identified by its invocation channel) from a subject
+    /// that may have several, such as an actor running multiple instances
+    pub fn unsubscribe_instance(&self, subject: &str, sender: &Sender<Invocation>) -> Result<()> {
+        let mut lock = self.subs.write().unwrap();
+        if let Some(subs) = lock.get_mut(subject) {
+            if let Some(idx) = subs.iter().position(|(s, _)| s.same_channel(sender)) {
+                let (_, sub) = subs.remove(idx);
+                sub.unsubscribe()?;
+            }
+            if subs.is_empty() {
+                lock.remove(subject);
+            }
         }
         Ok(())
     }
 
+    /// Delivers a host event to the local event subscribers and, if it has a lattice
+    /// counterpart, publishes it on the lattice
+    pub fn emit(&self, event: HostEvent) {
+        if let Some(be) = event.to_bus_event(&self.host_id) {
+            let _ = self.publish_event(be);
+        }
+        self.events.publish(event);
+    }
+
+    pub fn events(&self) -> Receiver<HostEvent> {
+        self.events.subscribe()
+    }
+
     pub fn publish_event(&self, event: BusEvent) -> Result<()> {
         let cloud_event = CloudEvent::from(event);
         let payload = match serde_json::to_vec(&cloud_event) {
@@ -278,6 +378,22 @@ impl DistributedBus {
         Ok(())
     }
 
+    /// Publishes a signed revocation token to the control plane of every host in the lattice
+    pub fn publish_revocation(&self, token: &str) -> Result<()> {
+        let subject = format!(
+            "{}.{}.{}",
+            super::nsprefix(self.ns.as_ref().map(String::as_str)),
+            CPLANE_PREFIX,
+            REVOKE_ACTOR
+        );
+        let lock = self.nc.read().unwrap();
+        if let Some(nc) = lock.as_ref() {
+            nc.publish(&subject, token)?;
+            nc.flush()?;
+        }
+        Ok(())
+    }
+
     pub fn actor_subject(&self, actor: &str) -> String {
         super::actor_subject(self.ns.as_ref().map(String::as_str), actor)
     }
@@ -328,8 +444,13 @@ pub(crate) fn spawn_controlplane(
     let bindings = host.bindings.clone();
     let claimsmap = host.claims.clone();
     let terminators = host.terminators.clone();
+    let instances = host.instances.clone();
     let hk = host.key.clone();
-    let auth = host.authorizer.clone();
+    let security = host.security();
+    let threads = host.threads.clone();
+    let revisions = host.revisions.clone();
+    let trusted_issuers = host.trusted_issuers.clone();
+    let revocations = host.revocations.clone();
     let gantry = host.gantry_client.clone();
 
     let subject = format!(
@@ -367,21 +488,36 @@ pub(crate) fn spawn_controlplane(
                                         error!("Attempt to remotely schedule invalid actor.");
                                         continue;
                                     }
-                                    if !auth.read().unwrap().can_load(&a.token.claims) {
+                                    if crate::authz::enforce_trusted_issuer(&trusted_issuers, &a.token.claims, &bus).is_err() {
+                                        error!("Attempt to remotely schedule actor from an untrusted issuer.");
+                                        continue;
+                                    }
+                                    if crate::authz::enforce_revocation(&revocations.read().unwrap(), &a.token.claims, &bus).is_err() {
+                                        error!("Attempt to remotely schedule revoked actor.");
+                                        continue;
+                                    }
+                                    if !security.authorizer.read().unwrap().can_load(&a.token.claims) {
                                         error!("Authorization hook denied access to remotely scheduled module.");
                                         continue;
                                     }
 
+                                    if let Err(e) = security.aliases.write().unwrap().declare(&a.token.claims) {
+                                        error!("Remotely scheduled actor declares conflicting call aliases: {}", e);
+                                        continue;
+                                    }
                                     crate::authz::register_claims(
                                         claims.clone(),
                                         &a.token.claims.subject,
                                         a.token.claims.clone(),
                                     );
 
-                                    let _ = crate::spawns::spawn_actor(wg, a.token.claims.clone(), a.bytes,
-                                        None, actor, binding.clone(), bus.clone(), mids.clone(),
+                                    if let Ok(handle) = crate::spawns::spawn_actor(wg, a.token.claims.clone(), a.bytes.clone(),
+                                        crate::spawns::ActorSettings::new(instances.clone()), None, actor, binding.clone(), bus.clone(), mids.clone(),
                                         caps.clone(), bindings.clone(), claimsmap.clone(), terminators.clone(),
-                                        hk.clone(), auth.clone());
+                                        hk.clone(), security.clone()) {
+                                        crate::track_thread(&threads, &a.token.claims.subject, handle);
+                                        revisions.write().unwrap().record(&a.token.claims, &a.bytes, a.source.clone());
+                                    }
 
 
                                 },
@@ -392,10 +528,20 @@ pub(crate) fn spawn_controlplane(
                         },
                         ControlCommand::TerminateActor(cmd) => {
                             let actor_subject = bus.actor_subject(&cmd.actor_id);
-                            terminators.read().unwrap()
-                                [&actor_subject]
-                                .send(true)
-                                .unwrap();
+                            let count = instances.read().unwrap().get(&cmd.actor_id).map(|rec| rec.count).unwrap_or(1);
+                            if let Err(e) = crate::inthost::stop_actor_instances(terminators.clone(), &actor_subject, count) {
+                                error!("Failed to terminate actor {}: {}", &cmd.actor_id, e);
+                            }
+                        }
+                        ControlCommand::Revoke(token) => {
+                            match crate::revocation::verify(&token, &trusted_issuers) {
+                                Ok(r) => {
+                                    if crate::revocation::apply(&r, &revocations, &claims, &instances, terminators.clone(), &bus) {
+                                        info!("Applied revocation received from the lattice: {:?}", r.entity);
+                                    }
+                                }
+                                Err(e) => warn!("Ignoring revocation received from the lattice: {}", e),
+                            }
                         }
                     }
                 }
@@ -416,7 +562,10 @@ fn fetch_actor(
 ) -> Result<crate::actor::Actor> {
     let vec = actor_bytes_from_gantry(actor_id, gantry, revision)?;
 
-    crate::actor::Actor::from_slice(&vec)
+    Ok(crate::actor::Actor {
+        source: crate::ActorSource::Gantry { revision },
+        ..crate::actor::Actor::from_slice(&vec)?
+    })
 }
 
 pub(crate) fn actor_bytes_from_gantry(
@@ -478,6 +627,9 @@ fn spawn_controlplane_handler(
                 } else {
                     cplane_s.send(ControlCommand::TerminateActor(tc)).unwrap();
                 }
+            } else if msg.subject.ends_with(REVOKE_ACTOR) {
+                let token = String::from_utf8_lossy(&msg.data).to_string();
+                cplane_s.send(ControlCommand::Revoke(token)).unwrap();
             } else if msg.subject.ends_with(AUCTION_REQ) {
                 let req: LaunchAuctionRequest = serde_json::from_slice(&msg.data)?;
                 if claims.read().unwrap().contains_key(&req.actor_id) {
@@ -630,26 +782,54 @@ fn respond_with_caps(
         .map_err(|e| e.into())
 }
 
-// This function is invoked any time an invocation is _received_ by the message bus
+// This function is invoked any time an invocation is _received_ by the message bus. Invocations
+// from other hosts are counted as in flight like local ones, and refused once this host is
+// draining. This host's own invocations were counted when they were sent
 fn handle_invocation(
     msg: &nats::Message,
     sender: Sender<Invocation>,
     receiver: Receiver<InvocationResponse>,
+    in_flight: &super::InFlight,
+    host_id: &str,
 ) {
     let inv = invocation_from_msg(msg);
     //TODO: when we implement the issue, check that the invocation's origin host is not in the block list
     if let Err(e) = inv.validate_antiforgery() {
         error!("Invocation Antiforgery check failure: {}", e);
-        let inv_r = InvocationResponse::error(&inv, &format!("Antiforgery check failure: {}", e));
+        let inv_r = InvocationResponse::error_with_code(
+            &inv,
+            InvocationErrorCode::AntiforgeryFailure,
+            &format!("Antiforgery check failure: {}", e),
+        );
         msg.respond(serialize(inv_r).unwrap()).unwrap();
-    // TODO: when we implement the issue, publish an antiforgery check event on wasmbus.events
-    // TODO: when we implement the issue, add the host origin of the invocation to the global lattice block list
+        // TODO: when we implement the issue, publish an antiforgery check event on wasmbus.events
+        // TODO: when we implement the issue, add the host origin of the invocation to the global lattice block list
+        return;
+    }
+    let _guard = if inv.host_id == host_id {
+        None
     } else {
-        if let Ok(()) = sender.send(inv) {
+        match in_flight.enter(&inv) {
+            Some(guard) => Some(guard),
+            None => {
+                let _ = msg.respond(serialize(super::draining_response(&inv)).unwrap());
+                return;
+            }
+        }
+    };
+    match sender.send(inv) {
+        Ok(()) => {
             let inv_r = receiver.recv().unwrap();
             msg.respond(serialize(inv_r).unwrap()).unwrap();
-        } else {
+        }
+        Err(e) => {
             warn!("Received invocation but its destination thread is no longer running.");
+            let inv_r = InvocationResponse::error_with_code(
+                &e.0,
+                InvocationErrorCode::Unavailable,
+                "Invocation target is no longer running",
+            );
+            let _ = msg.respond(serialize(inv_r).unwrap());
         }
     }
 }
@@ -1,5 +1,6 @@
 #[cfg(feature = "lattice")]
 use crossbeam::Sender;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 pub const URL_SCHEME: &str = "wasmbus";
 
@@ -57,6 +58,91 @@ pub(crate) fn new(
 
 const LATTICE_NAMESPACE_ENV: &str = "LATTICE_NAMESPACE";
 
+/// Counts the invocations passing through a bus. Once the bus starts draining, new invocations
+/// for actors are refused, while invocations of capability providers are still let through so
+/// that the actors' in-flight work can complete
+#[derive(Default)]
+pub(crate) struct InFlight {
+    draining: AtomicBool,
+    count: AtomicUsize,
+}
+
+/// Marks an invocation as in flight until dropped
+pub(crate) struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl InFlight {
+    pub(crate) fn enter(&self, inv: &crate::Invocation) -> Option<InFlightGuard<'_>> {
+        if self.draining.load(Ordering::SeqCst) {
+            if let crate::WasccEntity::Actor(_) = inv.target {
+                return None;
+            }
+        }
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Some(InFlightGuard(&self.count))
+    }
+
+    pub(crate) fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+// The response to an invocation refused because the host is shutting down
+pub(crate) fn draining_response(inv: &crate::Invocation) -> crate::InvocationResponse {
+    crate::InvocationResponse::error_with_code(
+        inv,
+        crate::InvocationErrorCode::Unavailable,
+        "Host is shutting down and no longer accepts invocations",
+    )
+}
+
+// An error for an invocation that failed before it reached its target
+pub(crate) fn invocation_error(
+    inv: &crate::Invocation,
+    code: crate::InvocationErrorCode,
+    reason: &str,
+) -> crate::errors::Error {
+    crate::errors::new(crate::errors::ErrorKind::Invocation {
+        invocation_id: inv.id.to_string(),
+        code,
+        reason: reason.to_string(),
+    })
+}
+
+// Describes the outcome of an invocation as a host event, if the invocation failed
+pub(crate) fn invocation_failure(
+    inv: &crate::Invocation,
+    res: &crate::Result<crate::InvocationResponse>,
+) -> Option<crate::HostEvent> {
+    let (code, reason) = match res {
+        Ok(r) => (
+            r.error_code.unwrap_or(crate::InvocationErrorCode::Other),
+            r.error.clone()?,
+        ),
+        Err(e) => match e.kind() {
+            crate::errors::ErrorKind::Invocation { code, reason, .. } => (*code, reason.clone()),
+            _ => (crate::InvocationErrorCode::HostFailure, e.to_string()),
+        },
+    };
+    Some(crate::HostEvent::InvocationFailed {
+        invocation_id: inv.id.to_string(),
+        origin: inv.origin.clone(),
+        target: inv.target.clone(),
+        operation: inv.operation.to_string(),
+        code,
+        reason,
+    })
+}
+
 pub(crate) fn get_namespace_prefix() -> Option<String> {
     ::std::env::var(LATTICE_NAMESPACE_ENV).ok()
 }
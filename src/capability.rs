@@ -1,12 +1,80 @@
+use crate::errors::{self, ErrorKind, PluginError};
 use crate::Result;
+use data_encoding::HEXUPPER;
 use libloading::Library;
 use libloading::Symbol;
+use ring::digest::{digest, SHA256};
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use wascc_codec::{
     capabilities::{CapabilityDescriptor, CapabilityProvider, OP_GET_CAPABILITY_DESCRIPTOR},
     deserialize, SYSTEM_ACTOR,
 };
 
+/// The symbol through which a native capability provider library reports the provider ABI
+/// version it was built with (see `provider_abi_version`). It names a function with the
+/// signature `unsafe extern "C" fn(buf: *mut u8, len: usize) -> usize`, which copies as much
+/// of the version as fits into the `len` bytes at `buf` and returns the version's full length
+pub const PROVIDER_ABI_SYMBOL: &str = "__capability_provider_abi";
+
+/// The version of the native provider ABI (the `CapabilityProvider` trait object handed across
+/// the FFI boundary) supported by this host. It names the wascc-codec and compiler versions the
+/// host was built with, as in `wascc-codec 0.8.1, rustc 1.46.0 (04488afe3 2020-08-24)`, since
+/// the trait object's layout depends on both. Libraries that report a different version under
+/// `PROVIDER_ABI_SYMBOL`, or none at all, are refused.
+///
+/// Providers that depend on wascc-host can export the version with `provider_abi_version!`.
+/// Those that only depend on wascc-codec set `WASCC_RUSTC_VERSION` to the output of
+/// `rustc --version` from their build script, and export the version themselves:
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub unsafe extern "C" fn __capability_provider_abi(buf: *mut u8, len: usize) -> usize {
+///     let abi = format!("wascc-codec {}, {}", wascc_codec::VERSION, env!("WASCC_RUSTC_VERSION"));
+///     std::ptr::copy_nonoverlapping(abi.as_ptr(), buf, abi.len().min(len));
+///     abi.len()
+/// }
+/// ```
+pub fn provider_abi_version() -> String {
+    format!(
+        "wascc-codec {}, {}",
+        wascc_codec::VERSION,
+        env!("WASCC_RUSTC_VERSION")
+    )
+}
+
+/// Exports the provider ABI version from a native capability provider library, next to
+/// `capability_provider!`, for providers that depend on wascc-host. The version then names
+/// the compiler the provider itself was built with
+#[macro_export]
+macro_rules! provider_abi_version {
+    () => {
+        /// Reports the provider ABI version this library was built with
+        ///
+        /// # Safety
+        ///
+        /// `buf` must be valid for writes of `len` bytes
+        #[no_mangle]
+        pub unsafe extern "C" fn __capability_provider_abi(buf: *mut u8, len: usize) -> usize {
+            let abi = $crate::provider_abi_version();
+            ::std::ptr::copy_nonoverlapping(abi.as_ptr(), buf, abi.len().min(len));
+            abi.len()
+        }
+    };
+}
+
+/// Where a capability provider came from, as presented to `Authorizer::can_load_provider`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProviderProvenance {
+    /// The file the provider was loaded from. `None` for embedded and portable providers
+    pub path: Option<PathBuf>,
+    /// The SHA-256 hash (upper-case hex) of the provider's library, or the module hash from
+    /// the claims of a portable provider. `None` for embedded providers
+    pub hash: Option<String>,
+    /// The public key of the account that signed the provider, if it was signed
+    pub signer: Option<String>,
+}
+
 /// Represents a native capability provider compiled as a shared object library.
 /// These plugins are OS- and architecture-specific, so they will be `.so` files on Linux, `.dylib`
 /// files on macOS, etc.
@@ -14,6 +82,7 @@ pub struct NativeCapability {
     pub(crate) plugin: Box<dyn CapabilityProvider>,
     pub(crate) binding_name: String,
     pub(crate) descriptor: CapabilityDescriptor,
+    pub(crate) provenance: ProviderProvenance,
     // This field is solely used to keep the FFI library instance allocated for the same
     // lifetime as the boxed plugin
     #[allow(dead_code)]
@@ -23,15 +92,43 @@ pub struct NativeCapability {
 impl NativeCapability {
     /// Reads a capability provider from a file. The capability provider must implement the
     /// correct FFI interface to support waSCC plugins. See [wascc.dev](https://wascc.dev) for
-    /// documentation and tutorials on how to create a native capability provider
+    /// documentation and tutorials on how to create a native capability provider. The library
+    /// is opened right away; use `Host::add_native_capability_file` to have the host's
+    /// authorizer vet the file before any of its code runs
     pub fn from_file<P: AsRef<OsStr>>(
         filename: P,
         binding_target_name: Option<String>,
+    ) -> Result<Self> {
+        NativeCapability::from_file_checked(filename, binding_target_name, |_| Ok(()))
+    }
+
+    // The library is only opened once `check` has accepted its provenance, since opening it
+    // runs its initializers
+    pub(crate) fn from_file_checked<P: AsRef<OsStr>>(
+        filename: P,
+        binding_target_name: Option<String>,
+        check: impl FnOnce(&ProviderProvenance) -> Result<()>,
     ) -> Result<Self> {
         type PluginCreate = unsafe fn() -> *mut dyn CapabilityProvider;
+        type PluginAbiVersion = unsafe extern "C" fn(*mut u8, usize) -> usize;
 
+        let provenance = file_provenance(Path::new(filename.as_ref()))?;
+        check(&provenance)?;
         let library = Library::new(filename.as_ref())?;
 
+        let version = unsafe {
+            library
+                .get::<PluginAbiVersion>(PROVIDER_ABI_SYMBOL.as_bytes())
+                .ok()
+                .map(|version| {
+                    // A version too long to fit can't be the one supported anyway
+                    let mut buf = [0u8; 256];
+                    let len = version(buf.as_mut_ptr(), buf.len()).min(buf.len());
+                    String::from_utf8_lossy(&buf[..len]).to_string()
+                })
+        };
+        check_abi_version(version)?;
+
         let plugin = unsafe {
             let constructor: Symbol<PluginCreate> = library.get(b"__capability_provider_create")?;
             let boxed_raw = constructor();
@@ -48,11 +145,111 @@ impl NativeCapability {
         Ok(NativeCapability {
             plugin,
             descriptor,
+            provenance,
             binding_name: binding,
             library: Some(library),
         })
     }
 
+    /// Reads a capability provider from a file like `from_file`, but loads and runs it in a child
+    /// process rather than in the host, so that a crash in the provider doesn't bring the host
+    /// down. The host restarts the child process if it exits, according to the isolation's
+    /// restart policy, and re-establishes the provider's actor bindings in the new process
+    #[cfg(unix)]
+    pub fn from_file_isolated<P: AsRef<Path>>(
+        filename: P,
+        binding_target_name: Option<String>,
+        isolation: crate::ProcessIsolation,
+    ) -> Result<Self> {
+        let provenance = file_provenance(filename.as_ref())?;
+        let plugin: Box<dyn CapabilityProvider> = Box::new(
+            crate::isolation::IsolatedProvider::start(filename.as_ref(), isolation)?,
+        );
+        let descriptor = get_descriptor(&plugin)?;
+        let binding = binding_target_name.unwrap_or("default".to_string());
+        info!(
+            "Loaded isolated capability provider '{}' v{} ({}) for {}/{}",
+            descriptor.name, descriptor.version, descriptor.revision, descriptor.id, binding
+        );
+
+        Ok(NativeCapability {
+            plugin,
+            descriptor,
+            provenance,
+            binding_name: binding,
+            library: None,
+        })
+    }
+
+    /// Loads the library for the given target (e.g. `x86_64-linux`) from a verified provider
+    /// archive. The provider must report the capability ID recorded in the archive's claims
+    #[cfg(feature = "provider_archive")]
+    pub fn from_archive(
+        archive: &crate::ProviderArchive,
+        target: &str,
+        binding_target_name: Option<String>,
+    ) -> Result<Self> {
+        NativeCapability::from_archive_checked(archive, target, binding_target_name, |_| Ok(()))
+    }
+
+    #[cfg(feature = "provider_archive")]
+    pub(crate) fn from_archive_checked(
+        archive: &crate::ProviderArchive,
+        target: &str,
+        binding_target_name: Option<String>,
+        check: impl FnOnce(&ProviderProvenance) -> Result<()>,
+    ) -> Result<Self> {
+        let claims = archive.claims().ok_or_else(|| {
+            errors::new(ErrorKind::CapabilityProvider(
+                "Provider archive has not been signed and verified".to_string(),
+            ))
+        })?;
+        let library = archive.library(target).ok_or_else(|| {
+            errors::new(ErrorKind::CapabilityProvider(format!(
+                "Provider archive for {} has no library for target {}",
+                archive.capid(),
+                target
+            )))
+        })?;
+        let hash = crate::archive::hash(library);
+        let provenance = ProviderProvenance {
+            path: archive.path.clone(),
+            hash: Some(hash.to_string()),
+            signer: Some(claims.issuer.to_string()),
+        };
+        check(&provenance)?;
+        // The library is written out to a new file in a new directory that only this user can
+        // access, so that it can't be planted or swapped before it is opened, and the hash of
+        // the file that is opened is checked again. Both are removed once it is loaded
+        let dir = private_temp_dir()?;
+        let path = dir.join(format!("provider.{}", std::env::consts::DLL_EXTENSION));
+        let loaded = write_private(&path, library).and_then(|_| {
+            NativeCapability::from_file_checked(&path, binding_target_name, |opened| {
+                if opened.hash.as_ref() == Some(&hash) {
+                    Ok(())
+                } else {
+                    Err(errors::new(ErrorKind::CapabilityProvider(format!(
+                        "Library extracted from the provider archive for {} has been altered",
+                        archive.capid()
+                    ))))
+                }
+            })
+        });
+        let _ = std::fs::remove_dir_all(&dir);
+        let capability = loaded?;
+        if capability.descriptor.id != archive.capid() {
+            return Err(errors::new(ErrorKind::CapabilityProvider(format!(
+                "Provider archive for {} contains a provider for {}",
+                archive.capid(),
+                capability.descriptor.id
+            ))));
+        }
+        Ok(NativeCapability {
+            provenance,
+            ..capability
+        })
+    }
+
     /// This function is to be used for _capability embedding_. If you are building a custom
     /// waSCC host and have a fixed set of capabilities that you want to always be available
     /// to actors, then you can declare a dependency on the capability provider, enable
@@ -72,6 +269,7 @@ impl NativeCapability {
         );
         Ok(NativeCapability {
             descriptor,
+            provenance: ProviderProvenance::default(),
             plugin: b,
             binding_name: binding,
             library: None,
@@ -92,6 +290,52 @@ impl NativeCapability {
     pub fn descriptor(&self) -> &CapabilityDescriptor {
         &self.descriptor
     }
+
+    /// Returns where the provider was loaded from
+    pub fn provenance(&self) -> &ProviderProvenance {
+        &self.provenance
+    }
+}
+
+fn check_abi_version(found: Option<String>) -> Result<()> {
+    let expected = provider_abi_version();
+    if found.as_ref() == Some(&expected) {
+        Ok(())
+    } else {
+        Err(errors::new(ErrorKind::Plugin(
+            PluginError::IncompatibleAbi { expected, found },
+        )))
+    }
+}
+
+fn file_provenance(path: &Path) -> Result<ProviderProvenance> {
+    Ok(ProviderProvenance {
+        path: Some(path.to_path_buf()),
+        hash: Some(HEXUPPER.encode(digest(&SHA256, &std::fs::read(path)?).as_ref())),
+        signer: None,
+    })
+}
+
+#[cfg(feature = "provider_archive")]
+fn private_temp_dir() -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("wascc-provider-{}", uuid::Uuid::new_v4()));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    // Fails if the directory already exists, rather than reusing one someone else created
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(feature = "provider_archive")]
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(bytes)?;
+    Ok(())
 }
 
 fn get_descriptor(plugin: &Box<dyn CapabilityProvider>) -> Result<CapabilityDescriptor> {
@@ -99,3 +343,63 @@ fn get_descriptor(plugin: &Box<dyn CapabilityProvider>) -> Result<CapabilityDesc
     let descriptor: CapabilityDescriptor = deserialize(&res)?;
     Ok(descriptor)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{check_abi_version, provider_abi_version};
+    use crate::errors::{ErrorKind, PluginError};
+
+    crate::provider_abi_version!();
+
+    #[test]
+    fn mismatched_abi_versions_are_refused() {
+        let version = provider_abi_version();
+        assert!(version.starts_with(&format!("wascc-codec {}, rustc ", wascc_codec::VERSION)));
+        assert!(check_abi_version(Some(version.clone())).is_ok());
+        let other = version.replace("rustc", "rustc 0.0.0");
+        for found in &[None, Some(other)] {
+            match check_abi_version(found.clone()).unwrap_err().into_kind() {
+                ErrorKind::Plugin(PluginError::IncompatibleAbi {
+                    expected,
+                    found: actual,
+                }) => {
+                    assert_eq!(version, expected);
+                    assert_eq!(*found, actual);
+                }
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn abi_version_is_exported_in_full() {
+        let version = provider_abi_version();
+        let mut buf = [0u8; 256];
+        let len = unsafe { __capability_provider_abi(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(version.as_bytes(), &buf[..len]);
+        // A buffer that is too small gets as much as fits, and the full length
+        let mut short = [0u8; 4];
+        let len = unsafe { __capability_provider_abi(short.as_mut_ptr(), short.len()) };
+        assert_eq!(version.len(), len);
+        assert_eq!(&version.as_bytes()[..4], &short);
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "provider_archive"))]
+    fn archive_libraries_are_extracted_privately() {
+        use super::{private_temp_dir, write_private};
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::Path;
+
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        let dir = private_temp_dir().unwrap();
+        let path = dir.join("provider.so");
+        write_private(&path, b"library").unwrap();
+        assert_eq!(0o700, mode(&dir));
+        assert_eq!(0o600, mode(&path));
+        // An existing file is never written through
+        assert!(write_private(&path, b"other").is_err());
+        assert_eq!(b"library".to_vec(), std::fs::read(&path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,437 @@
+// Execution deadlines and trap reporting for guest modules, and the wasmtime engine provider that
+// enforces them. The engine is adapted from the `wasmtime-provider` crate, with the addition of
+// interruptable stores so that a guest stuck in a loop can be stopped from another thread
+
+use crossbeam_channel::{self as channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "wasmtime")]
+mod callbacks;
+#[cfg(feature = "wasmtime")]
+mod modreg;
+
+#[cfg(feature = "wasmtime")]
+pub(crate) use self::wasmtime::WasmtimeEngineProvider;
+
+/// A handle shared between an actor's thread and its engine provider. The actor thread sets
+/// the timeout for the next guest call, the engine arms the instance's watchdog around that
+/// call, and the actor thread can then find out whether the call was interrupted for exceeding
+/// its deadline or trapped
+#[derive(Clone, Default)]
+pub(crate) struct GuestMonitor {
+    inner: Arc<Mutex<MonitorState>>,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    timeout: Option<Duration>,
+    generation: u64,
+    running: bool,
+    expired: bool,
+    trapped: bool,
+    // A single watchdog thread serves every call made through the monitor. It is started by
+    // the first call with a timeout and exits once the monitor is dropped
+    watchdog: Option<channel::Sender<Deadline>>,
+}
+
+/// The deadline of a guest call, handed to the watchdog when the call is made
+struct Deadline {
+    generation: u64,
+    at: Instant,
+    interrupt: Box<dyn Fn() + Send>,
+}
+
+/// Identifies the guest call that the watchdog has been armed for
+pub(crate) struct Watch {
+    generation: u64,
+}
+
+impl GuestMonitor {
+    pub(crate) fn new() -> GuestMonitor {
+        GuestMonitor::default()
+    }
+
+    /// Sets the maximum execution time of the next guest call. `None` means the call may
+    /// run for as long as it needs
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.timeout = timeout;
+        lock.expired = false;
+    }
+
+    /// Indicates whether the most recent guest call was interrupted for exceeding its deadline
+    pub(crate) fn expired(&self) -> bool {
+        self.inner.lock().unwrap().expired
+    }
+
+    /// Indicates whether the most recent guest call trapped (other than by being interrupted)
+    pub(crate) fn trapped(&self) -> bool {
+        self.inner.lock().unwrap().trapped
+    }
+
+    /// Records the outcome of a guest call made by the engine
+    #[allow(dead_code)]
+    pub(crate) fn record_trap(&self, trapped: bool) {
+        self.inner.lock().unwrap().trapped = trapped;
+    }
+
+    /// Arms the watchdog to invoke `interrupt` if the guest call that is about to be made
+    /// outlives the current timeout. Returns `None` if no timeout is set
+    #[allow(dead_code)]
+    pub(crate) fn arm(&self, interrupt: impl Fn() + Send + 'static) -> Option<Watch> {
+        let mut lock = self.inner.lock().unwrap();
+        let timeout = lock.timeout?;
+        lock.generation += 1;
+        lock.running = true;
+        lock.expired = false;
+        let deadline = Deadline {
+            generation: lock.generation,
+            at: Instant::now() + timeout,
+            interrupt: Box::new(interrupt),
+        };
+        let generation = deadline.generation;
+        let state = Arc::downgrade(&self.inner);
+        // The watchdog only exits once the monitor is gone, so it is there to take the deadline
+        let _ = lock
+            .watchdog
+            .get_or_insert_with(|| start_watchdog(state))
+            .send(deadline);
+        Some(Watch { generation })
+    }
+
+    /// Stops the watchdog for a completed guest call and returns `true` if the call was
+    /// interrupted (or an interrupt is still pending for it)
+    #[allow(dead_code)]
+    pub(crate) fn disarm(&self, watch: Option<Watch>) -> bool {
+        match watch {
+            Some(w) => {
+                let mut lock = self.inner.lock().unwrap();
+                if lock.generation == w.generation {
+                    lock.running = false;
+                }
+                lock.expired
+            }
+            None => false,
+        }
+    }
+}
+
+// Runs the watchdog of a monitor: waits for the deadline of the guest call being made, and
+// interrupts the call if it is still running once the deadline has passed. A newer deadline
+// replaces the one being waited for, as calls are made one at a time
+fn start_watchdog(state: Weak<Mutex<MonitorState>>) -> channel::Sender<Deadline> {
+    let (deadline_s, deadline_r) = channel::unbounded::<Deadline>();
+    thread::spawn(move || {
+        let mut pending: Option<Deadline> = None;
+        loop {
+            let next = match &pending {
+                Some(d) => deadline_r.recv_timeout(d.at.saturating_duration_since(Instant::now())),
+                None => deadline_r
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match next {
+                Ok(d) => pending = Some(d),
+                Err(RecvTimeoutError::Timeout) => {
+                    let d = pending.take().unwrap();
+                    let state = match state.upgrade() {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    let mut lock = state.lock().unwrap();
+                    // The call may have finished while we were waiting for the lock
+                    if lock.running && lock.generation == d.generation {
+                        (d.interrupt)();
+                        lock.expired = true;
+                    }
+                }
+                // The monitor, and with it the sending end, has been dropped
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+    deadline_s
+}
+
+#[cfg(feature = "wasmtime")]
+mod wasmtime {
+    use super::callbacks;
+    use super::modreg::{self, ModuleRegistry};
+    use super::GuestMonitor;
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use wapc::{ModuleState, WapcFunctions, WasiParams, WebAssemblyEngineProvider, HOST_NAMESPACE};
+    use wasmtime_rt::{
+        Config, Engine, Extern, ExternType, Func, Instance, InterruptHandle, Module, Store,
+    };
+
+    // namespace needed for some language support
+    const WASI_UNSTABLE_NAMESPACE: &str = "wasi_unstable";
+    const WASI_SNAPSHOT_PREVIEW1_NAMESPACE: &str = "wasi_snapshot_preview1";
+
+    struct EngineInner {
+        instance: Rc<RefCell<Option<Instance>>>,
+        guest_call_fn: Func,
+        interrupt: Arc<InterruptHandle>,
+        host: Arc<ModuleState>,
+    }
+
+    /// A waPC engine provider that encapsulates the Wasmtime WebAssembly runtime. Guest calls
+    /// are interrupted once they exceed the timeout set on the provider's `GuestMonitor`, and
+    /// traps are reported to the monitor
+    pub(crate) struct WasmtimeEngineProvider {
+        inner: Option<EngineInner>,
+        wasidata: Option<WasiParams>,
+        modbytes: Vec<u8>,
+        monitor: GuestMonitor,
+    }
+
+    impl WasmtimeEngineProvider {
+        /// Creates a new instance of the wasmtime provider
+        pub(crate) fn new(
+            buf: &[u8],
+            wasi: Option<WasiParams>,
+            monitor: GuestMonitor,
+        ) -> WasmtimeEngineProvider {
+            WasmtimeEngineProvider {
+                inner: None,
+                modbytes: buf.to_vec(),
+                wasidata: wasi,
+                monitor,
+            }
+        }
+
+        fn initialize(&self) -> Result<(), Box<dyn Error>> {
+            if let Some(ext) = self
+                .inner
+                .as_ref()
+                .unwrap()
+                .instance
+                .borrow()
+                .as_ref()
+                .unwrap()
+                .get_export("_start")
+            {
+                ext.into_func().unwrap().call(&[])?;
+            }
+            Ok(())
+        }
+
+        // An interrupted guest may have been stopped part way through updating its own memory
+        // (and a late interrupt would trap its next call), so it gets a fresh instance and store
+        fn reset(&mut self) -> Result<(), Box<dyn Error>> {
+            let host = self.inner.as_ref().unwrap().host.clone();
+            let (instance, interrupt) = instance_from_buffer(&self.modbytes, &self.wasidata, host)?;
+            let inner = self.inner.as_mut().unwrap();
+            inner.instance.replace(Some(instance));
+            inner.guest_call_fn = guest_call_fn(inner.instance.clone())?;
+            inner.interrupt = Arc::new(interrupt);
+            self.initialize()
+        }
+    }
+
+    impl WebAssemblyEngineProvider for WasmtimeEngineProvider {
+        fn init(&mut self, host: Arc<ModuleState>) -> Result<(), Box<dyn Error>> {
+            let instance_ref = Rc::new(RefCell::new(None));
+            let (instance, interrupt) =
+                instance_from_buffer(&self.modbytes, &self.wasidata, host.clone())?;
+            instance_ref.replace(Some(instance));
+            let gc = guest_call_fn(instance_ref.clone())?;
+            self.inner = Some(EngineInner {
+                instance: instance_ref,
+                guest_call_fn: gc,
+                interrupt: Arc::new(interrupt),
+                host,
+            });
+            self.initialize()?;
+            Ok(())
+        }
+
+        fn call(&mut self, op_length: i32, msg_length: i32) -> Result<i32, Box<dyn Error>> {
+            // Note that during this call, the guest should, through the functions
+            // it imports from the host, set the guest error and response
+            let inner = self.inner.as_ref().unwrap();
+            let interrupt = inner.interrupt.clone();
+            let watch = self.monitor.arm(move || interrupt.interrupt());
+
+            let callresult = inner
+                .guest_call_fn
+                .call(&[op_length.into(), msg_length.into()]);
+
+            if self.monitor.disarm(watch) {
+                self.monitor.record_trap(false);
+                self.reset()?;
+                return Err("Guest call interrupted after exceeding its execution deadline".into());
+            }
+            self.monitor.record_trap(callresult.is_err());
+            match callresult {
+                Ok(result) => Ok(result[0].i32().unwrap()),
+                Err(e) => {
+                    error!("Failure invoking guest module handler: {:?}", e);
+                    Err(format!("Guest module trapped: {}", e).into())
+                }
+            }
+        }
+
+        fn replace(&mut self, module: &[u8]) -> Result<(), Box<dyn Error>> {
+            info!(
+                "HOT SWAP - Replacing existing WebAssembly module with new buffer, {} bytes",
+                module.len()
+            );
+            self.modbytes = module.to_vec();
+            self.reset()
+        }
+    }
+
+    fn instance_from_buffer(
+        buf: &[u8],
+        wasi: &Option<WasiParams>,
+        state: Arc<ModuleState>,
+    ) -> Result<(Instance, InterruptHandle), Box<dyn Error>> {
+        let mut config = Config::new();
+        config.interruptable(true);
+        let engine = Engine::new(&config);
+        let store = Store::new(&engine);
+        let interrupt = store
+            .interrupt_handle()
+            .map_err(|e| format!("Failed to obtain interrupt handle: {}", e))?;
+        let module = Module::new(&engine, buf).map_err(|e| e.to_string())?;
+
+        let d = WasiParams::default();
+        let wasi = match wasi {
+            Some(w) => w,
+            None => &d,
+        };
+
+        // Make wasi available by default.
+        let preopen_dirs = modreg::compute_preopen_dirs(&wasi.preopened_dirs, &wasi.map_dirs)?;
+        let argv = vec![]; // TODO: add support for argv (if applicable)
+
+        let module_registry = ModuleRegistry::new(&store, &preopen_dirs, &argv, &wasi.env_vars)?;
+
+        let imports = arrange_imports(&module, state, store.clone(), &module_registry)?;
+
+        let instance = Instance::new(&store, &module, imports.as_slice())
+            .map_err(|e| format!("Failed to instantiate module: {}", e))?;
+        Ok((instance, interrupt))
+    }
+
+    /// wasmtime requires that the list of callbacks be "zippable" with the list
+    /// of module imports. In order to ensure that both lists are in the same
+    /// order, we have to loop through the module imports and instantiate the
+    /// corresponding callback. We **cannot** rely on a predictable import order
+    /// in the wasm module
+    fn arrange_imports(
+        module: &Module,
+        host: Arc<ModuleState>,
+        store: Store,
+        mod_registry: &ModuleRegistry,
+    ) -> Result<Vec<Extern>, Box<dyn Error>> {
+        module
+            .imports()
+            .filter_map(|imp| {
+                if let ExternType::Func(_) = imp.ty() {
+                    let ext = match imp.module() {
+                        HOST_NAMESPACE => {
+                            callback_for_import(imp.name(), host.clone(), store.clone())
+                                .ok_or_else(|| missing_import(imp.module(), imp.name()))
+                        }
+                        WASI_UNSTABLE_NAMESPACE => mod_registry
+                            .wasi_unstable
+                            .get_export(imp.name())
+                            .map(|f| Extern::from(f.clone()))
+                            .ok_or_else(|| missing_import(imp.module(), imp.name())),
+                        WASI_SNAPSHOT_PREVIEW1_NAMESPACE => mod_registry
+                            .wasi_snapshot_preview1
+                            .get_export(imp.name())
+                            .map(|f| Extern::from(f.clone()))
+                            .ok_or_else(|| missing_import(imp.module(), imp.name())),
+                        other => Err(format!("import module `{}` was not found", other).into()),
+                    };
+                    Some(ext)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn missing_import(module: &str, name: &str) -> Box<dyn Error> {
+        format!("import `{}` was not found in module `{}`", name, module).into()
+    }
+
+    fn callback_for_import(import: &str, host: Arc<ModuleState>, store: Store) -> Option<Extern> {
+        let ext = match import {
+            WapcFunctions::HOST_CONSOLE_LOG => callbacks::console_log_func(&store, host).into(),
+            WapcFunctions::HOST_CALL => callbacks::host_call_func(&store, host).into(),
+            WapcFunctions::GUEST_REQUEST_FN => callbacks::guest_request_func(&store, host).into(),
+            WapcFunctions::HOST_RESPONSE_FN => callbacks::host_response_func(&store, host).into(),
+            WapcFunctions::HOST_RESPONSE_LEN_FN => {
+                callbacks::host_response_len_func(&store, host).into()
+            }
+            WapcFunctions::GUEST_RESPONSE_FN => callbacks::guest_response_func(&store, host).into(),
+            WapcFunctions::GUEST_ERROR_FN => callbacks::guest_error_func(&store, host).into(),
+            WapcFunctions::HOST_ERROR_FN => callbacks::host_error_func(&store, host).into(),
+            WapcFunctions::HOST_ERROR_LEN_FN => callbacks::host_error_len_func(&store, host).into(),
+            _ => return None,
+        };
+        Some(ext)
+    }
+
+    // Called once per instance, then the result is cached. This returns a `Func` that
+    // corresponds to the `__guest_call` export
+    fn guest_call_fn(instance: Rc<RefCell<Option<Instance>>>) -> Result<Func, Box<dyn Error>> {
+        if let Some(func) = instance
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .get_func(WapcFunctions::GUEST_CALL)
+        {
+            Ok(func)
+        } else {
+            Err("Guest module did not export __guest_call function!".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GuestMonitor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn watchdog_is_rearmed_for_each_call() {
+        let monitor = GuestMonitor::new();
+        let interrupts = Arc::new(AtomicUsize::new(0));
+        let interrupt = || {
+            let interrupts = interrupts.clone();
+            move || {
+                interrupts.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        assert!(monitor.arm(interrupt()).is_none());
+
+        // A call that completes in time isn't interrupted once its deadline passes
+        monitor.set_timeout(Some(Duration::from_millis(50)));
+        let watch = monitor.arm(interrupt());
+        assert!(!monitor.disarm(watch));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(0, interrupts.load(Ordering::SeqCst));
+
+        // The next calls are watched by the same watchdog
+        for expected in 1..=2 {
+            let watch = monitor.arm(interrupt());
+            thread::sleep(Duration::from_millis(150));
+            assert!(monitor.disarm(watch));
+            assert!(monitor.expired());
+            assert_eq!(expected, interrupts.load(Ordering::SeqCst));
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// WASI module registration, borrowed from the wasmtime CLI
+
+use anyhow::Context as _;
+use std::error::Error;
+use std::fs::File;
+use wasi_common::preopen_dir;
+use wasmtime_rt::Store;
+use wasmtime_wasi::{old::snapshot_0::Wasi as WasiSnapshot0, Wasi};
+
+pub(crate) struct ModuleRegistry {
+    pub(crate) wasi_snapshot_preview1: Wasi,
+    pub(crate) wasi_unstable: WasiSnapshot0,
+}
+
+impl ModuleRegistry {
+    pub(crate) fn new(
+        store: &Store,
+        preopen_dirs: &[(String, File)],
+        argv: &[String],
+        vars: &[(String, String)],
+    ) -> Result<ModuleRegistry, Box<dyn Error>> {
+        let mut cx1 = wasi_common::WasiCtxBuilder::new();
+
+        cx1.inherit_stdio().args(argv).envs(vars);
+
+        for (name, file) in preopen_dirs {
+            cx1.preopened_dir(file.try_clone()?, name);
+        }
+
+        let cx1 = cx1.build()?;
+
+        let mut builder = wasi_common::old::snapshot_0::WasiCtxBuilder::new();
+
+        let mut cx2 = builder.inherit_stdio().args(argv).envs(vars);
+
+        for (name, file) in preopen_dirs {
+            cx2 = cx2.preopened_dir(file.try_clone()?, name);
+        }
+
+        let cx2 = cx2.build()?;
+
+        Ok(ModuleRegistry {
+            wasi_snapshot_preview1: Wasi::new(store, cx1),
+            wasi_unstable: WasiSnapshot0::new(store, cx2),
+        })
+    }
+}
+
+pub(crate) fn compute_preopen_dirs(
+    dirs: &[String],
+    map_dirs: &[(String, String)],
+) -> Result<Vec<(String, File)>, Box<dyn Error>> {
+    let mut preopen_dirs = Vec::new();
+
+    for dir in dirs.iter() {
+        preopen_dirs.push((
+            dir.clone(),
+            preopen_dir(dir).with_context(|| format!("failed to open directory '{}'", dir))?,
+        ));
+    }
+
+    for (guest, host) in map_dirs.iter() {
+        preopen_dirs.push((
+            guest.clone(),
+            preopen_dir(host).with_context(|| format!("failed to open directory '{}'", host))?,
+        ));
+    }
+
+    Ok(preopen_dirs)
+}
@@ -18,9 +18,60 @@ pub enum ErrorKind {
     IO(std::io::Error),
     CapabilityProvider(String),
     MiscHost(String),
-    Plugin(libloading::Error),
+    Plugin(PluginError),
     Middleware(String),
     Serialization(String),
+    Invocation {
+        invocation_id: String,
+        code: crate::InvocationErrorCode,
+        reason: String,
+    },
+}
+
+/// Failures to load a native capability provider library
+#[derive(Debug)]
+pub enum PluginError {
+    /// The library could not be opened or lacks a required symbol
+    Library(libloading::Error),
+    /// The library was built against a provider ABI the host doesn't support. `found` is
+    /// `None` if the library doesn't export an ABI version
+    IncompatibleAbi {
+        expected: String,
+        found: Option<String>,
+    },
+}
+
+impl StdError for PluginError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            PluginError::Library(ref err) => Some(err),
+            PluginError::IncompatibleAbi { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PluginError::Library(ref err) => write!(f, "{}", err),
+            PluginError::IncompatibleAbi {
+                ref expected,
+                found: Some(ref found),
+            } => write!(
+                f,
+                "provider was built for ABI version {}, the host requires version {}",
+                found, expected
+            ),
+            PluginError::IncompatibleAbi {
+                ref expected,
+                found: None,
+            } => write!(
+                f,
+                "provider does not export an ABI version, the host requires version {}",
+                expected
+            ),
+        }
+    }
 }
 
 impl Error {
@@ -46,6 +97,7 @@ impl StdError for Error {
             ErrorKind::Plugin(_) => "Plugin error",
             ErrorKind::Middleware(_) => "Middleware error",
             ErrorKind::Serialization(_) => "Serialization failure",
+            ErrorKind::Invocation { .. } => "Invocation failure",
         }
     }
 
@@ -61,6 +113,7 @@ impl StdError for Error {
             ErrorKind::Plugin(ref err) => Some(err),
             ErrorKind::Middleware(_) => None,
             ErrorKind::Serialization(_) => None,
+            ErrorKind::Invocation { .. } => None,
         }
     }
 }
@@ -84,13 +137,22 @@ impl fmt::Display for Error {
             ErrorKind::Plugin(ref err) => write!(f, "Plugin error: {}", err),
             ErrorKind::Middleware(ref err) => write!(f, "Middleware error: {}", err),
             ErrorKind::Serialization(ref err) => write!(f, "Serialization failure: {}", err),
+            ErrorKind::Invocation {
+                ref invocation_id,
+                ref code,
+                ref reason,
+            } => write!(
+                f,
+                "Invocation {} failed ({:?}): {}",
+                invocation_id, code, reason
+            ),
         }
     }
 }
 
 impl From<libloading::Error> for Error {
     fn from(source: libloading::Error) -> Error {
-        Error(Box::new(ErrorKind::Plugin(source)))
+        Error(Box::new(ErrorKind::Plugin(PluginError::Library(source))))
     }
 }
 impl From<wascap::Error> for Error {
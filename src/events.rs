@@ -0,0 +1,168 @@
+// Host lifecycle events. Every event is delivered to the local subscribers obtained through
+// `Host::events`, and in lattice mode the events that have a lattice counterpart are also
+// published on the lattice event subject
+
+use crate::{InvocationErrorCode, WasccEntity};
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel as channel;
+use std::sync::RwLock;
+
+/// An event describing a change in the state of a host runtime
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostEvent {
+    /// The host has stopped all of its actors and providers
+    HostStopped,
+    /// An actor was refused because it isn't signed by one of the host's trusted issuers, or
+    /// because its token has been revoked
+    ActorRejected {
+        actor: String,
+        issuer: String,
+        reason: String,
+    },
+    /// An actor instance has begun loading
+    ActorStarting { actor: String },
+    /// An actor instance has started and is ready to receive invocations
+    ActorStarted { actor: String },
+    /// A running actor's token has been revoked, its instances are about to be terminated
+    ActorRevoked { actor: String },
+    /// The last instance of an actor has stopped
+    ActorStopped { actor: String },
+    /// An actor instance failed and is about to be restarted
+    ActorRestarting { actor: String, reason: String },
+    /// A failed actor instance has been restarted
+    ActorRestarted { actor: String },
+    /// A capability provider has been loaded
+    ProviderLoaded { capid: String, binding: String },
+    /// A capability provider has been removed
+    ProviderRemoved { capid: String, binding: String },
+    /// A running capability provider has been replaced by a new build, keeping its bindings
+    ProviderReplaced { capid: String, binding: String },
+    /// The module of a running actor is being replaced
+    ActorUpdating { actor: String },
+    /// The replacement of an actor's module has finished, successfully or not
+    ActorUpdateComplete { actor: String, success: bool },
+    /// An actor has been bound to a capability provider
+    ActorBindingCreated {
+        actor: String,
+        capid: String,
+        binding: String,
+    },
+    /// The binding between an actor and a capability provider has been removed
+    ActorBindingRemoved {
+        actor: String,
+        capid: String,
+        binding: String,
+    },
+    /// An invocation made through this host failed
+    InvocationFailed {
+        invocation_id: String,
+        origin: WasccEntity,
+        target: WasccEntity,
+        operation: String,
+        code: InvocationErrorCode,
+        reason: String,
+    },
+}
+
+impl HostEvent {
+    /// The equivalent lattice event, if there is one
+    #[cfg(feature = "lattice")]
+    pub(crate) fn to_bus_event(&self, host: &str) -> Option<latticeclient::BusEvent> {
+        use latticeclient::BusEvent;
+        let host = host.to_string();
+        let be = match self.clone() {
+            HostEvent::HostStopped => BusEvent::HostStopped(host),
+            HostEvent::ActorStarting { actor } => BusEvent::ActorStarting { actor, host },
+            HostEvent::ActorStarted { actor } => BusEvent::ActorStarted { actor, host },
+            HostEvent::ActorStopped { actor } => BusEvent::ActorStopped { actor, host },
+            HostEvent::ActorRestarting { actor, .. } => {
+                BusEvent::ActorBecameUnhealthy { actor, host }
+            }
+            HostEvent::ActorRestarted { actor } => BusEvent::ActorBecameHealthy { actor, host },
+            HostEvent::ActorUpdating { actor } => BusEvent::ActorUpdating { actor, host },
+            HostEvent::ActorUpdateComplete { actor, success } => BusEvent::ActorUpdateComplete {
+                actor,
+                success,
+                host,
+            },
+            HostEvent::ProviderLoaded { capid, binding } => BusEvent::ProviderLoaded {
+                capid,
+                instance_name: binding,
+                host,
+            },
+            HostEvent::ProviderRemoved { capid, binding } => BusEvent::ProviderRemoved {
+                capid,
+                instance_name: binding,
+                host,
+            },
+            HostEvent::ActorBindingCreated {
+                actor,
+                capid,
+                binding,
+            } => BusEvent::ActorBindingCreated {
+                host,
+                actor,
+                capid,
+                instance_name: binding,
+            },
+            HostEvent::ActorBindingRemoved {
+                actor,
+                capid,
+                binding,
+            } => BusEvent::ActorBindingRemoved {
+                host,
+                actor,
+                capid,
+                instance_name: binding,
+            },
+            HostEvent::InvocationFailed { .. }
+            | HostEvent::ActorRejected { .. }
+            | HostEvent::ActorRevoked { .. }
+            | HostEvent::ProviderReplaced { .. } => return None,
+        };
+        Some(be)
+    }
+}
+
+/// Fans host events out to every local subscriber. Subscribers that have dropped their
+/// receiver are forgotten the next time an event is published
+#[derive(Default)]
+pub(crate) struct EventBroker {
+    subscribers: RwLock<Vec<Sender<HostEvent>>>,
+}
+
+impl EventBroker {
+    pub(crate) fn new() -> EventBroker {
+        EventBroker::default()
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<HostEvent> {
+        let (s, r) = channel::unbounded();
+        self.subscribers.write().unwrap().push(s);
+        r
+    }
+
+    pub(crate) fn publish(&self, event: HostEvent) {
+        let mut lock = self.subscribers.write().unwrap();
+        if lock.is_empty() {
+            return;
+        }
+        lock.retain(|s| s.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventBroker, HostEvent};
+
+    #[test]
+    fn events_reach_live_subscribers() {
+        let broker = EventBroker::new();
+        let first = broker.subscribe();
+        let second = broker.subscribe();
+        drop(second);
+        broker.publish(HostEvent::HostStopped);
+        assert_eq!(HostEvent::HostStopped, first.try_recv().unwrap());
+        assert_eq!(1, broker.subscribers.read().unwrap().len());
+    }
+}
@@ -5,25 +5,36 @@ use crate::Result;
 use data_encoding::HEXUPPER;
 use ring::digest::{Context, Digest, SHA256};
 
+use crate::aliases::{self, CallAliases};
+use crate::authz::SecurityContext;
 use crate::bus;
 use crate::bus::MessageBus;
 use crate::BindingsList;
-use crate::{authz, errors, Actor, Authorizer, NativeCapability, RouteKey};
+use crate::{
+    authz, errors, ActorSource, CanaryOptions, NativeCapability, ResourceLimits, RestartPolicy,
+    RouteKey,
+};
+use crossbeam::{Receiver, Sender};
+use crossbeam_channel as channel;
 use errors::ErrorKind;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Read,
-    sync::{Arc, RwLock},
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 use uuid::Uuid;
 use wapc::WapcHost;
 use wascap::{jwt::Claims, prelude::KeyPair};
 use wascc_codec::{
     capabilities::{CapabilityDescriptor, OP_GET_CAPABILITY_DESCRIPTOR},
-    core::{CapabilityConfiguration, OP_PERFORM_LIVE_UPDATE, OP_REMOVE_ACTOR},
+    core::{CapabilityConfiguration, OP_REMOVE_ACTOR},
     deserialize, serialize, SYSTEM_ACTOR,
 };
 
+const TIMEOUT_ERROR: &str = "Invocation timed out";
+
 pub(crate) const CORELABEL_ARCH: &str = "hostcore.arch";
 pub(crate) const CORELABEL_OS: &str = "hostcore.os";
 pub(crate) const CORELABEL_OSFAMILY: &str = "hostcore.osfamily";
@@ -69,7 +80,7 @@ impl Host {
     }
 
     pub(crate) fn ensure_extras(&self) -> Result<()> {
-        self.add_native_capability(NativeCapability::from_instance(
+        self.load_native_capability(NativeCapability::from_instance(
             crate::extras::ExtrasCapabilityProvider::default(),
             None,
         )?)?;
@@ -92,46 +103,6 @@ pub(crate) fn remove_cap(
     caps.write().unwrap().remove(&RouteKey::new(binding, capid));
 }
 
-/// Puts a "live update" message into the dispatch queue, which will be handled
-/// as soon as it is pulled off the channel for the target actor
-pub(crate) fn replace_actor(
-    hostkey: &KeyPair,
-    bus: Arc<MessageBus>,
-    new_actor: Actor,
-) -> Result<()> {
-    let public_key = new_actor.token.claims.subject;
-    let tgt_subject = bus.actor_subject(&public_key);
-    let inv = gen_liveupdate_invocation(hostkey, &public_key, new_actor.bytes);
-
-    match bus.invoke(&tgt_subject, inv) {
-        Ok(_) => {
-            info!("Actor {} replaced", public_key);
-            Ok(())
-        }
-        Err(e) => Err(e),
-    }
-}
-
-pub(crate) fn live_update(guest: &mut WapcHost, inv: &Invocation) -> InvocationResponse {
-    match guest.replace_module(&inv.msg) {
-        Ok(_) => InvocationResponse::success(inv, vec![]),
-        Err(e) => {
-            error!("Failed to perform hot swap, ignoring message: {}", e);
-            InvocationResponse::error(inv, "Failed to perform hot swap")
-        }
-    }
-}
-
-fn gen_liveupdate_invocation(hostkey: &KeyPair, target: &str, bytes: Vec<u8>) -> Invocation {
-    Invocation::new(
-        hostkey,
-        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
-        WasccEntity::Actor(target.to_string()),
-        OP_PERFORM_LIVE_UPDATE,
-        bytes,
-    )
-}
-
 /// Removes all bindings for a given actor by sending the "remove actor" message
 /// to each of the capabilities
 pub(crate) fn deconfigure_actor(
@@ -193,6 +164,401 @@ pub(crate) fn remove_binding(
     lock.remove(&(actor.to_string(), capid.to_string(), binding.to_string()));
 }
 
+/// Bookkeeping for the instances of a single actor running in this host. Every instance
+/// selects on the same terminator channel, so each `true` sent to it stops exactly one instance
+#[derive(Clone)]
+pub(crate) struct ActorInstances {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) limits: ResourceLimits,
+    pub(crate) policy: RestartPolicy,
+    pub(crate) count: usize,
+    pub(crate) term_s: Sender<bool>,
+    pub(crate) term_r: Receiver<bool>,
+    pub(crate) gate: Arc<ActorGate>,
+}
+
+impl ActorInstances {
+    pub(crate) fn new(
+        claims: Claims<wascap::jwt::Actor>,
+        bytes: Vec<u8>,
+        timeout: Option<Duration>,
+        limits: ResourceLimits,
+        policy: RestartPolicy,
+    ) -> ActorInstances {
+        let (term_s, term_r) = channel::unbounded();
+        ActorInstances {
+            timeout,
+            limits,
+            policy,
+            count: 0,
+            term_s,
+            term_r,
+            gate: Arc::new(ActorGate::new(claims, bytes)),
+        }
+    }
+}
+
+/// Whether an actor's instances run the invocations they receive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ActorMode {
+    Running,
+    // Invocations are held by the instances until the actor is resumed
+    Paused,
+    // New invocations are refused while the held ones are run, before the actor is removed
+    Draining,
+}
+
+impl Default for ActorMode {
+    fn default() -> Self {
+        ActorMode::Running
+    }
+}
+
+/// The module run by the instances of an actor. Every module staged for an actor gets
+/// a new revision number, which the instances compare against the one they are running
+pub(crate) struct ActorModule {
+    pub(crate) claims: Claims<wascap::jwt::Actor>,
+    // The module as supplied, before any resource limits are applied to it
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) revision: u64,
+}
+
+/// The state shared by all instances of an actor: whether they admit invocations, how many
+/// invocations they are holding, and the modules (current and canary) they should be running. Instances register a
+/// waker so that they notice changes while waiting for work, and report back on each module
+/// they are asked to load
+pub(crate) struct ActorGate {
+    mode: RwLock<ActorMode>,
+    held: AtomicUsize,
+    module: RwLock<Arc<ActorModule>>,
+    canary: RwLock<Option<Arc<Canary>>>,
+    revisions: AtomicU64,
+    wakers: RwLock<Vec<Sender<()>>>,
+    swapping: Mutex<()>,
+    reports_s: Sender<(u64, std::result::Result<(), String>)>,
+    reports_r: Receiver<(u64, std::result::Result<(), String>)>,
+}
+
+impl ActorGate {
+    pub(crate) fn new(claims: Claims<wascap::jwt::Actor>, bytes: Vec<u8>) -> ActorGate {
+        let (reports_s, reports_r) = channel::unbounded();
+        ActorGate {
+            mode: RwLock::new(ActorMode::default()),
+            held: AtomicUsize::new(0),
+            module: RwLock::new(Arc::new(ActorModule {
+                claims,
+                bytes,
+                revision: 0,
+            })),
+            canary: RwLock::new(None),
+            revisions: AtomicU64::new(0),
+            wakers: RwLock::new(vec![]),
+            swapping: Mutex::new(()),
+            reports_s,
+            reports_r,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> ActorMode {
+        *self.mode.read().unwrap()
+    }
+
+    pub(crate) fn set_mode(&self, mode: ActorMode) {
+        *self.mode.write().unwrap() = mode;
+        self.wake();
+    }
+
+    pub(crate) fn held(&self) -> usize {
+        self.held.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn hold(&self) {
+        self.held.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn release(&self) {
+        self.held.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn module(&self) -> Arc<ActorModule> {
+        self.module.read().unwrap().clone()
+    }
+
+    pub(crate) fn revision(&self) -> u64 {
+        self.module.read().unwrap().revision
+    }
+
+    /// Registers an instance, returning the channel on which it is woken up
+    pub(crate) fn waker(&self) -> Receiver<()> {
+        let (s, r) = channel::bounded(1);
+        self.wakers.write().unwrap().push(s);
+        r
+    }
+
+    fn wake(&self) {
+        // A full channel already has a wake up pending, only a dropped one is forgotten
+        self.wakers
+            .write()
+            .unwrap()
+            .retain(|s| !matches!(s.try_send(()), Err(channel::TrySendError::Disconnected(_))));
+    }
+
+    /// Called by an instance once it has tried to load the given revision of the module
+    pub(crate) fn report(&self, revision: u64, outcome: std::result::Result<(), String>) {
+        let _ = self.reports_s.send((revision, outcome));
+    }
+
+    /// Asks the instances to load a new module, then waits for `instances` of them to report
+    /// back, failing on the first instance that could not load it
+    pub(crate) fn swap(
+        &self,
+        claims: Claims<wascap::jwt::Actor>,
+        bytes: Vec<u8>,
+        instances: usize,
+        timeout: Duration,
+    ) -> std::result::Result<(), String> {
+        let _lock = self.swapping.lock().unwrap();
+        let module = self.next_module(claims, bytes);
+        let revision = module.revision;
+        *self.module.write().unwrap() = module;
+        self.wake();
+        self.await_reports(revision, instances, timeout)
+    }
+
+    pub(crate) fn canary(&self) -> Option<Arc<Canary>> {
+        self.canary.read().unwrap().clone()
+    }
+
+    /// Asks the instances to load a canary module alongside their current one, then waits
+    /// for `instances` of them to report back. The canary is withdrawn if any of them fails
+    pub(crate) fn start_canary(
+        &self,
+        claims: Claims<wascap::jwt::Actor>,
+        bytes: Vec<u8>,
+        source: ActorSource,
+        options: &CanaryOptions,
+        instances: usize,
+    ) -> std::result::Result<Arc<Canary>, String> {
+        let _lock = self.swapping.lock().unwrap();
+        let canary = {
+            let mut lock = self.canary.write().unwrap();
+            if lock.is_some() {
+                return Err("A canary is already running".to_string());
+            }
+            let canary = Arc::new(Canary::new(
+                self.next_module(claims, bytes),
+                source,
+                options.weight,
+                options.max_error_rate,
+            ));
+            *lock = Some(canary.clone());
+            canary
+        };
+        self.wake();
+        match self.await_reports(canary.module.revision, instances, options.timeout) {
+            Ok(()) => Ok(canary),
+            Err(e) => {
+                self.clear_canary(&canary);
+                Err(e)
+            }
+        }
+    }
+
+    /// Withdraws the given canary, returning `false` if it was no longer running
+    pub(crate) fn clear_canary(&self, canary: &Arc<Canary>) -> bool {
+        let mut lock = self.canary.write().unwrap();
+        match lock.as_ref() {
+            Some(c) if Arc::ptr_eq(c, canary) => {
+                *lock = None;
+                drop(lock);
+                self.wake();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn next_module(&self, claims: Claims<wascap::jwt::Actor>, bytes: Vec<u8>) -> Arc<ActorModule> {
+        Arc::new(ActorModule {
+            claims,
+            bytes,
+            revision: self.revisions.fetch_add(1, Ordering::SeqCst) + 1,
+        })
+    }
+
+    fn await_reports(
+        &self,
+        revision: u64,
+        instances: usize,
+        timeout: Duration,
+    ) -> std::result::Result<(), String> {
+        let deadline = std::time::Instant::now() + timeout;
+        let mut loaded = 0;
+        while loaded < instances {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            match self.reports_r.recv_timeout(remaining) {
+                Ok((r, outcome)) if r == revision => {
+                    outcome?;
+                    loaded += 1;
+                }
+                Ok(_) => {} // a report on an earlier revision
+                Err(_) => {
+                    return Err(format!(
+                        "{} of {} instances did not load the module within {:?}",
+                        instances - loaded,
+                        instances,
+                        timeout
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A revision of an actor's module that runs alongside the current one in every instance,
+/// receiving `weight` percent of the actor's invocations. The outcome of the invocations
+/// handled by both modules is counted so that they can be compared
+pub(crate) struct Canary {
+    pub(crate) module: Arc<ActorModule>,
+    pub(crate) source: ActorSource,
+    weight: AtomicU8,
+    routed: AtomicU64,
+    // The error rate above which the canary is withdrawn, once it has handled enough calls
+    max_error_rate: Option<(f64, u64)>,
+    // Invocations (by ID) that run on the canary whatever its weight, such as a smoke test
+    trials: Mutex<HashSet<String>>,
+    pub(crate) canary: CallStats,
+    pub(crate) stable: CallStats,
+}
+
+#[derive(Default)]
+pub(crate) struct CallStats {
+    calls: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl CallStats {
+    pub(crate) fn calls(&self) -> u64 {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn errors(&self) -> u64 {
+        self.errors.load(Ordering::SeqCst)
+    }
+}
+
+impl Canary {
+    fn new(
+        module: Arc<ActorModule>,
+        source: ActorSource,
+        weight: u8,
+        max_error_rate: Option<(f64, u64)>,
+    ) -> Canary {
+        Canary {
+            module,
+            source,
+            weight: AtomicU8::new(weight.min(100)),
+            routed: AtomicU64::new(0),
+            max_error_rate,
+            trials: Mutex::new(HashSet::new()),
+            canary: CallStats::default(),
+            stable: CallStats::default(),
+        }
+    }
+
+    pub(crate) fn weight(&self) -> u8 {
+        self.weight.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_weight(&self, weight: u8) {
+        self.weight.store(weight.min(100), Ordering::SeqCst);
+    }
+
+    /// Marks an invocation that must be handled by the canary
+    pub(crate) fn add_trial(&self, invocation_id: &str) {
+        self.trials
+            .lock()
+            .unwrap()
+            .insert(invocation_id.to_string());
+    }
+
+    /// Returns `true`, once, if the invocation was marked as a trial of the canary
+    pub(crate) fn take_trial(&self, invocation_id: &str) -> bool {
+        self.trials.lock().unwrap().remove(invocation_id)
+    }
+
+    /// Decides whether the next invocation goes to the canary. Trials always do; the other
+    /// invocations are spread evenly, so that `weight` out of every 100 consecutive invocations
+    /// are routed to it
+    pub(crate) fn route(&self, inv: &Invocation) -> bool {
+        if self.take_trial(&inv.id) {
+            return true;
+        }
+        let n = self.routed.fetch_add(1, Ordering::SeqCst) % 100;
+        let weight = u64::from(self.weight());
+        (n + 1) * weight / 100 != n * weight / 100
+    }
+
+    /// Counts the outcome of an invocation, returning `true` if the canary's error rate has
+    /// crossed its threshold
+    pub(crate) fn record(&self, canary: bool, failed: bool) -> bool {
+        let stats = if canary { &self.canary } else { &self.stable };
+        let calls = stats.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let errors = if failed {
+            stats.errors.fetch_add(1, Ordering::SeqCst) + 1
+        } else {
+            stats.errors()
+        };
+        match self.max_error_rate {
+            Some((rate, min_calls)) if canary && calls >= min_calls => {
+                errors as f64 / calls as f64 > rate
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Signals `count` of the instances subscribed to the given actor subject to terminate
+pub(crate) fn stop_actor_instances(
+    terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
+    subject: &str,
+    count: usize,
+) -> Result<()> {
+    match terminators.read().unwrap().get(subject) {
+        Some(t) => {
+            for _ in 0..count {
+                t.send(true).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+        None => Err(errors::new(ErrorKind::MiscHost(format!(
+            "No running actor instances for {}",
+            subject
+        )))),
+    }
+}
+
+/// Records that one instance of an actor has stopped. Returns `true` if it was the
+/// last running instance, in which case the actor's record is removed
+pub(crate) fn release_actor_instance(
+    instances: Arc<RwLock<HashMap<String, ActorInstances>>>,
+    actor: &str,
+) -> bool {
+    let mut lock = instances.write().unwrap();
+    let last = match lock.get_mut(actor) {
+        Some(rec) => {
+            rec.count = rec.count.saturating_sub(1);
+            rec.count == 0
+        }
+        None => true,
+    };
+    if last {
+        lock.remove(actor);
+    }
+    last
+}
+
 pub(crate) fn gen_remove_actor(
     hostkey: &KeyPair,
     msg: Vec<u8>,
@@ -222,6 +588,10 @@ pub struct Invocation {
     pub id: String,
     pub encoded_claims: String,
     pub host_id: String,
+    // The maximum amount of time the target may spend processing this invocation, set with
+    // `with_timeout`
+    #[cfg_attr(feature = "lattice", serde(default))]
+    pub(crate) timeout: Option<Duration>,
 }
 
 /// Represents an invocation target - either an actor or a bound capability provider
@@ -272,9 +642,25 @@ impl Invocation {
             id: subject,
             encoded_claims: claims.encode(&hostkey).unwrap(),
             host_id: issuer.to_string(),
+            timeout: None,
+        }
+    }
+
+    /// Sets the execution deadline for this invocation, relative to when the target starts
+    /// processing it
+    pub fn with_timeout(self, timeout: Duration) -> Invocation {
+        Invocation {
+            timeout: Some(timeout),
+            ..self
         }
     }
 
+    /// The maximum amount of time the target may spend processing this invocation. When
+    /// the target is an actor with its own timeout, the shorter of the two applies
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
     pub fn origin_url(&self) -> String {
         self.origin.url()
     }
@@ -336,6 +722,37 @@ impl Invocation {
     }
 }
 
+/// A machine-readable classification of why an invocation failed, set alongside the
+/// error message of an `InvocationResponse`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "lattice", derive(serde::Serialize, serde::Deserialize))]
+pub enum InvocationErrorCode {
+    /// The target of the invocation does not exist
+    NotFound,
+    /// The origin of the invocation is not allowed to make it
+    Unauthorized,
+    /// A capability provider was asked to perform an operation for an actor that isn't bound to it
+    Unbound,
+    /// The invocation did not complete within its deadline
+    Timeout,
+    /// The guest module trapped while handling the invocation
+    GuestTrap,
+    /// The guest module reported an error or failed while handling the invocation
+    GuestFailure,
+    /// The capability provider failed to handle the invocation
+    ProviderFailure,
+    /// The invocation did not pass the anti-forgery check
+    AntiforgeryFailure,
+    /// A middleware failed while processing the invocation
+    MiddlewareFailure,
+    /// The target stopped before it could respond
+    Unavailable,
+    /// The host could not process the invocation
+    HostFailure,
+    /// The failure has not been classified
+    Other,
+}
+
 /// The response to an invocation
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "lattice", derive(serde::Serialize, serde::Deserialize))]
@@ -343,6 +760,8 @@ pub struct InvocationResponse {
     pub msg: Vec<u8>,
     pub error: Option<String>,
     pub invocation_id: String,
+    #[cfg_attr(feature = "lattice", serde(default))]
+    pub error_code: Option<InvocationErrorCode>,
 }
 
 impl InvocationResponse {
@@ -351,16 +770,53 @@ impl InvocationResponse {
             msg,
             error: None,
             invocation_id: inv.id.to_string(),
+            error_code: None,
         }
     }
 
+    /// Creates an error response without classifying the failure
     pub fn error(inv: &Invocation, err: &str) -> InvocationResponse {
+        InvocationResponse::error_with_code(inv, InvocationErrorCode::Other, err)
+    }
+
+    /// Creates an error response with the given error code
+    pub fn error_with_code(
+        inv: &Invocation,
+        code: InvocationErrorCode,
+        err: &str,
+    ) -> InvocationResponse {
         InvocationResponse {
             msg: Vec::new(),
             error: Some(err.to_string()),
             invocation_id: inv.id.to_string(),
+            error_code: Some(code),
         }
     }
+
+    /// Creates the response for an invocation that did not complete within its deadline
+    pub fn timeout(inv: &Invocation, timeout: Duration) -> InvocationResponse {
+        InvocationResponse::error_with_code(
+            inv,
+            InvocationErrorCode::Timeout,
+            &format!("{} after {}ms", TIMEOUT_ERROR, timeout.as_millis()),
+        )
+    }
+
+    /// Indicates whether this response represents an invocation that exceeded its deadline
+    pub fn is_timeout(&self) -> bool {
+        self.error_code == Some(InvocationErrorCode::Timeout)
+    }
+}
+
+/// Returns the shorter of an actor's configured timeout and the invocation's own timeout
+pub(crate) fn effective_timeout(
+    actor: Option<Duration>,
+    invocation: Option<Duration>,
+) -> Option<Duration> {
+    match (actor, invocation) {
+        (Some(a), Some(i)) => Some(a.min(i)),
+        (a, i) => a.or(i),
+    }
 }
 
 pub(crate) fn wapc_host_callback(
@@ -371,7 +827,7 @@ pub(crate) fn wapc_host_callback(
     namespace: &str,
     operation: &str,
     payload: &[u8],
-    authorizer: Arc<RwLock<Box<dyn Authorizer>>>,
+    security: &SecurityContext,
 ) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     trace!(
         "Guest {} invoking {}:{}",
@@ -380,7 +836,6 @@ pub(crate) fn wapc_host_callback(
         operation
     );
 
-    let capability_id = namespace;
     let inv = invocation_from_callback(
         &hostkey,
         &claims.subject,
@@ -388,10 +843,54 @@ pub(crate) fn wapc_host_callback(
         namespace,
         operation,
         payload,
+        &security.aliases.read().unwrap(),
     );
+    // Calls to other actors need the caller's claims to attest to the target, and are further
+    // governed by the host's actor call policy. A call made through an alias is authorized
+    // against the actor the alias resolves to
+    let (capability_id, permitted) = match &inv.target {
+        WasccEntity::Actor(pk) => (
+            pk.as_str(),
+            authz::can_invoke(&claims, pk, operation)
+                && security.call_policy.read().unwrap().permits(&claims, pk),
+        ),
+        WasccEntity::Capability { .. } => (
+            namespace,
+            authz::can_invoke(&claims, namespace, operation)
+                && security
+                    .operations
+                    .read()
+                    .unwrap()
+                    .permits(&claims, namespace, operation),
+        ),
+    };
+    // Operations the provider doesn't support never reach it
+    if let WasccEntity::Capability { capid, binding } = &inv.target {
+        let supported = match security
+            .descriptors
+            .read()
+            .unwrap()
+            .get(&RouteKey::new(binding, capid))
+        {
+            Some(d) => authz::supports_operation(d, operation),
+            None => true,
+        };
+        if !supported {
+            return Err(denied(
+                &bus,
+                &inv,
+                format!(
+                    "Actor {} attempted to call {} on {},{} - the provider does not support this operation",
+                    claims.subject, operation, capid, binding
+                ),
+            ));
+        }
+    }
 
-    if !authz::can_invoke(&claims, capability_id, operation) {
-        return Err(Box::new(errors::new(errors::ErrorKind::Authorization(
+    if !permitted {
+        return Err(denied(
+            &bus,
+            &inv,
             format!(
                 "{} {} attempted to call {} on {},{} - PERMISSION DENIED.",
                 if claims.metadata.unwrap().provider {
@@ -404,14 +903,17 @@ pub(crate) fn wapc_host_callback(
                 capability_id,
                 binding
             ),
-        ))));
+        ));
     } else {
-        if !authorizer
+        if !security
+            .authorizer
             .read()
             .unwrap()
             .can_invoke(&claims, &inv.target, operation)
         {
-            return Err(Box::new(errors::new(errors::ErrorKind::Authorization(
+            return Err(denied(
+                &bus,
+                &inv,
                 format!(
                     "{} {} attempted to call {:?} - Authorizer denied access",
                     if claims.metadata.unwrap().provider {
@@ -422,7 +924,7 @@ pub(crate) fn wapc_host_callback(
                     claims.subject,
                     &inv.target
                 ),
-            ))));
+            ));
         }
     }
     // Make a request on either `wasmbus.Mxxxxx` for an actor or `wasmbus.{capid}.{binding}.{calling-actor}` for
@@ -441,6 +943,27 @@ pub(crate) fn wapc_host_callback(
     }
 }
 
+// Refuses a guest's host call, reporting it as a failed invocation with the `Unauthorized` code
+fn denied(
+    bus: &MessageBus,
+    inv: &Invocation,
+    reason: String,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    bus.emit(crate::HostEvent::InvocationFailed {
+        invocation_id: inv.id.to_string(),
+        origin: inv.origin.clone(),
+        target: inv.target.clone(),
+        operation: inv.operation.to_string(),
+        code: InvocationErrorCode::Unauthorized,
+        reason: reason.to_string(),
+    });
+    Box::new(bus::invocation_error(
+        inv,
+        InvocationErrorCode::Unauthorized,
+        &reason,
+    ))
+}
+
 fn invocation_from_callback(
     hostkey: &KeyPair,
     origin: &str,
@@ -448,6 +971,7 @@ fn invocation_from_callback(
     ns: &str,
     op: &str,
     payload: &[u8],
+    aliases: &CallAliases,
 ) -> Invocation {
     let binding = if bd.trim().is_empty() {
         // Some actor SDKs may not specify a binding field by default
@@ -455,8 +979,10 @@ fn invocation_from_callback(
     } else {
         bd.to_string()
     };
-    let target = if ns.len() == 56 && ns.starts_with("M") {
+    let target = if aliases::is_actor_key(ns) {
         WasccEntity::Actor(ns.to_string())
+    } else if let Some(pk) = aliases.resolve(ns) {
+        WasccEntity::Actor(pk)
     } else {
         WasccEntity::Capability {
             binding,
@@ -611,4 +1137,93 @@ mod test {
             "wasmbus://wascc/messaging/default/OP_TESTING"
         );
     }
+
+    #[test]
+    fn callback_namespace_resolves_aliases() {
+        use super::invocation_from_callback;
+        use crate::aliases::CallAliases;
+
+        let hostkey = KeyPair::new_server();
+        let target = KeyPair::new_module().public_key();
+        let mut aliases = CallAliases::new();
+        aliases.register("echo", &target).unwrap();
+
+        let call = |ns: &str| {
+            invocation_from_callback(&hostkey, "Mcaller", "", ns, "Op", &[], &aliases).target
+        };
+        assert_eq!(WasccEntity::Actor(target.to_string()), call("echo"));
+        assert_eq!(WasccEntity::Actor(target.to_string()), call(&target));
+        assert_eq!(
+            WasccEntity::Capability {
+                capid: "wascc:keyvalue".to_string(),
+                binding: "default".to_string()
+            },
+            call("wascc:keyvalue")
+        );
+    }
+
+    #[test]
+    fn canary_routing_and_threshold() {
+        use super::{ActorModule, ActorSource, Canary, Invocation, WasccEntity};
+        use std::sync::Arc;
+        use wascap::jwt::{Actor, ClaimsBuilder};
+        use wascap::prelude::KeyPair;
+
+        let module = Arc::new(ActorModule {
+            claims: ClaimsBuilder::<Actor>::new().build(),
+            bytes: vec![],
+            revision: 1,
+        });
+        let (key, actor) = (KeyPair::new_server(), KeyPair::new_module().public_key());
+        let inv = || {
+            Invocation::new(
+                &key,
+                WasccEntity::Actor("system".to_string()),
+                WasccEntity::Actor(actor.to_string()),
+                "Op",
+                vec![],
+            )
+        };
+        let canary = Canary::new(module, ActorSource::Slice, 25, Some((0.5, 4)));
+        assert_eq!(25, (0..100).filter(|_| canary.route(&inv())).count());
+        canary.set_weight(0);
+        assert!(!(0..100).any(|_| canary.route(&inv())));
+
+        // A trial goes to the canary once, whatever its weight
+        let trial = inv();
+        canary.add_trial(&trial.id);
+        assert!(canary.route(&trial));
+        assert!(!canary.route(&trial));
+
+        assert!(!canary.record(false, true));
+        assert!(!canary.record(true, true));
+        assert!(!canary.record(true, false));
+        assert!(!canary.record(true, true));
+        assert!(canary.record(true, true));
+        assert_eq!((4, 3), (canary.canary.calls(), canary.canary.errors()));
+        assert_eq!((1, 1), (canary.stable.calls(), canary.stable.errors()));
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn response_error_code_roundtrip() {
+        use super::{InvocationErrorCode, InvocationResponse};
+        use wascc_codec::{deserialize, serialize};
+
+        let inv = Invocation::new(
+            &KeyPair::new_server(),
+            WasccEntity::Actor("testing".into()),
+            WasccEntity::Actor("target".into()),
+            "OP_TESTING",
+            vec![],
+        );
+        let resp = InvocationResponse::error_with_code(
+            &inv,
+            InvocationErrorCode::Unbound,
+            "Attempted to invoke binding-required operation on unbound provider",
+        );
+        let resp: InvocationResponse = deserialize(&serialize(resp).unwrap()).unwrap();
+        assert_eq!(Some(InvocationErrorCode::Unbound), resp.error_code);
+        assert!(!resp.is_timeout());
+    }
 }
@@ -0,0 +1,586 @@
+// Out-of-process native capability providers. The library of an isolated provider is loaded by
+// a child process (the `wascc-provider-runner` binary) rather than by the host, so that a crash
+// in the provider can't take the host's actors down with it. The host and the child exchange
+// the calls of the `CapabilityProvider` and `Dispatcher` traits over a Unix socket, and the host
+// restarts the child when it exits, replaying the actor bindings the provider had.
+//
+// Every frame on the socket is a little-endian u32 length followed by the frame itself: a tag,
+// a u64 request ID and the length-prefixed fields. Requests travel both ways (calls from the
+// host, dispatches from the provider) and each side answers the other's requests by ID
+
+use crate::errors::{self, ErrorKind};
+use crate::supervisor::RestartTracker;
+use crate::{NativeCapability, RestartPolicy, Result};
+use crossbeam::Sender;
+use crossbeam_channel as channel;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use wascc_codec::{
+    capabilities::{CapabilityProvider, Dispatcher},
+    core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR},
+    deserialize,
+};
+
+const RUNNER_ENV: &str = "WASCC_PROVIDER_RUNNER";
+const RUNNER_NAME: &str = "wascc-provider-runner";
+// How long the host waits for a new child process to connect
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_POLL: Duration = Duration::from_millis(10);
+
+const REQUEST: u8 = 0;
+const RESPONSE_OK: u8 = 1;
+const RESPONSE_ERR: u8 = 2;
+
+type CallResult = std::result::Result<Vec<u8>, String>;
+type Handler = Arc<dyn Fn(&str, &str, &[u8]) -> CallResult + Send + Sync>;
+
+/// Options for running a native capability provider in a child process. See
+/// `NativeCapability::from_file_isolated`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessIsolation {
+    runner: PathBuf,
+    restart_policy: RestartPolicy,
+}
+
+impl Default for ProcessIsolation {
+    fn default() -> Self {
+        let runner = std::env::var_os(RUNNER_ENV)
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::current_exe().ok().and_then(|exe| {
+                    exe.parent().map(|dir| {
+                        dir.join(format!("{}{}", RUNNER_NAME, std::env::consts::EXE_SUFFIX))
+                    })
+                })
+            })
+            .unwrap_or_else(|| PathBuf::from(RUNNER_NAME));
+        ProcessIsolation {
+            runner,
+            restart_policy: RestartPolicy::default(),
+        }
+    }
+}
+
+impl ProcessIsolation {
+    /// Runs providers with the `wascc-provider-runner` binary named by the `WASCC_PROVIDER_RUNNER`
+    /// environment variable or, if it isn't set, found next to the current executable
+    pub fn new() -> ProcessIsolation {
+        ProcessIsolation::default()
+    }
+
+    /// Sets the path of the provider runner binary
+    pub fn with_runner(self, runner: impl AsRef<Path>) -> ProcessIsolation {
+        ProcessIsolation {
+            runner: runner.as_ref().to_path_buf(),
+            ..self
+        }
+    }
+
+    /// Sets how often the child process is restarted after it exits. The trap settings of the
+    /// policy don't apply to providers
+    pub fn with_restart_policy(self, restart_policy: RestartPolicy) -> ProcessIsolation {
+        ProcessIsolation {
+            restart_policy,
+            ..self
+        }
+    }
+}
+
+/// Runs the provider library named on the command line, serving the host connected to the
+/// socket named after it. This is the entry point of the `wascc-provider-runner` binary
+#[doc(hidden)]
+pub fn serve_provider() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (library, socket) = match (args.next(), args.next()) {
+        (Some(l), Some(s)) => (l, s),
+        _ => {
+            return Err(errors::new(ErrorKind::MiscHost(format!(
+                "Usage: {} <provider library> <socket>",
+                RUNNER_NAME
+            ))))
+        }
+    };
+    let capability = NativeCapability::from_file(&library, None)?;
+    let stream = UnixStream::connect(&socket)?;
+    let link = Link::new(Box::new(stream.try_clone()?));
+    capability
+        .plugin
+        .configure_dispatch(Box::new(LinkDispatcher { link: link.clone() }))?;
+    let plugin = Arc::new(capability.plugin);
+    serve(
+        link,
+        stream,
+        Arc::new(move |actor: &str, op: &str, msg: &[u8]| {
+            plugin
+                .handle_call(actor, op, msg)
+                .map_err(|e| e.to_string())
+        }),
+    );
+    Ok(())
+}
+
+// A provider whose library runs in a child process. Dropping it stops the child
+pub(crate) struct IsolatedProvider {
+    supervisor: Arc<Supervisor>,
+}
+
+impl IsolatedProvider {
+    pub(crate) fn start(library: &Path, isolation: ProcessIsolation) -> Result<IsolatedProvider> {
+        let supervisor = Arc::new(Supervisor {
+            library: library.to_path_buf(),
+            runner: isolation.runner,
+            tracker: Mutex::new(RestartTracker::new(isolation.restart_policy)),
+            dispatcher: Arc::new(RwLock::new(None)),
+            bindings: Mutex::new(HashMap::new()),
+            process: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+        });
+        supervisor.spawn()?;
+        Ok(IsolatedProvider { supervisor })
+    }
+}
+
+impl CapabilityProvider for IsolatedProvider {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        *self.supervisor.dispatcher.write().unwrap() = Some(dispatcher);
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        let res = self.supervisor.call(actor, op, msg)?;
+        // Remember the bindings so that they can be re-established in a new child process
+        if op == OP_BIND_ACTOR || op == OP_REMOVE_ACTOR {
+            if let Ok(cfg) = deserialize::<CapabilityConfiguration>(msg) {
+                let mut bindings = self.supervisor.bindings.lock().unwrap();
+                if op == OP_BIND_ACTOR {
+                    bindings.insert(cfg.module, (actor.to_string(), msg.to_vec()));
+                } else {
+                    bindings.remove(&cfg.module);
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl Drop for IsolatedProvider {
+    fn drop(&mut self) {
+        self.supervisor.stopped.store(true, Ordering::SeqCst);
+        if let Some(mut p) = self.supervisor.process.lock().unwrap().take() {
+            let _ = p.child.kill();
+            let _ = p.child.wait();
+        }
+    }
+}
+
+struct Process {
+    child: Child,
+    link: Arc<Link>,
+}
+
+struct Supervisor {
+    library: PathBuf,
+    runner: PathBuf,
+    tracker: Mutex<RestartTracker>,
+    dispatcher: Arc<RwLock<Option<Box<dyn Dispatcher>>>>,
+    // the key to this field is the bound actor, the value is the original bind call
+    bindings: Mutex<HashMap<String, (String, Vec<u8>)>>,
+    process: Mutex<Option<Process>>,
+    stopped: AtomicBool,
+}
+
+impl Supervisor {
+    // Starts a child process and waits for it to connect
+    fn spawn(self: &Arc<Self>) -> Result<()> {
+        let socket = std::env::temp_dir().join(format!("wascc-provider-{}.sock", Uuid::new_v4()));
+        let listener = UnixListener::bind(&socket)?;
+        let child = Command::new(&self.runner)
+            .arg(&self.library)
+            .arg(&socket)
+            .spawn();
+        let accepted =
+            child
+                .map_err(|e| e.into())
+                .and_then(|mut child| match accept(&listener, &mut child) {
+                    Ok(stream) => Ok((child, stream)),
+                    Err(e) => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        Err(e)
+                    }
+                });
+        let _ = std::fs::remove_file(&socket);
+        let (child, stream) = accepted?;
+        info!(
+            "Started provider process {} for {}",
+            child.id(),
+            self.library.display()
+        );
+
+        let link = Link::new(Box::new(stream.try_clone()?));
+        *self.process.lock().unwrap() = Some(Process {
+            child,
+            link: link.clone(),
+        });
+        let dispatcher = self.dispatcher.clone();
+        let handler: Handler =
+            Arc::new(move |actor: &str, op: &str, msg: &[u8]| {
+                match dispatcher.read().unwrap().as_ref() {
+                    Some(d) => d.dispatch(actor, op, msg).map_err(|e| e.to_string()),
+                    None => Err("No dispatcher has been configured for the provider".to_string()),
+                }
+            });
+        let supervisor = self.clone();
+        thread::spawn(move || {
+            serve(link, stream, handler);
+            supervisor.restart();
+        });
+        Ok(())
+    }
+
+    // Replaces a child process that has exited, unless the provider has been stopped
+    fn restart(self: Arc<Self>) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(mut p) = self.process.lock().unwrap().take() {
+            let status = p.child.wait();
+            error!(
+                "Provider process for {} exited ({:?})",
+                self.library.display(),
+                status
+            );
+        }
+        loop {
+            let delay = match self.tracker.lock().unwrap().next_restart() {
+                Some(d) => d,
+                None => {
+                    error!(
+                        "Provider process for {} exceeded its restart policy, giving up",
+                        self.library.display()
+                    );
+                    return;
+                }
+            };
+            thread::sleep(delay);
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            match self.spawn() {
+                Ok(_) => break,
+                Err(e) => error!(
+                    "Failed to restart provider process for {}: {}",
+                    self.library.display(),
+                    e
+                ),
+            }
+        }
+        let bindings: Vec<_> = self.bindings.lock().unwrap().values().cloned().collect();
+        for (actor, msg) in bindings {
+            if let Err(e) = self.call(&actor, OP_BIND_ACTOR, &msg) {
+                error!("Failed to re-establish binding in provider process: {}", e);
+            }
+        }
+    }
+
+    fn call(&self, actor: &str, op: &str, msg: &[u8]) -> CallResult {
+        let link = self
+            .process
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|p| p.link.clone())
+            .ok_or_else(|| "The provider process is not running".to_string())?;
+        link.request(actor, op, msg)
+    }
+}
+
+// Waits for a new child process to connect, giving up if it exits or takes too long
+fn accept(listener: &UnixListener, child: &mut Child) -> Result<UnixStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(errors::new(ErrorKind::CapabilityProvider(format!(
+                "Provider process exited before connecting ({})",
+                status
+            ))));
+        }
+        if Instant::now() > deadline {
+            return Err(errors::new(ErrorKind::CapabilityProvider(
+                "Timed out waiting for the provider process to connect".to_string(),
+            )));
+        }
+        thread::sleep(CONNECT_POLL);
+    }
+}
+
+struct LinkDispatcher {
+    link: Arc<Link>,
+}
+
+impl Dispatcher for LinkDispatcher {
+    fn dispatch(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Sync + Send>> {
+        self.link.request(actor, op, msg).map_err(|e| e.into())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Request {
+        id: u64,
+        actor: String,
+        op: String,
+        msg: Vec<u8>,
+    },
+    Response {
+        id: u64,
+        result: CallResult,
+    },
+}
+
+impl Frame {
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut buf = vec![];
+        let field = |buf: &mut Vec<u8>, bytes: &[u8]| {
+            buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            buf.extend_from_slice(bytes);
+        };
+        match self {
+            Frame::Request { id, actor, op, msg } => {
+                buf.push(REQUEST);
+                buf.extend_from_slice(&id.to_le_bytes());
+                field(&mut buf, actor.as_bytes());
+                field(&mut buf, op.as_bytes());
+                field(&mut buf, msg);
+            }
+            Frame::Response { id, result } => {
+                buf.push(if result.is_ok() {
+                    RESPONSE_OK
+                } else {
+                    RESPONSE_ERR
+                });
+                buf.extend_from_slice(&id.to_le_bytes());
+                match result {
+                    Ok(v) => field(&mut buf, v),
+                    Err(e) => field(&mut buf, e.as_bytes()),
+                }
+            }
+        }
+        w.write_all(&(buf.len() as u32).to_le_bytes())?;
+        w.write_all(&buf)?;
+        w.flush()
+    }
+
+    fn read(r: &mut dyn Read) -> io::Result<Frame> {
+        let mut len = [0; 4];
+        r.read_exact(&mut len)?;
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        r.read_exact(&mut buf)?;
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed frame");
+        let mut pos = 0;
+        let mut take = |len: usize| -> io::Result<&[u8]> {
+            let end = pos + len;
+            let bytes = buf.get(pos..end).ok_or_else(invalid)?;
+            pos = end;
+            Ok(bytes)
+        };
+        let tag = take(1)?[0];
+        let id = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let mut field = || -> io::Result<Vec<u8>> {
+            let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+            Ok(take(len)?.to_vec())
+        };
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| invalid());
+        match tag {
+            REQUEST => Ok(Frame::Request {
+                id,
+                actor: text(field()?)?,
+                op: text(field()?)?,
+                msg: field()?,
+            }),
+            RESPONSE_OK => Ok(Frame::Response {
+                id,
+                result: Ok(field()?),
+            }),
+            RESPONSE_ERR => Ok(Frame::Response {
+                id,
+                result: Err(text(field()?)?),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// One end of the connection between the host and a provider process
+struct Link {
+    writer: Mutex<Box<dyn Write + Send>>,
+    pending: Mutex<HashMap<u64, Sender<CallResult>>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl Link {
+    fn new(writer: Box<dyn Write + Send>) -> Arc<Link> {
+        Arc::new(Link {
+            writer: Mutex::new(writer),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    // Sends a request to the other end and waits for the response
+    fn request(&self, actor: &str, op: &str, msg: &[u8]) -> CallResult {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (s, r) = channel::bounded(1);
+        self.pending.lock().unwrap().insert(id, s);
+        let frame = Frame::Request {
+            id,
+            actor: actor.to_string(),
+            op: op.to_string(),
+            msg: msg.to_vec(),
+        };
+        if self.closed.load(Ordering::SeqCst) || self.send(&frame).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err("The provider process connection is closed".to_string());
+        }
+        r.recv()
+            .unwrap_or_else(|_| Err("The provider process exited during the call".to_string()))
+    }
+
+    fn send(&self, frame: &Frame) -> io::Result<()> {
+        frame.write(&mut **self.writer.lock().unwrap())
+    }
+
+    fn resolve(&self, id: u64, result: CallResult) {
+        if let Some(s) = self.pending.lock().unwrap().remove(&id) {
+            let _ = s.send(result);
+        }
+    }
+
+    // Fails every request that is still waiting for a response
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+// Reads frames until the other end goes away, answering each request on its own thread
+fn serve(link: Arc<Link>, reader: impl Read, handler: Handler) {
+    let mut reader = BufReader::new(reader);
+    while let Ok(frame) = Frame::read(&mut reader) {
+        match frame {
+            Frame::Response { id, result } => link.resolve(id, result),
+            Frame::Request { id, actor, op, msg } => {
+                let (link, handler) = (link.clone(), handler.clone());
+                thread::spawn(move || {
+                    let result = handler(&actor, &op, &msg);
+                    let _ = link.send(&Frame::Response { id, result });
+                });
+            }
+        }
+    }
+    link.close();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, Frame, Handler, Link};
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn frames_roundtrip() {
+        let frames = vec![
+            Frame::Request {
+                id: 7,
+                actor: "MACTOR".into(),
+                op: "Get".into(),
+                msg: vec![1, 2, 3],
+            },
+            Frame::Response {
+                id: 7,
+                result: Ok(vec![]),
+            },
+            Frame::Response {
+                id: 8,
+                result: Err("failed".into()),
+            },
+        ];
+        let mut buf = vec![];
+        for f in frames.iter() {
+            f.write(&mut buf).unwrap();
+        }
+        let mut reader = &buf[..];
+        for f in frames {
+            assert_eq!(f, Frame::read(&mut reader).unwrap());
+        }
+        assert!(Frame::read(&mut &b"\x01\x00\x00\x00\x09"[..]).is_err());
+    }
+
+    #[test]
+    fn requests_are_answered_both_ways() {
+        let (host, provider) = UnixStream::pair().unwrap();
+        let host_link = Link::new(Box::new(host.try_clone().unwrap()));
+        let provider_link = Link::new(Box::new(provider.try_clone().unwrap()));
+        let provider_end = provider.try_clone().unwrap();
+
+        let echo: Handler = Arc::new(|actor: &str, op: &str, msg: &[u8]| {
+            if op == "Fail" {
+                Err(format!("{} failed", actor))
+            } else {
+                Ok(msg.to_vec())
+            }
+        });
+        let (l, h) = (host_link.clone(), echo.clone());
+        let host_thread = thread::spawn(move || serve(l, host, h));
+        let l = provider_link.clone();
+        thread::spawn(move || serve(l, provider, echo));
+
+        assert_eq!(Ok(vec![4, 2]), host_link.request("MA", "Echo", &[4, 2]));
+        assert_eq!(
+            Err("MA failed".to_string()),
+            host_link.request("MA", "Fail", &[])
+        );
+        assert_eq!(Ok(vec![1]), provider_link.request("MB", "Echo", &[1]));
+
+        // Once the other end goes away, requests fail instead of waiting forever
+        provider_end.shutdown(Shutdown::Both).unwrap();
+        host_thread.join().unwrap();
+        assert!(host_link.request("MA", "Echo", &[]).is_err());
+    }
+}
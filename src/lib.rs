@@ -68,30 +68,57 @@ extern crate log;
 extern crate crossbeam;
 
 mod actor;
+mod aliases;
+#[cfg(feature = "provider_archive")]
+mod archive;
+#[cfg(feature = "async")]
+mod asynchost;
 mod authz;
 mod bus;
 mod capability;
 mod dispatch;
+mod engine;
 pub mod errors;
+mod events;
 mod extras;
 mod inthost;
+#[cfg(unix)]
+mod isolation;
+mod limits;
 #[cfg(feature = "manifest")]
 mod manifest;
 pub mod middleware;
 mod plugins;
+#[cfg(feature = "manifest")]
+mod policy;
+mod revisions;
+mod revocation;
 mod spawns;
+mod supervisor;
+mod upgrade;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const REVISION: u32 = 2;
 
 pub type Result<T> = std::result::Result<T, errors::Error>;
 
-pub use actor::Actor;
-pub use capability::NativeCapability;
-pub use inthost::{Invocation, InvocationResponse, WasccEntity};
+pub use actor::{Actor, ActorSource};
+pub use aliases::{CallAlias, CALL_ALIAS_TAG_PREFIX};
+#[cfg(feature = "provider_archive")]
+pub use archive::{ProviderArchive, ProviderMetadata};
+#[cfg(feature = "async")]
+pub use asynchost::AsyncHost;
+pub use capability::{
+    provider_abi_version, NativeCapability, ProviderProvenance, PROVIDER_ABI_SYMBOL,
+};
+pub use inthost::{Invocation, InvocationErrorCode, InvocationResponse, WasccEntity};
+#[cfg(unix)]
+pub use isolation::{serve_provider, ProcessIsolation};
 
 #[cfg(feature = "manifest")]
 pub use manifest::{BindingEntry, HostManifest};
+#[cfg(feature = "manifest")]
+pub use policy::{AuthorizationPolicy, PolicyAuthorizer, ProviderRule, TargetRule};
 
 #[cfg(feature = "prometheus_middleware")]
 pub use middleware::prometheus;
@@ -102,34 +129,53 @@ use latticeclient::BusEvent;
 #[cfg(feature = "lattice")]
 use bus::lattice::ControlCommand;
 
-pub use authz::Authorizer;
+pub use authz::{
+    ActorCallPolicy, Authorizer, OperationPolicy, CALL_TAG_PREFIX, OPERATION_TAG_PREFIX,
+};
+pub use events::HostEvent;
+pub use limits::ResourceLimits;
 pub use middleware::Middleware;
+pub use revisions::ActorRevision;
+pub use revocation::{Revocation, RevokedEntity};
+pub use supervisor::RestartPolicy;
+pub use upgrade::{CanaryOptions, CanaryStatus, ReplaceOptions, ReplaceOutcome, ReplaceReport};
 pub use wapc::WasiParams;
 
 pub type SubjectClaimsPair = (String, Claims<wascap::jwt::Actor>);
 
+use aliases::CallAliases;
 use bus::{get_namespace_prefix, MessageBus};
 use crossbeam::Sender;
 #[cfg(feature = "lattice")]
 use crossbeam_channel as channel;
 use crossbeam_channel::Receiver;
+use dispatch::WasccNativeDispatcher;
 #[cfg(any(feature = "lattice", feature = "manifest"))]
 use inthost::RESTRICTED_LABELS;
+use inthost::{ActorGate, ActorInstances, ActorMode};
 use plugins::PluginManager;
+use revisions::RevisionHistory;
+use revocation::RevocationList;
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 use wascap::jwt::Claims;
 use wascap::prelude::KeyPair;
 use wascc_codec::{
     capabilities::CapabilityDescriptor,
-    core::{CapabilityConfiguration, OP_BIND_ACTOR},
+    core::{CapabilityConfiguration, OP_BIND_ACTOR, OP_REMOVE_ACTOR},
     serialize, SYSTEM_ACTOR,
 };
 
 type BindingsList = HashMap<BindingTuple, CapabilityConfiguration>;
 type BindingTuple = (String, String, String); // (from-actor, to-capid, to-binding-name)
+type ThreadList = Vec<(String, HostThread)>; // (actor pk or "capid,binding", thread)
+
+// How often a draining actor or host is checked for outstanding work
+const DRAIN_POLL: Duration = Duration::from_millis(10);
 
 /// A routing key is a combination of a capability ID and the binding name used for
 /// that capability. Think of it as a unique or primary key for a capid+binding.
@@ -148,11 +194,35 @@ impl RouteKey {
     }
 }
 
+/// Describes the outcome of a graceful shutdown. Anything listed here had not stopped by the
+/// time the shutdown's timeout expired; the host's message bus is disconnected regardless
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// The number of invocations that were still in flight when the host stopped draining
+    pub pending_invocations: usize,
+    /// The bus subjects (actors, providers, and bindings) whose handlers had not terminated
+    pub unterminated: Vec<String>,
+    /// The actors (by public key) and providers (as `capid,binding`) whose threads were
+    /// still running
+    pub unjoined: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// Indicates whether everything in the host stopped before the timeout
+    pub fn is_clean(&self) -> bool {
+        self.pending_invocations == 0 && self.unterminated.is_empty() && self.unjoined.is_empty()
+    }
+}
+
 /// A builder pattern implementation for creating a custom-configured host runtime
 pub struct HostBuilder {
     labels: HashMap<String, String>,
     ns: Option<String>,
     authorizer: Box<dyn Authorizer + 'static>,
+    call_policy: ActorCallPolicy,
+    operations: OperationPolicy,
+    trusted_issuers: Vec<String>,
+    revocations: Vec<Revocation>,
     #[cfg(feature = "lattice")]
     gantry_client: Option<gantryclient::Client>,
 }
@@ -168,6 +238,10 @@ impl HostBuilder {
             labels: inthost::detect_core_host_labels(),
             ns: get_namespace_prefix(),
             authorizer: Box::new(authz::DefaultAuthorizer::new()),
+            call_policy: ActorCallPolicy::default(),
+            operations: OperationPolicy::default(),
+            trusted_issuers: vec![],
+            revocations: vec![],
         };
 
         #[cfg(feature = "lattice")]
@@ -175,6 +249,10 @@ impl HostBuilder {
             labels: inthost::detect_core_host_labels(),
             ns: get_namespace_prefix(),
             authorizer: Box::new(authz::DefaultAuthorizer::new()),
+            call_policy: ActorCallPolicy::default(),
+            operations: OperationPolicy::default(),
+            trusted_issuers: vec![],
+            revocations: vec![],
             gantry_client: None,
         };
 
@@ -206,6 +284,53 @@ impl HostBuilder {
         }
     }
 
+    /// Sets the policy that decides which actors may invoke other actors. An actor can only
+    /// invoke the actors its claims attest to; use `ActorCallPolicy::restricted` to also
+    /// require that each actor-to-actor call is permitted by a `call:` tag in the caller's
+    /// claims or by the policy's allowlist
+    pub fn with_actor_call_policy(self, policy: ActorCallPolicy) -> HostBuilder {
+        HostBuilder {
+            call_policy: policy,
+            ..self
+        }
+    }
+
+    /// Sets the policy that restricts which operations actors may invoke on capability
+    /// providers. Whatever the policy, actors can only invoke the operations that a provider
+    /// lists in its capability descriptor
+    pub fn with_operation_policy(self, policy: OperationPolicy) -> HostBuilder {
+        HostBuilder {
+            operations: policy,
+            ..self
+        }
+    }
+
+    /// Adds an issuer (an account public key) to the set of issuers trusted by the host. Once
+    /// any issuer is trusted, the host refuses actors and portable capability providers signed
+    /// by anyone else, including actors scheduled remotely in lattice mode. Each refusal is
+    /// reported as a `HostEvent::ActorRejected` event
+    pub fn with_trusted_issuer(self, issuer: &str) -> HostBuilder {
+        let mut trusted_issuers = self.trusted_issuers;
+        if !trusted_issuers.iter().any(|i| i == issuer) {
+            trusted_issuers.push(issuer.to_string());
+        }
+        HostBuilder {
+            trusted_issuers,
+            ..self
+        }
+    }
+
+    /// Adds an entry to the host's initial revocation list. The list can be changed at
+    /// runtime with `Host::revoke` and `Host::remove_revocation`
+    pub fn with_revocation(self, revocation: Revocation) -> HostBuilder {
+        let mut revocations = self.revocations;
+        revocations.push(revocation);
+        HostBuilder {
+            revocations,
+            ..self
+        }
+    }
+
     /// Adds an arbitrary label->value pair of metadata to the host. Cannot override
     /// reserved labels such as those that begin with `hostcore.` Calling this twice
     /// on the same label will have no effect after the first call.
@@ -229,14 +354,27 @@ impl HostBuilder {
     /// Converts the transient builder instance into a realized host runtime instance
     pub fn build(self) -> Host {
         #[cfg(not(feature = "lattice"))]
-        let h = Host::generate(self.authorizer, self.labels, self.ns.clone());
+        let h = Host::generate(
+            self.authorizer,
+            self.call_policy,
+            self.operations,
+            self.trusted_issuers,
+            self.labels,
+            self.ns.clone(),
+        );
         #[cfg(feature = "lattice")]
         let h = Host::generate(
             self.authorizer,
+            self.call_policy,
+            self.operations,
+            self.trusted_issuers,
             self.labels,
             self.ns.clone(),
             self.gantry_client.clone(),
         );
+        for r in self.revocations.iter() {
+            h.revocations.write().unwrap().revoke(r);
+        }
         h
     }
 }
@@ -252,6 +390,15 @@ pub struct Host {
     middlewares: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
     // the key to this field is the subscription subject, and not either a pk or a capid
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
+    // the key to this field is the actor's public key
+    instances: Arc<RwLock<HashMap<String, ActorInstances>>>,
+    threads: Arc<RwLock<ThreadList>>,
+    revisions: Arc<RwLock<RevisionHistory>>,
+    aliases: Arc<RwLock<CallAliases>>,
+    call_policy: Arc<RwLock<ActorCallPolicy>>,
+    operations: Arc<RwLock<OperationPolicy>>,
+    trusted_issuers: Arc<Vec<String>>,
+    revocations: Arc<RwLock<RevocationList>>,
     #[cfg(feature = "lattice")]
     gantry_client: Arc<RwLock<Option<gantryclient::Client>>>,
     key: KeyPair,
@@ -267,12 +414,18 @@ impl Host {
         #[cfg(not(feature = "lattice"))]
         let h = Self::generate(
             Box::new(authz::DefaultAuthorizer::new()),
+            ActorCallPolicy::default(),
+            OperationPolicy::default(),
+            vec![],
             inthost::detect_core_host_labels(),
             get_namespace_prefix(),
         );
         #[cfg(feature = "lattice")]
         let h = Self::generate(
             Box::new(authz::DefaultAuthorizer::new()),
+            ActorCallPolicy::default(),
+            OperationPolicy::default(),
+            vec![],
             inthost::detect_core_host_labels(),
             get_namespace_prefix(),
             None,
@@ -282,6 +435,9 @@ impl Host {
 
     pub(crate) fn generate(
         authz: Box<dyn Authorizer + 'static>,
+        call_policy: ActorCallPolicy,
+        operations: OperationPolicy,
+        trusted_issuers: Vec<String>,
         labels: HashMap<String, String>,
         ns: Option<String>,
         #[cfg(feature = "lattice")] gantry: Option<gantryclient::Client>,
@@ -320,6 +476,14 @@ impl Host {
         #[cfg(feature = "lattice")]
         let host = Host {
             terminators: terminators.clone(),
+            instances: Arc::new(RwLock::new(HashMap::new())),
+            threads: Arc::new(RwLock::new(vec![])),
+            revisions: Arc::new(RwLock::new(RevisionHistory::new())),
+            aliases: Arc::new(RwLock::new(CallAliases::new())),
+            call_policy: Arc::new(RwLock::new(call_policy)),
+            operations: Arc::new(RwLock::new(operations)),
+            trusted_issuers: Arc::new(trusted_issuers),
+            revocations: Arc::new(RwLock::new(RevocationList::new())),
             bus: bus.clone(),
             claims: claims.clone(),
             plugins: Arc::new(RwLock::new(PluginManager::default())),
@@ -335,6 +499,14 @@ impl Host {
         #[cfg(not(feature = "lattice"))]
         let host = Host {
             terminators: terminators.clone(),
+            instances: Arc::new(RwLock::new(HashMap::new())),
+            threads: Arc::new(RwLock::new(vec![])),
+            revisions: Arc::new(RwLock::new(RevisionHistory::new())),
+            aliases: Arc::new(RwLock::new(CallAliases::new())),
+            call_policy: Arc::new(RwLock::new(call_policy)),
+            operations: Arc::new(RwLock::new(operations)),
+            trusted_issuers: Arc::new(trusted_issuers),
+            revocations: Arc::new(RwLock::new(RevocationList::new())),
             bus: bus.clone(),
             claims: claims.clone(),
             plugins: Arc::new(RwLock::new(PluginManager::default())),
@@ -358,7 +530,9 @@ impl Host {
 
     /// Adds an actor to the host. This will provision resources (such as a handler thread) for the actor. Actors
     /// will not be able to make use of capability providers unless bindings are added (or existed prior to the actor
-    /// being added to a host, which is possible in `lattice` mode)
+    /// being added to a host, which is possible in `lattice` mode). Resource limits set on the actor (see
+    /// `Actor::with_limits`) are applied to the module before it is handed to the WebAssembly engine, and
+    /// the actor is rejected if its module cannot run within them
     pub fn add_actor(&self, actor: Actor) -> Result<()> {
         if self
             .claims
@@ -367,16 +541,18 @@ impl Host {
             .contains_key(&actor.public_key())
         {
             return Err(errors::new(errors::ErrorKind::MiscHost(
-                format!("Actor {} is already in this host. Cannot host multiple instances of the same actor in the same host", actor.public_key())
+                format!("Actor {} is already in this host. Use scale_actor to run multiple instances of the same actor in the same host", actor.public_key())
             )));
         }
         authz::enforce_validation(&actor.token.jwt)?; // returns an `Err` if validation fails
+        self.check_trust(&actor.token.claims)?;
         if !self.check_auth(&actor.token) {
             // invoke the auth hook, if there is one
             return Err(errors::new(errors::ErrorKind::Authorization(
                 "Authorization hook denied access to module".into(),
             )));
         }
+        self.aliases.write().unwrap().declare(&actor.token.claims)?;
 
         let c = self.claims.clone();
 
@@ -387,10 +563,16 @@ impl Host {
 
         let wg = crossbeam_utils::sync::WaitGroup::new();
         // Spin up a new thread that listens to "wasmbus.Mxxxx" calls on the message bus
-        spawns::spawn_actor(
+        match spawns::spawn_actor(
             wg.clone(),
             actor.token.claims.clone(),
             actor.bytes.clone(),
+            spawns::ActorSettings {
+                timeout: actor.timeout,
+                limits: actor.limits.clone(),
+                policy: actor.restart_policy.clone(),
+                instances: self.instances.clone(),
+            },
             None,
             true,
             None,
@@ -401,9 +583,21 @@ impl Host {
             c.clone(),
             self.terminators.clone(),
             self.key.clone(),
-            self.authorizer.clone(),
-        )?;
+            self.security(),
+        ) {
+            Ok(handle) => track_thread(&self.threads, &actor.public_key(), handle),
+            Err(e) => {
+                c.write().unwrap().remove(&actor.public_key());
+                self.aliases.write().unwrap().forget(&actor.public_key());
+                return Err(e);
+            }
+        }
         wg.wait();
+        self.revisions.write().unwrap().record(
+            &actor.token.claims,
+            &actor.bytes,
+            actor.source.clone(),
+        );
         if actor.capabilities().contains(&extras::CAPABILITY_ID.into()) {
             // force a binding so that there's a private actor subject on the bus for the
             // actor to communicate with the extras provider
@@ -433,7 +627,10 @@ impl Host {
         let vec =
             bus::lattice::actor_bytes_from_gantry(actor, self.gantry_client.clone(), revision)?;
 
-        self.add_actor(Actor::from_slice(&vec)?)?;
+        self.add_actor(Actor {
+            source: ActorSource::Gantry { revision },
+            ..Actor::from_slice(&vec)?
+        })?;
         Ok(())
     }
 
@@ -448,13 +645,27 @@ impl Host {
         wasi: WasiParams,
     ) -> Result<()> {
         let binding = binding.unwrap_or("default");
+        self.check_trust(&actor.token.claims)?;
 
         let wg = crossbeam_utils::sync::WaitGroup::new();
         // Spins up a new thread subscribed to the "wasmbus.{capid}.{binding}" subject
-        spawns::spawn_actor(
+        let label = format!(
+            "{},{}",
+            actor
+                .token
+                .claims
+                .metadata
+                .as_ref()
+                .and_then(|m| m.caps.as_ref())
+                .and_then(|c| c.first())
+                .map_or_else(|| actor.public_key(), |c| c.to_string()),
+            binding
+        );
+        let handle = spawns::spawn_actor(
             wg.clone(),
             actor.token.claims,
             actor.bytes.clone(),
+            spawns::ActorSettings::new(self.instances.clone()),
             Some(wasi),
             false,
             Some(binding.to_string()),
@@ -465,8 +676,9 @@ impl Host {
             self.claims.clone(),
             self.terminators.clone(),
             self.key.clone(),
-            self.authorizer.clone(),
+            self.security(),
         )?;
+        track_thread(&self.threads, &label, handle);
         wg.wait();
         Ok(())
     }
@@ -476,20 +688,369 @@ impl Host {
     /// (in lattice mode, this unbinding only takes place if the actor is the last instance of its
     /// kind in the lattice)
     pub fn remove_actor(&self, pk: &str) -> Result<()> {
-        self.terminators.read().unwrap()
-            [&bus::actor_subject(self.ns.as_ref().map(String::as_str), pk)]
-            .send(true)
-            .unwrap();
+        let count = self.actor_instances(pk).max(1);
+        inthost::stop_actor_instances(
+            self.terminators.clone(),
+            &bus::actor_subject(self.ns.as_ref().map(String::as_str), pk),
+            count,
+        )
+    }
+
+    /// Pauses an actor. Its instances hold on to the invocations they receive without running
+    /// them until the actor is resumed. Callers are not answered while the actor is paused,
+    /// so an invocation with a timeout may expire while it is held
+    pub fn pause_actor(&self, pk: &str) -> Result<()> {
+        let gate = self.actor_gate(pk)?;
+        if gate.mode() == ActorMode::Draining {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Cannot pause actor {}, it is draining",
+                pk
+            ))));
+        }
+        gate.set_mode(ActorMode::Paused);
+        Ok(())
+    }
+
+    /// Resumes a paused actor. The invocations held while it was paused are run in the order
+    /// in which they arrived, ahead of any new invocations
+    pub fn resume_actor(&self, pk: &str) -> Result<()> {
+        let gate = self.actor_gate(pk)?;
+        if gate.mode() == ActorMode::Draining {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Cannot resume actor {}, it is draining",
+                pk
+            ))));
+        }
+        gate.set_mode(ActorMode::Running);
+        Ok(())
+    }
+
+    /// Drains an actor and then removes it from the host. New invocations for the actor are
+    /// refused while the invocations it is holding (because it was paused) or running are
+    /// finished. This function blocks until all of the actor's instances have stopped or until
+    /// the timeout expires. The actor is removed either way; if it had not drained in time, the
+    /// returned error lists the invocations and instances that were still outstanding
+    pub fn drain_actor(&self, pk: &str, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let gate = self.actor_gate(pk)?;
+        gate.set_mode(ActorMode::Draining);
+        wait_until(deadline, || gate.held() == 0);
+        let held = gate.held();
+        self.remove_actor(pk)?;
+        wait_until(deadline, || self.actor_instances(pk) == 0);
+        let running = self.actor_instances(pk);
+        if held > 0 || running > 0 {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Actor {} did not drain within {:?}: {} held invocation(s), {} instance(s) still running",
+                pk, timeout, held, running
+            ))));
+        }
+        Ok(())
+    }
+
+    fn actor_gate(&self, pk: &str) -> Result<Arc<ActorGate>> {
+        match self.instances.read().unwrap().get(pk) {
+            Some(rec) => Ok(rec.gate.clone()),
+            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "No running instances of actor {}",
+                pk
+            )))),
+        }
+    }
+
+    /// Changes the number of instances of an actor running in this host. Each instance runs
+    /// on its own thread with its own WebAssembly module instance, and invocations for the actor
+    /// are spread across the instances that are not already busy. Scaling down stops instances
+    /// as soon as they finish the invocation they are processing. An actor must be added to the
+    /// host via `add_actor` before it can be scaled, and cannot be scaled to zero (use `remove_actor`)
+    pub fn scale_actor(&self, pk: &str, instances: usize) -> Result<()> {
+        if instances == 0 {
+            return Err(errors::new(errors::ErrorKind::MiscHost(
+                "Cannot scale an actor to zero instances, remove the actor instead".into(),
+            )));
+        }
+        let (bytes, timeout, limits, policy, current) = match self.instances.read().unwrap().get(pk)
+        {
+            Some(rec) => (
+                rec.gate.module().bytes.clone(),
+                rec.timeout,
+                rec.limits.clone(),
+                rec.policy.clone(),
+                rec.count,
+            ),
+            None => {
+                return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "Actor {} is not running in this host",
+                    pk
+                ))))
+            }
+        };
+        let claims = match self.claims.read().unwrap().get(pk) {
+            Some(c) => c.clone(),
+            None => {
+                return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "No claims found for actor {}",
+                    pk
+                ))))
+            }
+        };
+
+        if instances > current {
+            let wg = crossbeam_utils::sync::WaitGroup::new();
+            for _ in current..instances {
+                let handle = spawns::spawn_actor(
+                    wg.clone(),
+                    claims.clone(),
+                    bytes.clone(),
+                    spawns::ActorSettings {
+                        timeout,
+                        limits: limits.clone(),
+                        policy: policy.clone(),
+                        instances: self.instances.clone(),
+                    },
+                    None,
+                    true,
+                    None,
+                    self.bus.clone(),
+                    self.middlewares.clone(),
+                    self.caps.clone(),
+                    self.bindings.clone(),
+                    self.claims.clone(),
+                    self.terminators.clone(),
+                    self.key.clone(),
+                    self.security(),
+                )?;
+                track_thread(&self.threads, pk, handle);
+            }
+            wg.wait();
+        } else if instances < current {
+            inthost::stop_actor_instances(
+                self.terminators.clone(),
+                &bus::actor_subject(self.ns.as_ref().map(String::as_str), pk),
+                current - instances,
+            )?;
+        }
         Ok(())
     }
 
+    /// Registers a call alias, a human-friendly name that actors can use in place of the given
+    /// actor's public key when they invoke it. Actors may also declare their own aliases with a
+    /// `call_alias:<alias>` tag in their claims (see `CALL_ALIAS_TAG_PREFIX`). Aliases cannot
+    /// contain `:`, `.` or whitespace, so that they never shadow a capability ID, and an alias
+    /// that already resolves to a different actor is rejected
+    pub fn register_call_alias(&self, alias: &str, pk: &str) -> Result<()> {
+        if !aliases::is_actor_key(pk) {
+            return Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Cannot register call alias '{}', {} is not an actor public key",
+                alias, pk
+            ))));
+        }
+        self.aliases.write().unwrap().register(alias, pk)
+    }
+
+    /// Removes a call alias, whether it was registered on the host or declared by an actor
+    pub fn remove_call_alias(&self, alias: &str) -> Result<()> {
+        match self.aliases.write().unwrap().remove(alias) {
+            Some(_) => Ok(()),
+            None => Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "No call alias '{}' in this host",
+                alias
+            )))),
+        }
+    }
+
+    /// Lists the call aliases known to this host and the actors they resolve to
+    pub fn call_aliases(&self) -> Vec<CallAlias> {
+        self.aliases.read().unwrap().list()
+    }
+
+    /// Adds an entry to the allowlist of the host's actor call policy, permitting the caller
+    /// to invoke the target actor. The allowlist is only consulted when the policy is
+    /// restricted (see `HostBuilder::with_actor_call_policy`)
+    pub fn allow_actor_call(&self, caller: &str, target: &str) {
+        self.call_policy.write().unwrap().allow(caller, target);
+    }
+
+    /// Removes an entry from the allowlist of the host's actor call policy. Under a restricted
+    /// policy the caller can no longer invoke the target, unless its claims permit the call
+    pub fn revoke_actor_call(&self, caller: &str, target: &str) -> Result<()> {
+        if self.call_policy.write().unwrap().revoke(caller, target) {
+            Ok(())
+        } else {
+            Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "Actor {} is not on the allowlist to call actor {}",
+                caller, target
+            ))))
+        }
+    }
+
+    /// Obtains the policy that decides which actors may invoke other actors
+    pub fn actor_call_policy(&self) -> ActorCallPolicy {
+        self.call_policy.read().unwrap().clone()
+    }
+
+    /// Replaces the policy that restricts which operations actors may invoke on capability
+    /// providers. The new policy applies to invocations made from then on
+    pub fn set_operation_policy(&self, policy: OperationPolicy) {
+        *self.operations.write().unwrap() = policy;
+    }
+
+    /// Obtains the policy that restricts which operations actors may invoke on capability
+    /// providers
+    pub fn operation_policy(&self) -> OperationPolicy {
+        self.operations.read().unwrap().clone()
+    }
+
+    /// Returns the issuers whose actors this host accepts. An empty list means that actors
+    /// from any issuer are accepted
+    pub fn trusted_issuers(&self) -> Vec<String> {
+        self.trusted_issuers.to_vec()
+    }
+
+    /// Adds an entry to this host's revocation list. Revoked actors can no longer be loaded,
+    /// and running actors holding a revoked token are terminated, which removes their
+    /// bindings once their last instance has stopped. Use `Host::revoke_across_lattice` to
+    /// revoke an actor on every host in a lattice
+    pub fn revoke(&self, revocation: Revocation) -> Result<()> {
+        revocation::apply(
+            &revocation,
+            &self.revocations,
+            &self.claims,
+            &self.instances,
+            self.terminators.clone(),
+            &self.bus,
+        );
+        Ok(())
+    }
+
+    /// Applies a revocation on this host, then publishes it on the control plane signed with
+    /// the given issuer's key. Other hosts in the lattice only apply it if that issuer is one
+    /// of their trusted issuers
+    #[cfg(feature = "lattice")]
+    pub fn revoke_across_lattice(&self, revocation: Revocation, issuer: &KeyPair) -> Result<()> {
+        let token = revocation.sign(issuer)?;
+        self.revoke(revocation)?;
+        self.bus.publish_revocation(&token)
+    }
+
+    /// Removes an entry from this host's revocation list. Unlike revocations, this is not
+    /// propagated to the other hosts in a lattice
+    pub fn remove_revocation(&self, entity: &RevokedEntity) -> Result<()> {
+        if self.revocations.write().unwrap().remove(entity) {
+            Ok(())
+        } else {
+            Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                "{:?} is not in the revocation list",
+                entity
+            ))))
+        }
+    }
+
+    /// Returns the host's revocation list
+    pub fn revocations(&self) -> Vec<Revocation> {
+        self.revocations.read().unwrap().list()
+    }
+
+    /// Returns the number of instances of the given actor running in this host, or 0 if
+    /// the actor is not present
+    pub fn actor_instances(&self, pk: &str) -> usize {
+        self.instances
+            .read()
+            .unwrap()
+            .get(pk)
+            .map(|rec| rec.count)
+            .unwrap_or(0)
+    }
+
     /// Replaces one running actor with another live actor with no message loss. Note that
     /// the time it takes to perform this replacement can cause pending messages from capability
     /// providers (e.g. messages from subscriptions or HTTP requests) to build up in a backlog,
-    /// so make sure the new actor can handle this stream of these delayed messages. Also ensure that
-    /// the underlying WebAssembly driver (chosen via feature flag) supports hot-swapping module bytes.
+    /// so make sure the new actor can handle this stream of these delayed messages.
+    ///
+    /// The new actor must have the same public key and issuer as the running one, must pass
+    /// the same validation and authorization checks as `add_actor`, and must claim every
+    /// capability the actor is bound to. Each instance of the actor then loads the new module,
+    /// keeping its timeout, resource limits, and restart policy. If any instance fails to load
+    /// it, the previous module is restored and an error describing the failure is returned
     pub fn replace_actor(&self, new_actor: Actor) -> Result<()> {
-        crate::inthost::replace_actor(&self.key, self.bus.clone(), new_actor)
+        let report = self.replace_actor_with(new_actor, ReplaceOptions::default())?;
+        match report.outcome {
+            ReplaceOutcome::Replaced => Ok(()),
+            ReplaceOutcome::RolledBack { reason } => {
+                Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "Replacement of actor {} was rolled back: {}",
+                    report.actor, reason
+                ))))
+            }
+            ReplaceOutcome::RollbackFailed { reason, error } => {
+                Err(errors::new(errors::ErrorKind::MiscHost(format!(
+                    "Replacement of actor {} failed ({}), and so did restoring the previous module: {}",
+                    report.actor, reason, error
+                ))))
+            }
+        }
+    }
+
+    /// Replaces a running actor as `replace_actor` does, with options such as a smoke test
+    /// that is invoked on a staged copy of the new module and stops the replacement if it fails.
+    /// Rather than failing when the replacement is rolled back, this returns a report
+    /// describing the outcome
+    pub fn replace_actor_with(
+        &self,
+        new_actor: Actor,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        upgrade::replace_actor(self, new_actor, options)
+    }
+
+    /// Starts a canary: a new revision of a running actor that runs alongside the current
+    /// revision in each of the actor's instances and receives a share of the invocations made
+    /// on the actor. The new actor is validated as it is by `replace_actor`. Compare the error
+    /// rates reported by `canary_status`, then call `promote_canary` or `abort_canary`
+    pub fn start_canary(&self, new_actor: Actor, options: CanaryOptions) -> Result<CanaryStatus> {
+        upgrade::start_canary(self, new_actor, options)
+    }
+
+    /// Reports the progress of an actor's canary, if it has one running
+    pub fn canary_status(&self, pk: &str) -> Option<CanaryStatus> {
+        upgrade::canary_status(self, pk)
+    }
+
+    /// Changes the percentage (0 to 100) of an actor's invocations routed to its canary
+    pub fn set_canary_weight(&self, pk: &str, weight: u8) -> Result<()> {
+        upgrade::set_canary_weight(self, pk, weight)
+    }
+
+    /// Replaces the actor's current revision with its canary, in the same way as
+    /// `replace_actor_with`, and stops routing invocations separately to the canary
+    pub fn promote_canary(&self, pk: &str, options: ReplaceOptions) -> Result<ReplaceReport> {
+        upgrade::promote_canary(self, pk, options)
+    }
+
+    /// Stops an actor's canary, so that all invocations go to the current revision again.
+    /// Returns the canary's final status
+    pub fn abort_canary(&self, pk: &str) -> Result<CanaryStatus> {
+        upgrade::abort_canary(self, pk)
+    }
+
+    /// Lists the revisions of an actor that this host has run, oldest first, including the
+    /// revision it is currently running. The modules of the most recent revisions are kept in
+    /// memory, and a revision loaded from a file can also be restored while that file is unchanged
+    pub fn actor_revisions(&self, pk: &str) -> Vec<ActorRevision> {
+        let running = self.instances.read().unwrap().contains_key(pk);
+        self.revisions.read().unwrap().list(pk, running)
+    }
+
+    /// Puts a previous revision of a running actor back in place, identified by the `rev`
+    /// in its claims (see `actor_revisions`). The revision goes through the same checks as a
+    /// module given to `replace_actor_with`, and is recorded as the actor's newest revision
+    pub fn rollback_actor(
+        &self,
+        pk: &str,
+        rev: i32,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        upgrade::rollback_actor(self, pk, rev, options)
     }
 
     /// Adds a middleware item to the middleware processing pipeline
@@ -503,6 +1064,173 @@ impl Host {
     /// the binding configuration. Note that because these capabilities are native,
     /// cross-platform support is not always guaranteed.
     pub fn add_native_capability(&self, capability: NativeCapability) -> Result<()> {
+        self.authorize_provider(&capability)?;
+        self.load_native_capability(capability)
+    }
+
+    /// Loads a native capability provider from a file and adds it to the host as with
+    /// `add_native_capability`. The authorizer's `can_load_provider_file` check is made
+    /// against the file's path and hash before the library is opened, so a library the
+    /// authorizer refuses never runs any code
+    pub fn add_native_capability_file(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        binding_name: Option<String>,
+    ) -> Result<()> {
+        let capability =
+            NativeCapability::from_file_checked(path.as_ref(), binding_name, |provenance| {
+                self.authorize_provider_file(provenance)
+            })?;
+        self.add_native_capability(capability)
+    }
+
+    /// Replaces a running native capability provider with a new build of it without dropping
+    /// its bindings. The new provider must have the same capability ID and binding name as the
+    /// one it replaces. It is bound to every actor bound to the old provider before taking its
+    /// place, and the old provider's library is unloaded last. The old provider keeps serving
+    /// invocations while the new one is bound, and invocations that arrive while one provider
+    /// takes the other's place wait for the swap to complete. If the new provider fails to
+    /// accept one of the bindings, the bindings it did accept are removed from it again, and it
+    /// is discarded while the old provider keeps running
+    pub fn replace_native_capability(&self, capability: NativeCapability) -> Result<()> {
+        self.authorize_provider(&capability)?;
+        let capid = capability.id();
+        let binding = capability.binding_name.to_string();
+        let route_key = RouteKey::new(&binding, &capid);
+        if !self.caps.read().unwrap().contains_key(&route_key) {
+            return Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                "No capability provider {} is bound to the name {}, replacement failed",
+                capid, binding
+            ))));
+        }
+        let descriptor = capability.descriptor().clone();
+        let dispatcher = WasccNativeDispatcher::new(
+            Arc::new(self.key.clone()),
+            self.bus.clone(),
+            &capid,
+            &binding,
+        );
+        capability
+            .plugin
+            .configure_dispatch(Box::new(dispatcher))
+            .map_err(|e| {
+                errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                    "Failed to configure dispatch on replacement provider: {}",
+                    e
+                )))
+            })?;
+        // No locks are held while binding, since the new provider may call back into an actor
+        // that invokes the old one
+        let mut configs: Vec<CapabilityConfiguration> = self
+            .bindings
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((_, c, b), _)| *c == capid && *b == binding)
+            .map(|(_, config)| config.clone())
+            .collect();
+        configs.sort_by(|a, b| a.module.cmp(&b.module));
+        for (i, config) in configs.iter().enumerate() {
+            if let Err(e) =
+                capability
+                    .plugin
+                    .handle_call(SYSTEM_ACTOR, OP_BIND_ACTOR, &serialize(config)?)
+            {
+                unbind_replacement(&capability, &configs[..i]);
+                return Err(errors::new(errors::ErrorKind::CapabilityProvider(format!(
+                    "Replacement provider failed to bind actor {}: {}",
+                    config.module, e
+                ))));
+            }
+        }
+        let old = self.plugins.write().unwrap().replace_plugin(capability)?;
+        self.caps.write().unwrap().insert(route_key, descriptor);
+        info!(
+            "Replaced native capability provider '({},{})'",
+            binding, capid
+        );
+        self.bus.emit(HostEvent::ProviderReplaced {
+            capid: capid.to_string(),
+            binding,
+        });
+        drop(old);
+        Ok(())
+    }
+
+    fn authorize_provider(&self, capability: &NativeCapability) -> Result<()> {
+        if self
+            .authorizer
+            .read()
+            .unwrap()
+            .can_load_provider(capability.descriptor(), capability.provenance())
+        {
+            Ok(())
+        } else {
+            Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Authorization hook denied access to capability provider {}",
+                capability.id()
+            ))))
+        }
+    }
+
+    fn authorize_provider_file(&self, provenance: &ProviderProvenance) -> Result<()> {
+        if self
+            .authorizer
+            .read()
+            .unwrap()
+            .can_load_provider_file(provenance)
+        {
+            Ok(())
+        } else {
+            Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Authorization hook denied access to capability provider library {}",
+                provenance
+                    .path
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default()
+            ))))
+        }
+    }
+
+    /// Verifies a signed provider archive and adds the provider library in it that matches
+    /// this host's `hostcore.arch` and `hostcore.os` labels, as with `add_native_capability`.
+    /// If the host has trusted issuers, the archive must be signed by one of them. The
+    /// authorizer's `can_load_provider_file` check is made before the library is opened
+    #[cfg(feature = "provider_archive")]
+    pub fn add_provider_archive(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        binding_name: Option<String>,
+    ) -> Result<()> {
+        let archive = ProviderArchive::from_file(path)?;
+        let signer = archive.claims().map(|c| c.issuer.to_string());
+        if !self.trusted_issuers.is_empty()
+            && !matches!(signer, Some(s) if self.trusted_issuers.contains(&s))
+        {
+            return Err(errors::new(errors::ErrorKind::Authorization(format!(
+                "Provider archive for {} is not signed by a trusted issuer",
+                archive.capid()
+            ))));
+        }
+        let target = {
+            let labels = self.labels.read().unwrap();
+            let label = |name: &str| labels.get(name).cloned().unwrap_or_default();
+            archive::target_name(
+                &label(inthost::CORELABEL_ARCH),
+                &label(inthost::CORELABEL_OS),
+            )
+        };
+        self.add_native_capability(NativeCapability::from_archive_checked(
+            &archive,
+            &target,
+            binding_name,
+            |provenance| self.authorize_provider_file(provenance),
+        )?)
+    }
+
+    // The host's own providers (e.g. the extras) are loaded without consulting the authorizer
+    pub(crate) fn load_native_capability(&self, capability: NativeCapability) -> Result<()> {
         let capid = capability.id();
         if self
             .caps
@@ -519,7 +1247,8 @@ impl Host {
             capability.descriptor().clone(),
         );
         let wg = crossbeam_utils::sync::WaitGroup::new();
-        spawns::spawn_native_capability(
+        let label = format!("{},{}", capid, capability.binding_name);
+        let handle = spawns::spawn_native_capability(
             capability,
             self.bus.clone(),
             self.middlewares.clone(),
@@ -529,6 +1258,7 @@ impl Host {
             wg.clone(),
             Arc::new(self.key.clone()),
         )?;
+        track_thread(&self.threads, &label, handle);
         wg.wait();
         Ok(())
     }
@@ -605,7 +1335,8 @@ impl Host {
         }
         let c = claims.unwrap().clone();
         let binding = binding_name.unwrap_or("default".to_string());
-        if !authz::can_invoke(&c, capid, OP_BIND_ACTOR) {
+        // The binding is requested by the host, so the actor's operation restrictions don't apply
+        if !authz::attests(&c, capid) {
             return Err(errors::new(errors::ErrorKind::Authorization(format!(
                 "Unauthorized binding: actor {} is not authorized to use capability {}.",
                 actor, capid
@@ -663,12 +1394,10 @@ impl Host {
                             values: config,
                         },
                     )?;
-                    #[cfg(feature = "lattice")]
-                    let _ = self.bus.publish_event(BusEvent::ActorBindingCreated {
+                    self.bus.emit(HostEvent::ActorBindingCreated {
                         actor: actor.to_string(),
                         capid: capid.to_string(),
-                        instance_name: binding.to_string(),
-                        host: self.id(),
+                        binding: binding.to_string(),
                     });
                     Ok(())
                 }
@@ -684,22 +1413,71 @@ impl Host {
     /// knowing ahead of time if the given actor supports the specified operation. In lattice
     /// mode, this call will still only attempt a _local_ invocation on the host and will not
     /// make a lattice-wide call. If you want to make lattice-wide invocations, please use
-    /// the lattice client library.
+    /// the lattice client library. If the actor fails to handle the invocation, the call fails
+    /// with an `ErrorKind::Invocation` error carrying the invocation ID, the error code, and the
+    /// failure reason.
     pub fn call_actor(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
-        if !self.claims.read().unwrap().contains_key(actor) {
-            return Err(errors::new(errors::ErrorKind::MiscHost(
-                "No such actor".into(),
-            )));
-        }
-        let inv = Invocation::new(
+        self.invoke_actor(actor, operation, msg, None)
+    }
+
+    /// Invoke an operation handler on an actor, giving up once the supplied timeout has elapsed.
+    /// The timeout bounds the actor's execution (in addition to any timeout the actor was added
+    /// with), and a call that exceeds it fails with an `ErrorKind::Invocation` error whose code is
+    /// `InvocationErrorCode::Timeout` rather than blocking the caller.
+    ///
+    /// As with `Actor::with_timeout`, the timeout is not enforced under the `wasm3` engine
+    /// feature, which can't interrupt a guest call: the call blocks until the actor is done
+    pub fn call_actor_with_timeout(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        self.invoke_actor(actor, operation, msg, Some(timeout))
+    }
+
+    fn invoke_actor(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let mut inv = Invocation::new(
             &self.key,
             WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
             WasccEntity::Actor(actor.to_string()),
             operation,
             msg.to_vec(),
         );
+        if !self.claims.read().unwrap().contains_key(actor) {
+            return Err(bus::invocation_error(
+                &inv,
+                InvocationErrorCode::NotFound,
+                "No such actor",
+            ));
+        }
+        if let Some(t) = timeout {
+            inv = inv.with_timeout(t);
+        }
+        self.deliver_to_actor(actor, inv)
+    }
+
+    // Sends a prepared invocation to an actor, turning an error response into an error
+    pub(crate) fn deliver_to_actor(&self, actor: &str, inv: Invocation) -> Result<Vec<u8>> {
         let tgt_subject = bus::actor_subject(self.ns.as_ref().map(String::as_str), actor);
         match self.bus.invoke(&tgt_subject, inv) {
+            Ok(InvocationResponse {
+                invocation_id,
+                error: Some(reason),
+                error_code,
+                ..
+            }) => Err(errors::new(errors::ErrorKind::Invocation {
+                invocation_id,
+                code: error_code.unwrap_or(InvocationErrorCode::Other),
+                reason,
+            })),
             Ok(resp) => Ok(resp.msg),
             Err(e) => Err(e),
         }
@@ -714,9 +1492,15 @@ impl Host {
     }
 
     /// Applies a manifest JSON or YAML file to set up a host's actors, capability providers,
-    /// and actor bindings
+    /// and actor bindings. If the manifest refers to an authorization policy, the policy
+    /// becomes the host's authorizer
     #[cfg(feature = "manifest")]
     pub fn apply_manifest(&self, manifest: HostManifest) -> Result<()> {
+        if let Some(ref path) = manifest.authorization {
+            let authorizer = PolicyAuthorizer::from_path(path)?;
+            *self.authorizer.write().unwrap() = Box::new(authorizer);
+            info!("Applied authorization policy {}", path);
+        }
         {
             let mut labels = self.labels.write().unwrap();
             for (label, label_value) in manifest.labels {
@@ -734,7 +1518,7 @@ impl Host {
         }
         for cap in manifest.capabilities {
             // for now, supports only file paths
-            self.add_native_capability(NativeCapability::from_file(cap.path, cap.binding_name)?)?;
+            self.add_native_capability_file(cap.path, cap.binding_name)?;
         }
         for config in manifest.bindings {
             self.set_binding(
@@ -811,8 +1595,154 @@ impl Host {
         Ok(())
     }
 
+    /// Shuts down the host and blocks until it has stopped or until the timeout expires. The
+    /// host first stops accepting new invocations for its actors and waits for the invocations
+    /// already in flight to finish. It then removes every actor and capability provider, waits
+    /// for their threads to exit, and finally disconnects from the message bus. The returned
+    /// report lists anything that had not stopped in time
+    pub fn shutdown_graceful(&self, timeout: Duration) -> Result<ShutdownReport> {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+
+        self.bus.drain();
+        wait_until(deadline, || self.bus.in_flight() == 0);
+        report.pending_invocations = self.bus.in_flight();
+
+        let actors: Vec<_> = self.claims.read().unwrap().keys().cloned().collect();
+        for pk in actors {
+            if let Err(e) = self.remove_actor(&pk) {
+                warn!("Failed to remove actor {} during shutdown: {}", pk, e);
+            }
+        }
+        for (binding_name, capid) in self.capabilities().keys() {
+            if let Err(e) = self.remove_native_capability(capid, Some(binding_name.to_string())) {
+                warn!(
+                    "Failed to remove capability provider {},{} during shutdown: {}",
+                    capid, binding_name, e
+                );
+            }
+        }
+
+        // The bus's own handlers only terminate when it disconnects
+        let control = self.bus.controlplane_subject();
+        let unterminated = || -> Vec<String> {
+            self.terminators
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|k| Some(*k) != control.as_ref())
+                .cloned()
+                .collect()
+        };
+        wait_until(deadline, || unterminated().is_empty());
+        report.unterminated = unterminated();
+
+        wait_until(deadline, || {
+            self.threads
+                .read()
+                .unwrap()
+                .iter()
+                .all(|(_, h)| h.is_finished())
+        });
+        let threads: ThreadList = self.threads.write().unwrap().drain(..).collect();
+        for (label, handle) in threads {
+            if handle.is_finished() {
+                if handle.join().is_err() {
+                    warn!("Thread for {} panicked during shutdown", label);
+                }
+            } else {
+                report.unjoined.push(label);
+            }
+        }
+
+        self.bus.disconnect();
+        Ok(report)
+    }
+
+    /// Subscribes to the events of this host: actors starting, stopping, and restarting,
+    /// capability providers loading and unloading, bindings being created and removed, and
+    /// invocations failing. Each call returns a new channel that receives every event raised
+    /// after the subscription is made. Events are delivered in every build mode, in addition
+    /// to being published on the lattice in lattice mode
+    pub fn events(&self) -> Receiver<HostEvent> {
+        self.bus.events()
+    }
+
     /// Returns the public key of the host
     pub fn id(&self) -> String {
         self.key.public_key()
     }
 }
+
+// A host thread, which can be checked for having finished without joining it
+pub(crate) struct HostThread {
+    handle: JoinHandle<()>,
+    // Disconnected once the thread has exited, whether or not it panicked
+    done: Receiver<()>,
+}
+
+impl HostThread {
+    pub(crate) fn spawn<F: FnOnce() + Send + 'static>(f: F) -> HostThread {
+        let (done_s, done) = crossbeam_channel::bounded::<()>(0);
+        let handle = thread::spawn(move || {
+            let _done = done_s;
+            f()
+        });
+        HostThread { handle, done }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.done.try_recv(),
+            Err(crossbeam_channel::TryRecvError::Disconnected)
+        )
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.handle.join()
+    }
+}
+
+// Remembers a host thread so that a graceful shutdown can wait for it, forgetting any
+// threads that have already finished
+fn track_thread(threads: &RwLock<ThreadList>, label: &str, handle: HostThread) {
+    let mut lock = threads.write().unwrap();
+    lock.retain(|(_, h)| !h.is_finished());
+    lock.push((label.to_string(), handle));
+}
+
+// Polls the condition until it holds or the deadline passes, returning the final outcome
+fn wait_until(deadline: Instant, cond: impl Fn() -> bool) -> bool {
+    loop {
+        if cond() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep((deadline - now).min(DRAIN_POLL));
+    }
+}
+
+// Releases whatever a replacement provider that is about to be discarded has provisioned for
+// the actors it was bound to
+fn unbind_replacement(capability: &NativeCapability, bound: &[CapabilityConfiguration]) {
+    for config in bound {
+        let cfg = CapabilityConfiguration {
+            module: config.module.to_string(),
+            values: HashMap::new(),
+        };
+        let res = serialize(&cfg).and_then(|buf| {
+            capability
+                .plugin
+                .handle_call(SYSTEM_ACTOR, OP_REMOVE_ACTOR, &buf)
+        });
+        if let Err(e) = res {
+            warn!(
+                "Failed to unbind actor {} from discarded replacement provider: {}",
+                config.module, e
+            );
+        }
+    }
+}
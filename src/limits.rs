@@ -0,0 +1,308 @@
+// Resource limits for actor sandboxes. Limits are applied by rewriting the actor's module before it
+// is handed to an engine provider, so they are enforced the same way by every engine: memory and
+// table maximums become part of the module's own declarations, and the fuel budget is metered by
+// instructions injected into each function and loop
+
+use crate::errors::{self, ErrorKind};
+use crate::Result;
+use parity_wasm::elements::{
+    BlockType, External, GlobalEntry, GlobalSection, GlobalType, InitExpr, Instruction,
+    Instructions, Internal, MemoryType, Module, Section, TableType, ValueType,
+};
+
+const WASM_PAGE_SIZE: u64 = 65536;
+const GUEST_CALL: &str = "__guest_call";
+
+/// Limits on the resources an actor's WebAssembly module may consume. Limits that are not set
+/// leave the module's own declarations in place
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// The maximum size of the actor's linear memory in bytes, rounded down to whole 64KiB pages
+    pub max_memory_bytes: Option<u64>,
+    /// The maximum number of elements in each of the actor's tables
+    pub max_table_elements: Option<u32>,
+    /// The number of instructions (approximately) the actor may execute per invocation. An
+    /// invocation that runs out of fuel traps and fails
+    pub fuel: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn new() -> ResourceLimits {
+        ResourceLimits::default()
+    }
+
+    /// Sets the maximum size of the actor's linear memory, in bytes
+    pub fn with_max_memory(self, bytes: u64) -> ResourceLimits {
+        ResourceLimits {
+            max_memory_bytes: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of elements in each of the actor's tables
+    pub fn with_max_table_elements(self, elements: u32) -> ResourceLimits {
+        ResourceLimits {
+            max_table_elements: Some(elements),
+            ..self
+        }
+    }
+
+    /// Sets the instruction budget for each invocation of the actor
+    pub fn with_fuel(self, fuel: u64) -> ResourceLimits {
+        ResourceLimits {
+            fuel: Some(fuel),
+            ..self
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &ResourceLimits::default()
+    }
+}
+
+/// Rewrites the module bytes so that the module cannot exceed the given limits. Fails if
+/// the module's initial requirements are already beyond the limits
+pub(crate) fn apply_limits(buf: &[u8], limits: &ResourceLimits) -> Result<Vec<u8>> {
+    if limits.is_empty() {
+        return Ok(buf.to_vec());
+    }
+    let mut module = Module::from_bytes(buf).map_err(|e| wasm_error(&e))?;
+
+    if let Some(bytes) = limits.max_memory_bytes {
+        limit_memories(&mut module, (bytes / WASM_PAGE_SIZE) as u32)?;
+    }
+    if let Some(elements) = limits.max_table_elements {
+        limit_tables(&mut module, elements)?;
+    }
+    if let Some(fuel) = limits.fuel {
+        meter_fuel(&mut module, fuel)?;
+    }
+
+    module.to_bytes().map_err(|e| wasm_error(&e))
+}
+
+fn limit_memories(module: &mut Module, max_pages: u32) -> Result<()> {
+    let limit = |mem: &MemoryType| {
+        let initial = mem.limits().initial();
+        if initial > max_pages {
+            Err(errors::new(ErrorKind::MiscHost(format!(
+                "Module requires {} pages of memory, which exceeds the limit of {} pages",
+                initial, max_pages
+            ))))
+        } else {
+            let max = mem
+                .limits()
+                .maximum()
+                .map_or(max_pages, |m| m.min(max_pages));
+            Ok(MemoryType::new(initial, Some(max)))
+        }
+    };
+    if let Some(imports) = module.import_section_mut() {
+        for entry in imports.entries_mut() {
+            if let External::Memory(ref mut mem) = entry.external_mut() {
+                *mem = limit(mem)?;
+            }
+        }
+    }
+    if let Some(memories) = module.memory_section_mut() {
+        for mem in memories.entries_mut() {
+            *mem = limit(mem)?;
+        }
+    }
+    Ok(())
+}
+
+fn limit_tables(module: &mut Module, max_elements: u32) -> Result<()> {
+    let limit = |table: &TableType| {
+        let initial = table.limits().initial();
+        if initial > max_elements {
+            Err(errors::new(ErrorKind::MiscHost(format!(
+                "Module requires a table of {} elements, which exceeds the limit of {}",
+                initial, max_elements
+            ))))
+        } else {
+            let max = table
+                .limits()
+                .maximum()
+                .map_or(max_elements, |m| m.min(max_elements));
+            Ok(TableType::new(initial, Some(max)))
+        }
+    };
+    if let Some(imports) = module.import_section_mut() {
+        for entry in imports.entries_mut() {
+            if let External::Table(ref mut table) = entry.external_mut() {
+                *table = limit(table)?;
+            }
+        }
+    }
+    if let Some(tables) = module.table_section_mut() {
+        for table in tables.entries_mut() {
+            *table = limit(table)?;
+        }
+    }
+    Ok(())
+}
+
+// Adds a global fuel counter that the guest call entry point refills, and charges each function
+// body and loop iteration the number of instructions it contains. Running out of fuel traps
+fn meter_fuel(module: &mut Module, fuel: u64) -> Result<()> {
+    let fuel = fuel.min(i64::MAX as u64) as i64;
+    let imported_funcs = module.import_section().map_or(0, |s| s.functions());
+    let entry_point = module
+        .export_section()
+        .and_then(|s| {
+            s.entries().iter().find_map(|e| match e.internal() {
+                Internal::Function(idx) if e.field() == GUEST_CALL => Some(*idx as usize),
+                _ => None,
+            })
+        })
+        .ok_or_else(|| {
+            errors::new(ErrorKind::MiscHost(format!(
+                "Module does not export {}",
+                GUEST_CALL
+            )))
+        })?;
+
+    let global = module.globals_space() as u32;
+    if module.global_section().is_none() {
+        module
+            .insert_section(Section::Global(GlobalSection::with_entries(vec![])))
+            .map_err(|e| wasm_error(&e))?;
+    }
+    module
+        .global_section_mut()
+        .unwrap()
+        .entries_mut()
+        .push(GlobalEntry::new(
+            GlobalType::new(ValueType::I64, true),
+            InitExpr::new(vec![Instruction::I64Const(fuel), Instruction::End]),
+        ));
+
+    if let Some(code) = module.code_section_mut() {
+        for (idx, body) in code.bodies_mut().iter_mut().enumerate() {
+            let mut metered = meter_body(body.code().elements(), global);
+            if idx + imported_funcs == entry_point {
+                let refill = vec![Instruction::I64Const(fuel), Instruction::SetGlobal(global)];
+                metered.splice(0..0, refill);
+            }
+            *body.code_mut() = Instructions::new(metered);
+        }
+    }
+    Ok(())
+}
+
+fn meter_body(code: &[Instruction], global: u32) -> Vec<Instruction> {
+    // The cost of each metered block (the function body and every loop), keyed by the index
+    // of the instruction that opens it. The function body itself opens "before" instruction 0
+    let mut costs = vec![0_i64; code.len() + 1];
+    let mut frames: Vec<Option<usize>> = vec![Some(0)];
+    for (idx, instr) in code.iter().enumerate() {
+        if let Some(Some(open)) = frames.iter().rev().find(|f| f.is_some()) {
+            costs[*open] += 1;
+        }
+        match instr {
+            Instruction::Loop(_) => frames.push(Some(idx + 1)),
+            Instruction::Block(_) | Instruction::If(_) => frames.push(None),
+            Instruction::End => {
+                frames.pop();
+            }
+            _ => {}
+        }
+    }
+
+    let mut metered = charge(costs[0], global);
+    for (idx, instr) in code.iter().enumerate() {
+        metered.push(instr.clone());
+        if let Instruction::Loop(_) = instr {
+            metered.extend(charge(costs[idx + 1], global));
+        }
+    }
+    metered
+}
+
+fn charge(cost: i64, global: u32) -> Vec<Instruction> {
+    vec![
+        Instruction::GetGlobal(global),
+        Instruction::I64Const(cost),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(global),
+        Instruction::GetGlobal(global),
+        Instruction::I64Const(0),
+        Instruction::I64LtS,
+        Instruction::If(BlockType::NoResult),
+        Instruction::Unreachable,
+        Instruction::End,
+    ]
+}
+
+fn wasm_error(e: &parity_wasm::elements::Error) -> errors::Error {
+    errors::new(ErrorKind::MiscHost(format!(
+        "Failed to apply resource limits to module: {}",
+        e
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_limits, ResourceLimits};
+    use parity_wasm::builder;
+    use parity_wasm::elements::{Instruction, Instructions, Module, ValueType};
+
+    fn test_module(initial_pages: u32, max_pages: Option<u32>) -> Vec<u8> {
+        let module = builder::module()
+            .memory()
+            .with_min(initial_pages)
+            .with_max(max_pages)
+            .build()
+            .function()
+            .signature()
+            .params()
+            .i32()
+            .i32()
+            .build()
+            .with_return_type(Some(ValueType::I32))
+            .build()
+            .body()
+            .with_instructions(Instructions::new(vec![
+                Instruction::I32Const(1),
+                Instruction::End,
+            ]))
+            .build()
+            .build()
+            .export()
+            .field("__guest_call")
+            .internal()
+            .func(0)
+            .build()
+            .build();
+        module.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn memory_maximum_is_capped() {
+        let buf = test_module(1, None);
+        let limits = ResourceLimits::new().with_max_memory(4 * 65536);
+        let module = Module::from_bytes(apply_limits(&buf, &limits).unwrap()).unwrap();
+        let mem = &module.memory_section().unwrap().entries()[0];
+        assert_eq!(1, mem.limits().initial());
+        assert_eq!(Some(4), mem.limits().maximum());
+    }
+
+    #[test]
+    fn initial_memory_beyond_limit_is_rejected() {
+        let buf = test_module(8, Some(16));
+        let limits = ResourceLimits::new().with_max_memory(4 * 65536);
+        assert!(apply_limits(&buf, &limits).is_err());
+    }
+
+    #[test]
+    fn fuel_counter_is_injected() {
+        let buf = test_module(1, None);
+        let limits = ResourceLimits::new().with_fuel(1000);
+        let module = Module::from_bytes(apply_limits(&buf, &limits).unwrap()).unwrap();
+        assert_eq!(1, module.global_section().unwrap().entries().len());
+        let code = module.code_section().unwrap().bodies()[0].code().elements();
+        assert_eq!(Instruction::I64Const(1000), code[0]);
+        assert_eq!(Instruction::SetGlobal(0), code[1]);
+    }
+}
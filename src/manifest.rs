@@ -6,6 +6,11 @@ pub struct HostManifest {
     #[serde(default)]
     #[serde(skip_serializing_if = "HashMap::is_empty")]
     pub labels: HashMap<String, String>,
+    /// The path to an authorization policy file (see `PolicyAuthorizer`). When present, the
+    /// policy replaces the host's authorizer before any actor in the manifest is added
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<String>,
     pub actors: Vec<String>,
     pub capabilities: Vec<Capability>,
     pub bindings: Vec<BindingEntry>,
@@ -77,6 +82,7 @@ mod test {
     fn round_trip() {
         let manifest = super::HostManifest {
             labels: HashMap::new(),
+            authorization: None,
             actors: vec!["a".to_string(), "b".to_string(), "c".to_string()],
             capabilities: vec![
                 Capability {
@@ -107,6 +113,7 @@ mod test {
                 hm.insert("test".to_string(), "value".to_string());
                 hm
             },
+            authorization: None,
             actors: vec!["a".to_string(), "b".to_string(), "c".to_string()],
             capabilities: vec![
                 Capability {
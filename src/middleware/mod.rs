@@ -1,5 +1,5 @@
 use crate::Result;
-use crate::{plugins::PluginManager, Invocation, InvocationResponse};
+use crate::{plugins::PluginManager, Invocation, InvocationErrorCode, InvocationResponse};
 use std::sync::Arc;
 use std::sync::RwLock;
 use wapc::WapcHost;
@@ -150,7 +150,11 @@ fn run_actor_invoke(
 ) -> Result<InvocationResponse> {
     let invoke_operation = |inv: Invocation| match guest.call(&inv.operation, &inv.msg) {
         Ok(v) => InvocationResponse::success(&inv, v),
-        Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke actor: {}", e)),
+        Err(e) => InvocationResponse::error_with_code(
+            &inv,
+            InvocationErrorCode::GuestFailure,
+            &format!("failed to invoke actor: {}", e),
+        ),
     };
 
     run_invoke(middlewares, inv, &invoke_operation)
@@ -191,7 +195,11 @@ pub(crate) fn run_native_capability_invoke(
 ) -> Result<InvocationResponse> {
     let invoke_operation = |inv: Invocation| match plugins.call(&inv) {
         Ok(r) => r,
-        Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke capability: {}", e)),
+        Err(e) => InvocationResponse::error_with_code(
+            &inv,
+            InvocationErrorCode::ProviderFailure,
+            &format!("failed to invoke capability: {}", e),
+        ),
     };
 
     run_invoke(middlewares, inv, &invoke_operation)
@@ -204,7 +212,11 @@ pub(crate) fn run_portable_capability_invoke(
 ) -> Result<InvocationResponse> {
     let invoke_operation = |inv: Invocation| match guest.call(&inv.operation, &inv.msg) {
         Ok(v) => InvocationResponse::success(&inv, v),
-        Err(e) => InvocationResponse::error(&inv, &format!("failed to invoke capability: {}", e)),
+        Err(e) => InvocationResponse::error_with_code(
+            &inv,
+            InvocationErrorCode::ProviderFailure,
+            &format!("failed to invoke capability: {}", e),
+        ),
     };
 
     run_invoke(middlewares, inv, &invoke_operation)
@@ -215,8 +227,9 @@ fn run_invoke(
     inv: Invocation,
     invoke_operation: &dyn Fn(Invocation) -> InvocationResponse,
 ) -> Result<InvocationResponse> {
-    let mut cur_resp = Ok(InvocationResponse::error(
+    let mut cur_resp = Ok(InvocationResponse::error_with_code(
         &inv,
+        InvocationErrorCode::MiddlewareFailure,
         "No middleware invoked the operation",
     ));
 
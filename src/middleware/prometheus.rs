@@ -881,6 +881,7 @@ mod tests {
             msg: "response".as_bytes().to_vec(),
             error: None,
             invocation_id: id.clone(),
+            error_code: None,
         }
     }
 
@@ -73,6 +73,19 @@ impl PluginManager {
         }
     }
 
+    /// Puts a plugin in the place of the one registered under the same binding and capability
+    /// ID, returning the plugin it replaced
+    pub fn replace_plugin(&mut self, plugin: NativeCapability) -> Result<NativeCapability> {
+        let key = RouteKey::new(&plugin.binding_name, &plugin.id());
+        match self.plugins.get_mut(&key) {
+            Some(existing) => Ok(std::mem::replace(existing, plugin)),
+            None => Err(errors::new(ErrorKind::CapabilityProvider(format!(
+                "Attempt to replace non-existent plugin {:?}",
+                key
+            )))),
+        }
+    }
+
     pub fn remove_plugin(&mut self, binding: &str, capid: &str) -> Result<()> {
         let key = RouteKey::new(&binding, &capid);
         if let Some(plugin) = self.plugins.remove(&key) {
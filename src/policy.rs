@@ -0,0 +1,259 @@
+// A built-in authorizer whose rules are read from a YAML or JSON policy document, so that
+// hosts can be locked down without writing an `Authorizer` of their own
+
+use crate::errors::{self, ErrorKind};
+use crate::{Authorizer, ProviderProvenance, Result, WasccEntity};
+use std::collections::HashMap;
+use std::{fs::File, io::Read, path::Path};
+use wascap::jwt::{Actor, Claims};
+use wascc_codec::capabilities::CapabilityDescriptor;
+use wascc_codec::core::OP_BIND_ACTOR;
+
+const ANY: &str = "*";
+
+/// The rules enforced by a `PolicyAuthorizer`. Every rule is optional, and a policy with
+/// no rules permits everything that the host itself permits
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AuthorizationPolicy {
+    /// The accounts whose actors may be loaded. When empty, actors from any issuer are loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_issuers: Vec<String>,
+    /// The public keys of actors that may neither be loaded nor invoke anything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub banned_subjects: Vec<String>,
+    /// The tags that an actor's claims must all contain for it to be loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_tags: Vec<String>,
+    /// The capabilities that the actors of each issuer may claim and invoke. Actors from
+    /// issuers that aren't listed may use any capability
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub issuer_capabilities: HashMap<String, Vec<String>>,
+    /// The targets (capability IDs or actor public keys) that actors may invoke, and the
+    /// operations they may invoke on each. When empty, any target may be invoked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_targets: Vec<TargetRule>,
+    /// The capability providers that may be loaded. When empty, any provider may be loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_providers: Vec<ProviderRule>,
+}
+
+/// Permits invoking some or all of the operations of a target
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TargetRule {
+    /// A capability ID, an actor's public key, or `*` for any target
+    pub target: String,
+    /// The permitted operations. When empty, or when it contains `*`, any operation is permitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub operations: Vec<String>,
+}
+
+/// Permits loading capability providers with a given capability ID
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProviderRule {
+    /// A capability ID, or `*` for any provider
+    pub capid: String,
+    /// The permitted versions, as reported in the provider's descriptor. When empty, any
+    /// version is permitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+    /// The permitted hashes of the provider's library or module. When empty, any provider is
+    /// permitted, otherwise providers whose hash isn't known (e.g. embedded ones) are refused
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hashes: Vec<String>,
+}
+
+/// An authorizer that enforces an `AuthorizationPolicy`. Like any authorizer, it can only
+/// deny what the host would otherwise permit
+#[derive(Debug, Clone)]
+pub struct PolicyAuthorizer {
+    policy: AuthorizationPolicy,
+}
+
+impl PolicyAuthorizer {
+    pub fn new(policy: AuthorizationPolicy) -> PolicyAuthorizer {
+        PolicyAuthorizer { policy }
+    }
+
+    /// Loads the policy from a file. As with the host manifest, YAML is expected for files
+    /// with a `.yaml` or `.yml` extension or no extension at all, and JSON otherwise
+    pub fn from_path(path: impl AsRef<Path>) -> Result<PolicyAuthorizer> {
+        let mut contents = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut contents)?;
+        let yaml = match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some(e) => matches!(e.to_lowercase().as_str(), "yaml" | "yml"),
+            None => true,
+        };
+        let policy = if yaml {
+            serde_yaml::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
+        };
+        policy.map(PolicyAuthorizer::new).map_err(|e| {
+            errors::new(ErrorKind::Serialization(format!(
+                "Failed to read authorization policy {}: {}",
+                path.as_ref().display(),
+                e
+            )))
+        })
+    }
+
+    /// The policy being enforced
+    pub fn policy(&self) -> &AuthorizationPolicy {
+        &self.policy
+    }
+
+    fn is_banned(&self, claims: &Claims<Actor>) -> bool {
+        self.policy.banned_subjects.contains(&claims.subject)
+    }
+
+    fn issuer_may_use(&self, claims: &Claims<Actor>, capid: &str) -> bool {
+        match self.policy.issuer_capabilities.get(&claims.issuer) {
+            Some(caps) => caps.iter().any(|c| c == capid),
+            None => true,
+        }
+    }
+
+    fn target_permits(&self, target: &str, operation: &str) -> bool {
+        self.policy.allowed_targets.is_empty()
+            || self.policy.allowed_targets.iter().any(|r| {
+                (r.target == target || r.target == ANY)
+                    && (r.operations.is_empty()
+                        || r.operations.iter().any(|o| o == operation || o == ANY))
+            })
+    }
+}
+
+impl Authorizer for PolicyAuthorizer {
+    fn can_load(&self, claims: &Claims<Actor>) -> bool {
+        let metadata = claims.metadata.as_ref();
+        let tags = metadata.and_then(|m| m.tags.clone()).unwrap_or_default();
+        let caps = metadata.and_then(|m| m.caps.clone()).unwrap_or_default();
+        !self.is_banned(claims)
+            && (self.policy.trusted_issuers.is_empty()
+                || self.policy.trusted_issuers.contains(&claims.issuer))
+            && self.policy.required_tags.iter().all(|t| tags.contains(t))
+            && caps.iter().all(|c| self.issuer_may_use(claims, c))
+    }
+
+    // Bindings are requested by the host rather than the actor, so they are subject to the
+    // issuer's capabilities but not to the target rules
+    fn can_invoke(&self, claims: &Claims<Actor>, target: &WasccEntity, operation: &str) -> bool {
+        if self.is_banned(claims) {
+            return false;
+        }
+        match target {
+            WasccEntity::Capability { capid, .. } => {
+                self.issuer_may_use(claims, capid)
+                    && (operation == OP_BIND_ACTOR || self.target_permits(capid, operation))
+            }
+            WasccEntity::Actor(pk) => self.target_permits(pk, operation),
+        }
+    }
+
+    fn can_load_provider(
+        &self,
+        descriptor: &CapabilityDescriptor,
+        provenance: &ProviderProvenance,
+    ) -> bool {
+        self.policy.allowed_providers.is_empty()
+            || self.policy.allowed_providers.iter().any(|r| {
+                (r.capid == descriptor.id || r.capid == ANY)
+                    && (r.versions.is_empty() || r.versions.contains(&descriptor.version))
+                    && (r.hashes.is_empty()
+                        || matches!(&provenance.hash, Some(h) if r.hashes.contains(h)))
+            })
+    }
+
+    // The capability ID and version aren't known until the library is loaded, so only the
+    // hashes can rule a library out beforehand
+    fn can_load_provider_file(&self, provenance: &ProviderProvenance) -> bool {
+        self.policy.allowed_providers.is_empty()
+            || self.policy.allowed_providers.iter().any(|r| {
+                r.hashes.is_empty() || matches!(&provenance.hash, Some(h) if r.hashes.contains(h))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PolicyAuthorizer;
+    use crate::authz::test_claims;
+    use crate::{Authorizer, ProviderProvenance, WasccEntity};
+    use wascc_codec::capabilities::CapabilityDescriptor;
+
+    const POLICY: &str = r#"
+trusted_issuers:
+  - ATRUSTED
+banned_subjects:
+  - MBANNED
+required_tags:
+  - approved
+issuer_capabilities:
+  ATRUSTED:
+    - "wascc:keyvalue"
+    - "wascc:http_server"
+allowed_targets:
+  - target: "wascc:keyvalue"
+    operations: [Get, Set]
+  - target: "wascc:http_server"
+allowed_providers:
+  - capid: "wascc:keyvalue"
+    versions: ["0.1.0"]
+    hashes: [ABC123]
+"#;
+
+    fn cap(capid: &str) -> WasccEntity {
+        WasccEntity::Capability {
+            capid: capid.to_string(),
+            binding: "default".to_string(),
+        }
+    }
+
+    #[test]
+    fn policy_rules_are_enforced() {
+        let authz = PolicyAuthorizer::new(serde_yaml::from_str(POLICY).unwrap());
+        let kv = &["wascc:keyvalue"];
+        assert!(authz.can_load(&test_claims("MGOOD", Some("ATRUSTED"), kv, &["approved"])));
+        assert!(!authz.can_load(&test_claims("MGOOD", Some("AOTHER"), kv, &["approved"])));
+        assert!(!authz.can_load(&test_claims("MBANNED", Some("ATRUSTED"), kv, &["approved"])));
+        assert!(!authz.can_load(&test_claims("MGOOD", Some("ATRUSTED"), kv, &[])));
+        assert!(!authz.can_load(&test_claims(
+            "MGOOD",
+            Some("ATRUSTED"),
+            &["wascc:messaging"],
+            &["approved"]
+        )));
+
+        let good = test_claims("MGOOD", Some("ATRUSTED"), kv, &["approved"]);
+        assert!(authz.can_invoke(&good, &cap("wascc:keyvalue"), "Get"));
+        assert!(!authz.can_invoke(&good, &cap("wascc:keyvalue"), "Del"));
+        assert!(authz.can_invoke(&good, &cap("wascc:keyvalue"), "BindActor"));
+        assert!(authz.can_invoke(&good, &cap("wascc:http_server"), "HandleRequest"));
+        assert!(!authz.can_invoke(&good, &cap("wascc:messaging"), "BindActor"));
+        assert!(!authz.can_invoke(&good, &WasccEntity::Actor("MOTHER".into()), "Op"));
+
+        let provider = |capid: &str, version: &str, hash: Option<&str>| {
+            let descriptor = CapabilityDescriptor::builder()
+                .id(capid)
+                .version(version)
+                .build();
+            let provenance = ProviderProvenance {
+                hash: hash.map(|h| h.to_string()),
+                ..Default::default()
+            };
+            authz.can_load_provider(&descriptor, &provenance)
+        };
+        assert!(provider("wascc:keyvalue", "0.1.0", Some("ABC123")));
+        assert!(!provider("wascc:keyvalue", "0.2.0", Some("ABC123")));
+        assert!(!provider("wascc:keyvalue", "0.1.0", Some("DEF456")));
+        assert!(!provider("wascc:keyvalue", "0.1.0", None));
+
+        let file = |hash: &str| ProviderProvenance {
+            hash: Some(hash.to_string()),
+            ..Default::default()
+        };
+        assert!(authz.can_load_provider_file(&file("ABC123")));
+        assert!(!authz.can_load_provider_file(&file("DEF456")));
+        assert!(!provider("wascc:messaging", "0.1.0", Some("ABC123")));
+    }
+}
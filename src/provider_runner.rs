@@ -0,0 +1,18 @@
+// Runs a single native capability provider on behalf of a host that loaded it with
+// `NativeCapability::from_file_isolated`. The host starts this binary with the path of the
+// provider library and of the socket to connect to
+
+#[cfg(unix)]
+fn main() {
+    env_logger::init();
+    if let Err(e) = wascc_host::serve_provider() {
+        eprintln!("Provider process failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("Isolated capability providers are only supported on Unix");
+    std::process::exit(1);
+}
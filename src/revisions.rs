@@ -0,0 +1,167 @@
+// The history of the revisions each actor has run in this host. The module bytes of the most
+// recent revisions are kept in memory so that an actor can be rolled back to them; older
+// revisions loaded from a file can still be restored as long as the file is unchanged
+
+use crate::errors::{self, ErrorKind};
+use crate::{authz, ActorSource, Result};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use wascap::jwt::Claims;
+
+// The number of revisions of each actor whose module bytes are kept in memory
+const CACHED_REVISIONS: usize = 5;
+
+/// A revision of an actor that has been loaded by the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActorRevision {
+    /// The public key of the actor
+    pub actor: String,
+    /// The revision number in the actor's claims
+    pub rev: Option<i32>,
+    /// The version string in the actor's claims
+    pub ver: Option<String>,
+    /// The hash of the module, as recorded in the actor's claims
+    pub module_hash: String,
+    /// When the revision became the actor's running module
+    pub loaded_at: SystemTime,
+    /// Where the module was obtained from
+    pub source: ActorSource,
+    /// Whether this is the revision the actor is currently running
+    pub current: bool,
+    /// Whether the module can be restored from memory or from disk
+    pub cached: bool,
+}
+
+struct Entry {
+    revision: ActorRevision,
+    bytes: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub(crate) struct RevisionHistory {
+    actors: HashMap<String, Vec<Entry>>,
+}
+
+impl RevisionHistory {
+    pub(crate) fn new() -> RevisionHistory {
+        RevisionHistory::default()
+    }
+
+    /// Records that the actor is now running the given module
+    pub(crate) fn record(
+        &mut self,
+        claims: &Claims<wascap::jwt::Actor>,
+        bytes: &[u8],
+        source: ActorSource,
+    ) {
+        let md = claims.metadata.as_ref();
+        let entries = self.actors.entry(claims.subject.to_string()).or_default();
+        entries.push(Entry {
+            revision: ActorRevision {
+                actor: claims.subject.to_string(),
+                rev: md.and_then(|m| m.rev),
+                ver: md.and_then(|m| m.ver.clone()),
+                module_hash: md.map(|m| m.module_hash.to_string()).unwrap_or_default(),
+                loaded_at: SystemTime::now(),
+                source,
+                current: false,
+                cached: false,
+            },
+            bytes: Some(bytes.to_vec()),
+        });
+        let evicted = entries.len().saturating_sub(CACHED_REVISIONS);
+        for e in entries.iter_mut().take(evicted) {
+            e.bytes = None;
+        }
+    }
+
+    /// Lists the revisions of an actor, oldest first. The most recent one is marked as
+    /// current if the actor is still running
+    pub(crate) fn list(&self, actor: &str, running: bool) -> Vec<ActorRevision> {
+        let entries = match self.actors.get(actor) {
+            Some(entries) => entries,
+            None => return vec![],
+        };
+        let last = entries.len() - 1;
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| ActorRevision {
+                current: running && i == last,
+                cached: e.bytes.is_some() || on_disk(&e.revision).is_some(),
+                ..e.revision.clone()
+            })
+            .collect()
+    }
+
+    /// Obtains the module bytes and source of the most recent entry for the given revision
+    pub(crate) fn module(&self, actor: &str, rev: i32) -> Result<(Vec<u8>, ActorSource)> {
+        let entry = self
+            .actors
+            .get(actor)
+            .and_then(|entries| entries.iter().rev().find(|e| e.revision.rev == Some(rev)))
+            .ok_or_else(|| {
+                errors::new(ErrorKind::MiscHost(format!(
+                    "Actor {} has no revision {}",
+                    actor, rev
+                )))
+            })?;
+        let bytes = match &entry.bytes {
+            Some(b) => b.clone(),
+            None => on_disk(&entry.revision).ok_or_else(|| {
+                errors::new(ErrorKind::MiscHost(format!(
+                    "Revision {} of actor {} is no longer cached",
+                    rev, actor
+                )))
+            })?,
+        };
+        Ok((bytes, entry.revision.source.clone()))
+    }
+}
+
+// Reads a revision's module back from the file it was loaded from, provided it hasn't changed
+fn on_disk(revision: &ActorRevision) -> Option<Vec<u8>> {
+    let path = match &revision.source {
+        ActorSource::File(path) => path,
+        _ => return None,
+    };
+    let bytes = std::fs::read(path).ok()?;
+    let token = authz::extract_claims(&bytes).ok()?;
+    let hash = token.claims.metadata.map(|m| m.module_hash);
+    if hash.as_deref() == Some(revision.module_hash.as_str()) {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RevisionHistory, CACHED_REVISIONS};
+    use crate::ActorSource;
+    use wascap::jwt::{Actor, ClaimsBuilder};
+
+    #[test]
+    fn old_revisions_are_evicted() {
+        let mut history = RevisionHistory::new();
+        for rev in 0..(CACHED_REVISIONS as i32 + 2) {
+            let claims = ClaimsBuilder::<Actor>::new()
+                .subject("MTEST")
+                .with_metadata(Actor {
+                    rev: Some(rev),
+                    module_hash: format!("HASH{}", rev),
+                    ..Default::default()
+                })
+                .build();
+            history.record(&claims, &[rev as u8], ActorSource::Slice);
+        }
+        let revisions = history.list("MTEST", true);
+        assert_eq!(CACHED_REVISIONS + 2, revisions.len());
+        assert!(!revisions[1].cached);
+        assert!(revisions[2].cached);
+        assert!(revisions.last().unwrap().current);
+        assert!(history.module("MTEST", 0).is_err());
+        assert_eq!(vec![6], history.module("MTEST", 6).unwrap().0);
+        assert!(history.list("MOTHER", true).is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+// The host's revocation list. Actors can be revoked by their public key (subject) or by the ID
+// (`jti`) of a single token, as of a given time. Tokens issued at or before that time are
+// refused when loading, and running actors holding one are terminated
+
+use crate::bus::MessageBus;
+#[cfg(feature = "lattice")]
+use crate::errors::{self, ErrorKind};
+use crate::inthost::{self, ActorInstances};
+use crate::HostEvent;
+use crossbeam::Sender;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wascap::jwt::Claims;
+
+/// What a revocation applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "lattice", derive(serde::Serialize, serde::Deserialize))]
+pub enum RevokedEntity {
+    /// The actor with this public key
+    Actor(String),
+    /// The token with this ID (`jti`)
+    Token(String),
+}
+
+/// An entry in the host's revocation list
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "lattice", derive(serde::Serialize, serde::Deserialize))]
+pub struct Revocation {
+    /// The actor or token that is revoked
+    pub entity: RevokedEntity,
+    /// Tokens issued at or before this time (in seconds since the epoch) are revoked. Tokens
+    /// issued afterwards for a revoked actor, e.g. once it has been re-signed, are accepted
+    pub revoked_at: u64,
+}
+
+impl Revocation {
+    /// Revokes the tokens issued so far for the actor with the given public key
+    pub fn actor(pk: &str) -> Revocation {
+        Revocation {
+            entity: RevokedEntity::Actor(pk.to_string()),
+            revoked_at: now(),
+        }
+    }
+
+    /// Revokes the token with the given ID (`jti`)
+    pub fn token(jti: &str) -> Revocation {
+        Revocation {
+            entity: RevokedEntity::Token(jti.to_string()),
+            revoked_at: now(),
+        }
+    }
+
+    /// Sets the time of the revocation, in seconds since the epoch
+    pub fn with_revoked_at(self, revoked_at: u64) -> Revocation {
+        Revocation { revoked_at, ..self }
+    }
+
+    /// Indicates whether the revocation applies to the given token
+    pub fn applies_to(&self, claims: &Claims<wascap::jwt::Actor>) -> bool {
+        let matches = match &self.entity {
+            RevokedEntity::Actor(pk) => *pk == claims.subject,
+            RevokedEntity::Token(jti) => *jti == claims.id,
+        };
+        matches && claims.issued_at <= self.revoked_at
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RevocationList {
+    revocations: HashMap<RevokedEntity, u64>,
+}
+
+impl RevocationList {
+    pub(crate) fn new() -> RevocationList {
+        RevocationList::default()
+    }
+
+    /// Adds a revocation to the list, returning false if the list already contained it
+    pub(crate) fn revoke(&mut self, revocation: &Revocation) -> bool {
+        match self.revocations.get(&revocation.entity) {
+            Some(at) if *at >= revocation.revoked_at => false,
+            _ => {
+                self.revocations
+                    .insert(revocation.entity.clone(), revocation.revoked_at);
+                true
+            }
+        }
+    }
+
+    /// Removes a revocation from the list, returning false if there was none
+    pub(crate) fn remove(&mut self, entity: &RevokedEntity) -> bool {
+        self.revocations.remove(entity).is_some()
+    }
+
+    /// Finds the revocation, if any, that applies to the given token
+    pub(crate) fn find(&self, claims: &Claims<wascap::jwt::Actor>) -> Option<Revocation> {
+        self.list().into_iter().find(|r| r.applies_to(claims))
+    }
+
+    pub(crate) fn list(&self) -> Vec<Revocation> {
+        self.revocations
+            .iter()
+            .map(|(entity, revoked_at)| Revocation {
+                entity: entity.clone(),
+                revoked_at: *revoked_at,
+            })
+            .collect()
+    }
+}
+
+/// Adds a revocation to the list and terminates the running actors it applies to. Stopping
+/// an actor's last instance removes its bindings. Returns false if the list already
+/// contained the revocation
+pub(crate) fn apply(
+    revocation: &Revocation,
+    revocations: &RwLock<RevocationList>,
+    claims: &RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>,
+    instances: &RwLock<HashMap<String, ActorInstances>>,
+    terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
+    bus: &MessageBus,
+) -> bool {
+    if !revocations.write().unwrap().revoke(revocation) {
+        return false;
+    }
+    let revoked: Vec<String> = claims
+        .read()
+        .unwrap()
+        .values()
+        .filter(|c| revocation.applies_to(c))
+        .map(|c| c.subject.to_string())
+        .collect();
+    for actor in revoked {
+        warn!("Terminating actor {}, its token has been revoked", actor);
+        bus.emit(HostEvent::ActorRevoked {
+            actor: actor.to_string(),
+        });
+        let count = instances
+            .read()
+            .unwrap()
+            .get(&actor)
+            .map_or(1, |rec| rec.count.max(1));
+        if let Err(e) =
+            inthost::stop_actor_instances(terminators.clone(), &bus.actor_subject(&actor), count)
+        {
+            error!("Failed to terminate revoked actor {}: {}", actor, e);
+        }
+    }
+    true
+}
+
+// Revocations travel through the lattice as JWTs whose `wascap` claims hold the revocation,
+// so that hosts can check who issued them before terminating anything
+#[cfg(feature = "lattice")]
+impl wascap::jwt::WascapEntity for Revocation {
+    fn name(&self) -> String {
+        match &self.entity {
+            RevokedEntity::Actor(pk) => pk.to_string(),
+            RevokedEntity::Token(jti) => jti.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "lattice")]
+impl Revocation {
+    /// Produces a token for the revocation signed with the given issuer's key. Hosts in the
+    /// lattice only apply revocations signed by one of their trusted issuers
+    pub fn sign(&self, issuer: &wascap::prelude::KeyPair) -> crate::Result<String> {
+        use wascap::jwt::WascapEntity;
+        let claims = Claims {
+            expires: None,
+            id: uuid::Uuid::new_v4().to_string(),
+            issued_at: now(),
+            issuer: issuer.public_key(),
+            subject: self.name(),
+            not_before: None,
+            metadata: Some(self.clone()),
+        };
+        Ok(claims.encode(issuer)?)
+    }
+}
+
+/// Decodes a revocation token received from the lattice, refusing it unless it carries a
+/// valid signature from one of the trusted issuers. A host without trusted issuers accepts
+/// no revocations from the lattice
+#[cfg(feature = "lattice")]
+pub(crate) fn verify(token: &str, trusted: &[String]) -> crate::Result<Revocation> {
+    let refuse = |reason: String| Err(errors::new(ErrorKind::Authorization(reason)));
+    // Decoding first rejects malformed tokens, which the validation doesn't expect
+    let claims = Claims::<Revocation>::decode(token)?;
+    let validation = wascap::jwt::validate_token::<Revocation>(token)?;
+    if !validation.signature_valid || validation.expired || validation.cannot_use_yet {
+        return refuse("Revocation token is not valid".to_string());
+    }
+    if !trusted.contains(&claims.issuer) {
+        return refuse(format!(
+            "Revocation is signed by {}, which is not a trusted issuer",
+            claims.issuer
+        ));
+    }
+    match claims.metadata {
+        Some(revocation) => Ok(revocation),
+        None => refuse("Revocation token does not contain a revocation".to_string()),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Revocation, RevocationList, RevokedEntity};
+    use crate::authz::test_claims;
+    use wascap::jwt::{Actor, Claims};
+
+    fn claims(subject: &str, issued_at: u64) -> Claims<Actor> {
+        Claims {
+            issued_at,
+            ..test_claims(subject, None, &[], &[])
+        }
+    }
+
+    #[test]
+    fn revocations_apply_to_earlier_tokens() {
+        let mut list = RevocationList::new();
+        assert!(list.revoke(&Revocation::actor("MA").with_revoked_at(100)));
+        assert!(!list.revoke(&Revocation::actor("MA").with_revoked_at(50)));
+        assert!(list.find(&claims("MA", 100)).is_some());
+        assert!(list.find(&claims("MA", 101)).is_none());
+        assert!(list.find(&claims("MB", 10)).is_none());
+
+        let token = claims("MB", 10);
+        assert!(list.revoke(&Revocation::token(&token.id)));
+        assert_eq!(
+            Some(RevokedEntity::Token(token.id.to_string())),
+            list.find(&token).map(|r| r.entity)
+        );
+        assert_eq!(2, list.list().len());
+        assert!(list.remove(&RevokedEntity::Actor("MA".into())));
+        assert!(list.find(&claims("MA", 100)).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "lattice")]
+    fn lattice_revocations_need_a_trusted_signature() {
+        use super::verify;
+        use wascap::prelude::KeyPair;
+
+        let issuer = KeyPair::new_account();
+        let trusted = vec![issuer.public_key()];
+        let revocation = Revocation::actor("MA").with_revoked_at(100);
+        let token = revocation.sign(&issuer).unwrap();
+        assert_eq!(revocation, verify(&token, &trusted).unwrap());
+
+        // A host without trusted issuers, or trusting someone else, refuses it
+        assert!(verify(&token, &[]).is_err());
+        assert!(verify(&token, &[KeyPair::new_account().public_key()]).is_err());
+
+        // Changing the claims of a token breaks its signature
+        let mut claims = Claims::<Revocation>::decode(&token).unwrap();
+        claims.metadata = Some(revocation.with_revoked_at(u64::MAX));
+        let forged = claims.encode(&KeyPair::new_account()).unwrap();
+        assert!(verify(&forged, &trusted).is_err());
+        assert!(verify("not-a-token", &trusted).is_err());
+    }
+}
@@ -1,21 +1,29 @@
+use crate::errors::{self, ErrorKind};
 use crate::Result;
 
+use crate::authz::SecurityContext;
+#[cfg(feature = "wasmtime")]
+use crate::engine::WasmtimeEngineProvider;
 use crate::inthost::*;
+use crate::supervisor::RestartTracker;
 use crate::BindingsList;
 use crate::{
-    bus::MessageBus, dispatch::WasccNativeDispatcher, plugins::PluginManager, Authorizer,
-    Invocation, InvocationResponse, Middleware, RouteKey,
+    bus::MessageBus, dispatch::WasccNativeDispatcher, plugins::PluginManager, HostEvent,
+    HostThread, Invocation, InvocationResponse, Middleware, RouteKey,
+};
+use crate::{
+    engine::GuestMonitor, limits, middleware, NativeCapability, ProviderProvenance, ResourceLimits,
+    RestartPolicy,
 };
-use crate::{middleware, NativeCapability};
 
 use crossbeam::{Receiver, Sender};
 use crossbeam_channel as channel;
 use crossbeam_utils::sync::WaitGroup;
-#[cfg(feature = "lattice")]
-use latticeclient::BusEvent;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
 use wapc::{WapcHost, WasiParams};
 use wascap::{jwt::Claims, prelude::KeyPair};
 use wascc_codec::{
@@ -24,15 +32,44 @@ use wascc_codec::{
     deserialize, serialize, SYSTEM_ACTOR,
 };
 
+// An invocation bound for a portable capability provider's guest module, along with
+// the channel on which to deliver its response
+type PortableRequest = (Invocation, Sender<InvocationResponse>);
+
+/// The settings an actor instance runs under, along with the host's registry of running
+/// actors, in which the instance is counted and through which it shares its actor's gate
+pub(crate) struct ActorSettings {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) limits: ResourceLimits,
+    pub(crate) policy: RestartPolicy,
+    pub(crate) instances: Arc<RwLock<HashMap<String, ActorInstances>>>,
+}
+
+impl ActorSettings {
+    /// Default settings, for modules spawned without any of their own
+    pub(crate) fn new(instances: Arc<RwLock<HashMap<String, ActorInstances>>>) -> ActorSettings {
+        ActorSettings {
+            timeout: None,
+            limits: ResourceLimits::default(),
+            policy: RestartPolicy::default(),
+            instances,
+        }
+    }
+}
+
 /// Spawns a new background thread in which a new `WapcHost` is created for the actor
 /// module bytes. A message bus subscription is created either for the actor's RPC
 /// subject OR for the capability provider's root subject. We then select between a receive
 /// invocation on the subscription's channel or a receive invocation on the terminator channel,
-/// which will then trigger a cleanup of the actor's resources.
+/// which will then trigger a cleanup of the actor's resources. A guest that panics while
+/// handling an invocation, or that traps repeatedly, is re-instantiated from its module bytes
+/// under the restart policy; once the policy gives up, the instance is cleaned up as if it
+/// had been terminated
 pub(crate) fn spawn_actor(
     wg: WaitGroup,
     claims: Claims<wascap::jwt::Actor>,
     buf: Vec<u8>,
+    settings: ActorSettings,
     wasi: Option<WasiParams>,
     actor: bool,
     binding: Option<String>,
@@ -43,39 +80,96 @@ pub(crate) fn spawn_actor(
     claimsmap: Arc<RwLock<HashMap<String, Claims<wascap::jwt::Actor>>>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     hk: KeyPair,
-    auth: Arc<RwLock<Box<dyn Authorizer>>>,
-) -> Result<()> {
-    let c = claims.clone();
+    security: SecurityContext,
+) -> Result<HostThread> {
+    let ActorSettings {
+        timeout,
+        limits,
+        policy,
+        instances,
+    } = settings;
     let b = bus.clone();
     let hostkey = hk.clone();
-    let authorizer = auth.clone();
+    let provider_auth = security.authorizer.clone();
+    let aliases = security.aliases.clone();
+    // Fail early if the resource limits can't be baked into the module
+    limits::apply_limits(&buf, &limits)?;
 
-    thread::spawn(move || {
+    // All instances of an actor share a terminator channel. The instance is counted before
+    // the thread starts so that concurrent scaling requests see a consistent total
+    let (term_s, term_r, gate) = if actor {
+        let mut lock = instances.write().unwrap();
+        let rec = lock.entry(claims.subject.to_string()).or_insert_with(|| {
+            ActorInstances::new(
+                claims.clone(),
+                buf.clone(),
+                timeout,
+                limits.clone(),
+                policy.clone(),
+            )
+        });
+        rec.count += 1;
+        (rec.term_s.clone(), rec.term_r.clone(), rec.gate.clone())
+    } else {
+        let (term_s, term_r) = channel::unbounded();
+        (
+            term_s,
+            term_r,
+            Arc::new(ActorGate::new(claims.clone(), buf)),
+        )
+    };
+
+    // Reports whether the module could be instantiated, so that a module that can't (e.g. one
+    // with unknown imports) fails the caller rather than just its thread
+    let (ready_s, ready_r) = channel::bounded::<Result<()>>(1);
+    let handle = HostThread::spawn(move || {
         if actor {
-            #[cfg(feature = "lattice")]
-            let _ = bus.publish_event(BusEvent::ActorStarting {
-                host: hostkey.public_key(),
+            bus.emit(HostEvent::ActorStarting {
                 actor: claims.subject.to_string(),
             });
         }
-        #[cfg(feature = "wasmtime")]
-        let engine = wasmtime_provider::WasmtimeEngineProvider::new(&buf, wasi);
-        #[cfg(feature = "wasm3")]
-        let engine = wasm3_provider::Wasm3EngineProvider::new(&buf);
-
-        let mut guest = WapcHost::new(Box::new(engine), move |_id, bd, ns, op, payload| {
-            wapc_host_callback(
-                hk.clone(),
-                c.clone(),
-                bus.clone(),
-                bd,
-                ns,
-                op,
-                payload,
-                authorizer.clone(),
-            )
-        })
-        .unwrap();
+        let monitor = GuestMonitor::new();
+        let engine_monitor = monitor.clone();
+        // Instances are created from the actor's current module, which changes when the actor
+        // is replaced, with the resource limits baked into the module handed to the engine
+        let instantiate = move |m: &ActorModule| -> Result<WapcHost> {
+            let module = limits::apply_limits(&m.bytes, &limits)?;
+            #[cfg(feature = "wasmtime")]
+            let engine =
+                WasmtimeEngineProvider::new(&module, copy_wasi(&wasi), engine_monitor.clone());
+            #[cfg(feature = "wasm3")]
+            let engine = wasm3_provider::Wasm3EngineProvider::new(&module);
+
+            let (hk, c, bus, security) =
+                (hk.clone(), m.claims.clone(), bus.clone(), security.clone());
+            let guest = WapcHost::new(Box::new(engine), move |_id, bd, ns, op, payload| {
+                wapc_host_callback(
+                    hk.clone(),
+                    c.clone(),
+                    bus.clone(),
+                    bd,
+                    ns,
+                    op,
+                    payload,
+                    &security,
+                )
+            })?;
+            Ok(guest)
+        };
+
+        let current = gate.module();
+        let mut revision = current.revision;
+        let mut guest = match instantiate(&current) {
+            Ok(g) => g,
+            Err(e) => {
+                error!("Failed to instantiate module {}: {}", &claims.subject, e);
+                if actor {
+                    release_actor_instance(instances.clone(), &claims.subject);
+                }
+                let _ = ready_s.send(Err(e));
+                return;
+            }
+        };
         let mut d: Option<CapabilityDescriptor> = None;
 
         let subscribe_subject = if actor {
@@ -83,88 +177,446 @@ pub(crate) fn spawn_actor(
         } else {
             d = match get_descriptor(&mut guest) {
                 Ok(d) => Some(d),
-                Err(_) => None,
+                Err(e) => {
+                    error!(
+                        "Failed to get the descriptor of portable capability provider {}: {}",
+                        &claims.subject, e
+                    );
+                    let _ = ready_s.send(Err(errors::new(ErrorKind::CapabilityProvider(format!(
+                        "Portable capability provider {} did not describe itself: {}",
+                        &claims.subject, e
+                    )))));
+                    return;
+                }
+            };
+            let provenance = ProviderProvenance {
+                path: None,
+                hash: claims.metadata.as_ref().map(|m| m.module_hash.to_string()),
+                signer: Some(claims.issuer.to_string()),
             };
-            if d.is_none() {
-                return "".to_string();
+            if !provider_auth
+                .read()
+                .unwrap()
+                .can_load_provider(d.as_ref().unwrap(), &provenance)
+            {
+                let msg = format!(
+                    "Authorization hook denied access to portable capability provider {}",
+                    d.as_ref().unwrap().id
+                );
+                error!("{}", msg);
+                let _ = ready_s.send(Err(errors::new(ErrorKind::Authorization(msg))));
+                return;
             }
             let capid = d.as_ref().unwrap().id.to_string();
             let bname = binding.clone().unwrap();
-            #[cfg(feature = "lattice")]
-            let _ = b.publish_event(BusEvent::ProviderLoaded {
-                host: hostkey.public_key(),
+            caps.write()
+                .unwrap()
+                .insert(RouteKey::new(&bname, &capid), d.clone().unwrap());
+            b.emit(HostEvent::ProviderLoaded {
                 capid: capid.to_string(),
-                instance_name: bname.to_string(),
+                binding: bname.to_string(),
             });
 
             b.provider_subject(&capid, &bname)
         };
+        // Only a module that is instantiated, and for a provider described and authorized,
+        // lets the caller succeed
+        let _ = ready_s.send(Ok(()));
 
         let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
         let (resp_s, resp_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) =
             channel::unbounded();
-        let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
+        // Invocations forwarded from the bound actor-provider threads of a portable capability
+        // provider. The sender must stay alive for as long as this thread runs
+        let (guest_s, guest_r): (Sender<PortableRequest>, Receiver<PortableRequest>) =
+            channel::unbounded();
 
         if subscribe_subject.is_empty() {
-            return "can't subscribe to message bus".to_string();
+            error!("can't subscribe to message bus");
+            return;
         }
         terminators
             .write()
             .unwrap()
             .insert(subscribe_subject.clone(), term_s);
+        // Keep a handle on our own channel so that this instance alone can be unsubscribed
+        let inv_handle = inv_s.clone();
         let _ = b.subscribe(&subscribe_subject, inv_s, resp_r).unwrap();
-        drop(wg); // Let the Host wrapper function return
         if actor {
-            #[cfg(feature = "lattice")]
-            let _ = b.publish_event(BusEvent::ActorStarted {
-                host: hostkey.public_key(),
+            b.emit(HostEvent::ActorStarted {
                 actor: claims.subject.to_string(),
             });
             info!("Actor {} up and running.", &claims.subject);
         }
-        loop {
-            select! {
-                recv(inv_r) -> inv => {
-                    if let Ok(inv) = inv {
-                        let inv_r = if actor {
-                            middleware::invoke_actor(mids.clone(), inv.clone(), &mut guest).unwrap()
-                        } else {
-                            if inv.operation != OP_BIND_ACTOR && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR {
-                                InvocationResponse::error(&inv, "Attempted to invoke binding-required operation on unbound provider")
-                            } else {
-                                middleware::invoke_portable_capability(mids.clone(), inv.clone(), &mut guest).unwrap()
-                            }
-                        };
-                        resp_s.send(inv_r.clone()).unwrap();
-                        if inv.operation == OP_BIND_ACTOR && !actor && inv_r.error.is_none() {
-                            spawn_bound_portable_capability();
-                        }
+        drop(wg); // Let the Host wrapper function return, once its events are published
+        #[cfg(feature = "lattice")]
+        if !actor {
+            reestablish_portable_bindings(
+                b.clone(),
+                mids.clone(),
+                &mut guest,
+                guest_s.clone(),
+                terminators.clone(),
+                bindings.clone(),
+                &hostkey,
+                &d.as_ref().unwrap().id,
+                binding.as_ref().unwrap(),
+            );
+        }
+        let mut tracker = RestartTracker::new(policy);
+        // Invocations received while the actor is paused, in the order they arrived
+        let mut held: VecDeque<Invocation> = VecDeque::new();
+        let wake_r = gate.waker();
+        // This instance's copy of the actor's canary module, if one is running
+        let mut canary_guest: Option<WapcHost> = None;
+        let mut canary_loaded: Option<u64> = None;
+        'supervised: loop {
+            let mut failure: Option<String> = None;
+            if gate.revision() != revision {
+                // The actor has been replaced. If the new module can't be loaded the old one
+                // keeps running, and the host decides whether to roll the replacement back
+                let current = gate.module();
+                let outcome = match instantiate(&current) {
+                    Ok(g) => {
+                        guest = g;
+                        info!(
+                            "Actor {} loaded module revision {}",
+                            &claims.subject, current.revision
+                        );
+                        Ok(())
                     }
+                    Err(e) => Err(format!("Failed to instantiate replacement module: {}", e)),
+                };
+                revision = current.revision;
+                gate.report(revision, outcome);
+            }
+            let canary = gate.canary();
+            let canary_revision = canary.as_ref().map(|c| c.module.revision);
+            if canary_revision != canary_loaded {
+                canary_guest = None;
+                if let Some(c) = &canary {
+                    let outcome = match instantiate(&c.module) {
+                        Ok(g) => {
+                            canary_guest = Some(g);
+                            Ok(())
+                        }
+                        Err(e) => Err(format!("Failed to instantiate canary module: {}", e)),
+                    };
+                    gate.report(c.module.revision, outcome);
+                }
+                canary_loaded = canary_revision;
+            }
+            let mode = gate.mode();
+            let replay = if mode != ActorMode::Paused {
+                held.pop_front()
+            } else {
+                None
+            };
+            let next = match replay {
+                Some(inv) => {
+                    gate.release();
+                    Some(inv)
+                }
+                None => select! {
+                    recv(inv_r) -> inv => match (inv, gate.mode()) {
+                        (Ok(inv), ActorMode::Paused) if actor => {
+                            gate.hold();
+                            held.push_back(inv);
+                            None
+                        }
+                        (Ok(inv), ActorMode::Draining) if actor => {
+                            let msg = format!("Actor {} is draining and no longer accepts invocations", &claims.subject);
+                            resp_s.send(InvocationResponse::error_with_code(&inv, InvocationErrorCode::Unavailable, &msg)).unwrap();
+                            None
+                        }
+                        (inv, _) => inv.ok(),
+                    },
+                    recv(guest_r) -> req => {
+                        if let Ok((inv, reply_s)) = req {
+                            let (inv_r, panicked) = guarded(&inv, || middleware::invoke_portable_capability(mids.clone(), inv.clone(), &guest));
+                            failure = panicked.or_else(|| trap_failure(&mut tracker, &monitor));
+                            let _ = reply_s.send(inv_r);
+                        }
+                        None
+                    },
+                    recv(term_r) -> _term => {
+                        info!("Terminating {} {}", if actor { "actor" } else { "capability" }, &claims.subject);
+                        break 'supervised;
+                    },
+                    recv(wake_r) -> _ => None,
                 },
-                recv(term_r) -> _term => {
-                    info!("Terminating {} {}", if actor { "actor" } else { "capability" }, &claims.subject);
-                    let _ = b.unsubscribe(&subscribe_subject);
-                    terminators.write().unwrap().remove(&subscribe_subject);
-                    if !actor {
-                        //#[cfg(feature = "lattice")]
-                        //let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: hostkey.public_key(), actor: claims.subject.to_string() });
-                        remove_cap(caps.clone(), &d.as_ref().unwrap().id, binding.as_ref().unwrap()); // for cap providers, route key is the capid
-                        unbind_all_from_cap(bindings.clone(), &d.unwrap().id, binding.as_ref().unwrap());
-                    } else {
-                        #[cfg(feature = "lattice")]
-                        let _ = b.publish_event(BusEvent::ActorStopped{ host: hostkey.public_key(), actor: claims.subject.to_string() });
-                        let mut lock = claimsmap.write().unwrap();
-                        let _ = lock.remove(&claims.subject);
-                        drop(lock);
-                        deconfigure_actor(hostkey.clone(),b.clone(), bindings.clone(), &claims.subject);
+            };
+            // A trial of the canary must not fall back to the current module
+            let next = match (next, &canary, &canary_guest) {
+                (Some(inv), Some(c), None) if actor && c.take_trial(&inv.id) => {
+                    resp_s
+                        .send(InvocationResponse::error_with_code(
+                            &inv,
+                            InvocationErrorCode::Unavailable,
+                            "The staged module is not loaded by this instance",
+                        ))
+                        .unwrap();
+                    None
+                }
+                (next, _, _) => next,
+            };
+            if let Some(inv) = next {
+                let routed = match (&canary, &canary_guest) {
+                    (Some(c), Some(_)) if actor => Some((c.clone(), c.route(&inv))),
+                    _ => None,
+                };
+                let to_canary = matches!(routed, Some((_, true)));
+                let (inv_r, panicked) = if actor {
+                    let timeout = effective_timeout(timeout, inv.timeout);
+                    monitor.set_timeout(timeout);
+                    let target = match &canary_guest {
+                        Some(g) if to_canary => g,
+                        _ => &guest,
+                    };
+                    let (r, panicked) = guarded(&inv, || {
+                        middleware::invoke_actor(mids.clone(), inv.clone(), target)
+                    });
+                    match timeout {
+                        Some(t) if monitor.expired() => {
+                            warn!("Actor {} exceeded its execution deadline", &claims.subject);
+                            (InvocationResponse::timeout(&inv, t), panicked)
+                        }
+                        _ if monitor.trapped() && r.error.is_some() => (
+                            InvocationResponse {
+                                error_code: Some(InvocationErrorCode::GuestTrap),
+                                ..r
+                            },
+                            panicked,
+                        ),
+                        _ => (r, panicked),
+                    }
+                } else if inv.operation != OP_BIND_ACTOR
+                    && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR
+                    && inv.operation != OP_REMOVE_ACTOR
+                {
+                    (
+                        InvocationResponse::error_with_code(
+                            &inv,
+                            InvocationErrorCode::Unbound,
+                            "Attempted to invoke binding-required operation on unbound provider",
+                        ),
+                        None,
+                    )
+                } else {
+                    guarded(&inv, || {
+                        middleware::invoke_portable_capability(mids.clone(), inv.clone(), &guest)
+                    })
+                };
+                if let Some((c, to_canary)) = &routed {
+                    if c.record(*to_canary, inv_r.error.is_some()) && gate.clear_canary(c) {
+                        warn!(
+                            "Withdrawing the canary of actor {}, its error rate is too high",
+                            &claims.subject
+                        );
+                        b.emit(HostEvent::ActorUpdateComplete {
+                            actor: claims.subject.to_string(),
+                            success: false,
+                        });
+                    }
+                }
+                // The canary's failures are counted against it rather than the instance
+                if to_canary {
+                    if let Some(reason) = panicked {
+                        warn!("Canary of actor {} failed: {}", &claims.subject, reason);
+                        canary_guest = None;
+                    }
+                } else {
+                    failure = panicked.or_else(|| trap_failure(&mut tracker, &monitor));
+                }
+                if inv.operation == OP_BIND_ACTOR && !actor && inv_r.error.is_none() {
+                    // The private subject must exist before the binding is acknowledged
+                    spawn_bound_portable_capability(
+                        b.clone(),
+                        inv.clone(),
+                        &d.as_ref().unwrap().id,
+                        binding.as_ref().unwrap(),
+                        guest_s.clone(),
+                        terminators.clone(),
+                        bindings.clone(),
+                    );
+                }
+                resp_s.send(inv_r.clone()).unwrap();
+                if inv.operation == OP_REMOVE_ACTOR && !actor && inv_r.error.is_none() {
+                    let capid = d.as_ref().unwrap().id.to_string();
+                    let bname = binding.clone().unwrap();
+                    let bound_actor = actor_from_config(&inv.msg);
+                    let key = b.provider_subject_bound_actor(&capid, &bname, &bound_actor);
+                    if let Some(t) = terminators.read().unwrap().get(&key) {
+                        let _ = t.send(true);
                     }
-                    break "".to_string(); // TODO: WHY WHY WHY does this recv arm need to return a value?!?!?
+                    b.emit(HostEvent::ActorBindingRemoved {
+                        actor: bound_actor,
+                        capid,
+                        binding: bname,
+                    });
                 }
             }
+
+            while let Some(reason) = failure.take() {
+                let delay = match tracker.next_restart() {
+                    Some(delay) => delay,
+                    None => {
+                        error!(
+                            "Giving up on {} {} after repeated failures ({}), stopping it",
+                            if actor { "actor" } else { "capability" },
+                            &claims.subject,
+                            reason
+                        );
+                        break 'supervised;
+                    }
+                };
+                warn!(
+                    "Restarting {} {} in {:?}: {}",
+                    if actor { "actor" } else { "capability" },
+                    &claims.subject,
+                    delay,
+                    reason
+                );
+                if actor {
+                    b.emit(HostEvent::ActorRestarting {
+                        actor: claims.subject.to_string(),
+                        reason: reason.to_string(),
+                    });
+                }
+                thread::sleep(delay);
+                match instantiate(&gate.module()) {
+                    Ok(g) => {
+                        guest = g;
+                        info!(
+                            "Restarted {} {}",
+                            if actor { "actor" } else { "capability" },
+                            &claims.subject
+                        );
+                        if actor {
+                            b.emit(HostEvent::ActorRestarted {
+                                actor: claims.subject.to_string(),
+                            });
+                        }
+                    }
+                    Err(e) => failure = Some(format!("failed to re-instantiate module: {}", e)),
+                }
+            }
+        }
+
+        if actor {
+            // Stop callers from picking this instance. Invocations it had queued but not
+            // started go unanswered and the bus hands them to the remaining instances
+            let _ = b.unsubscribe_instance(&subscribe_subject, &inv_handle);
+        }
+        for inv in held.drain(..) {
+            gate.release();
+            let msg = format!(
+                "Actor {} stopped before running the invocation",
+                &claims.subject
+            );
+            let _ = resp_s.send(InvocationResponse::error_with_code(
+                &inv,
+                InvocationErrorCode::Unavailable,
+                &msg,
+            ));
+        }
+        if actor {
+            if !release_actor_instance(instances.clone(), &claims.subject) {
+                info!(
+                    "Actor {} instance stopped, other instances still running",
+                    &claims.subject
+                );
+                return;
+            }
+        } else {
+            let _ = b.unsubscribe(&subscribe_subject);
+        }
+        terminators.write().unwrap().remove(&subscribe_subject);
+        if !actor {
+            // Shut down the private actor-provider threads before forgetting the bindings
+            let bound_prefix = format!("{}.", subscribe_subject);
+            for (subject, t) in terminators.read().unwrap().iter() {
+                if subject.starts_with(&bound_prefix) {
+                    let _ = t.send(true);
+                }
+            }
+            b.emit(HostEvent::ProviderRemoved {
+                capid: d.as_ref().unwrap().id.to_string(),
+                binding: binding.clone().unwrap(),
+            });
+            remove_cap(
+                caps.clone(),
+                &d.as_ref().unwrap().id,
+                binding.as_ref().unwrap(),
+            ); // for cap providers, route key is the capid
+            unbind_all_from_cap(bindings.clone(), &d.unwrap().id, binding.as_ref().unwrap());
+        } else {
+            b.emit(HostEvent::ActorStopped {
+                actor: claims.subject.to_string(),
+            });
+            let mut lock = claimsmap.write().unwrap();
+            let _ = lock.remove(&claims.subject);
+            drop(lock);
+            aliases.write().unwrap().forget(&claims.subject);
+            deconfigure_actor(
+                hostkey.clone(),
+                b.clone(),
+                bindings.clone(),
+                &claims.subject,
+            );
         }
     });
 
-    Ok(())
+    match ready_r.recv() {
+        Ok(Err(e)) => Err(e),
+        _ => Ok(handle),
+    }
+}
+
+// Runs a guest invocation, turning a panic into an error response. The second value
+// describes the failure if the invocation panicked
+fn guarded(
+    inv: &Invocation,
+    f: impl FnOnce() -> Result<InvocationResponse>,
+) -> (InvocationResponse, Option<String>) {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(r)) => (r, None),
+        Ok(Err(e)) => (
+            InvocationResponse::error_with_code(
+                inv,
+                InvocationErrorCode::MiddlewareFailure,
+                &format!("Middleware failure: {}", e),
+            ),
+            None,
+        ),
+        Err(_) => (
+            InvocationResponse::error_with_code(
+                inv,
+                InvocationErrorCode::GuestFailure,
+                "Guest module failed while handling the invocation",
+            ),
+            Some(format!("panicked while handling {}", inv.operation)),
+        ),
+    }
+}
+
+fn trap_failure(tracker: &mut RestartTracker, monitor: &GuestMonitor) -> Option<String> {
+    if tracker.record_call(monitor.trapped()) {
+        Some("guest module trapped repeatedly".to_string())
+    } else {
+        None
+    }
+}
+
+// `WasiParams` isn't `Clone`, but a restarted guest needs the same parameters as the original
+#[cfg(feature = "wasmtime")]
+fn copy_wasi(wasi: &Option<WasiParams>) -> Option<WasiParams> {
+    wasi.as_ref().map(|w| WasiParams {
+        argv: w.argv.clone(),
+        map_dirs: w.map_dirs.clone(),
+        env_vars: w.env_vars.clone(),
+        preopened_dirs: w.preopened_dirs.clone(),
+    })
 }
 
 pub(crate) fn spawn_native_capability(
@@ -176,7 +628,7 @@ pub(crate) fn spawn_native_capability(
     plugins: Arc<RwLock<PluginManager>>,
     wg: WaitGroup,
     hk: Arc<KeyPair>,
-) -> Result<()> {
+) -> Result<HostThread> {
     let capid = capability.id().to_string();
     let binding = capability.binding_name.to_string();
     let b = bus.clone();
@@ -192,7 +644,7 @@ pub(crate) fn spawn_native_capability(
 
     plugins.write().unwrap().add_plugin(capability)?;
 
-    thread::spawn(move || {
+    let handle = HostThread::spawn(move || {
         let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
         let (resp_s, resp_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) =
             channel::unbounded();
@@ -213,26 +665,24 @@ pub(crate) fn spawn_native_capability(
 
         info!("Native capability provider '({},{})' ready", binding, capid);
 
-        drop(wg);
-        #[cfg(feature = "lattice")]
-        let _ = b.publish_event(BusEvent::ProviderLoaded {
-            host: hk.public_key(),
+        b.emit(HostEvent::ProviderLoaded {
             capid: capid.to_string(),
-            instance_name: binding.to_string(),
+            binding: binding.to_string(),
         });
+        drop(wg);
 
         loop {
             select! {
                 recv(inv_r) -> inv => {
                     if let Ok(inv) = inv {
                         let inv_r = if inv.operation != OP_BIND_ACTOR && inv.operation != OP_GET_CAPABILITY_DESCRIPTOR && inv.operation != OP_REMOVE_ACTOR {
-                            InvocationResponse::error(&inv, "Attempted to invoke binding-required operation on unbound provider")
+                            InvocationResponse::error_with_code(&inv, InvocationErrorCode::Unbound, "Attempted to invoke binding-required operation on unbound provider")
                         } else {
                             middleware::invoke_native_capability(mids.clone(), inv.clone(), plugins.clone()).unwrap()
                         };
                         resp_s.send(inv_r.clone()).unwrap();
                         if inv.operation == OP_BIND_ACTOR && inv_r.error.is_none() {
-                            spawn_bound_native_capability(bus.clone(), inv.clone(), &capid, &binding, mids.clone(), plugins.clone(), terminators.clone(), bindings.clone(), hk.clone());
+                            spawn_bound_native_capability(bus.clone(), inv.clone(), &capid, &binding, mids.clone(), plugins.clone(), terminators.clone(), bindings.clone());
                         }
                         if inv.operation == OP_REMOVE_ACTOR && inv_r.error.is_none() {
                             let actor = actor_from_config(&inv.msg);
@@ -248,8 +698,7 @@ pub(crate) fn spawn_native_capability(
                     let _ = bus.unsubscribe(&subscribe_subject);
                     plugins.write().unwrap().remove_plugin(&binding, &capid).unwrap();
                     terminators.write().unwrap().remove(&subscribe_subject);
-                    #[cfg(feature="lattice")]
-                    let _ = b.publish_event(BusEvent::ProviderRemoved{ host: hk.public_key(), capid: capid.to_string(), instance_name: binding.to_string()});
+                    b.emit(HostEvent::ProviderRemoved{ capid: capid.to_string(), binding: binding.to_string() });
                     break;
                 }
             }
@@ -267,7 +716,7 @@ pub(crate) fn spawn_native_capability(
         &capid2,
         &bindingname2,
     );
-    Ok(())
+    Ok(handle)
 }
 
 #[cfg(feature = "lattice")]
@@ -322,7 +771,6 @@ fn reestablish_bindings(
                         plugins.clone(),
                         terminators.clone(),
                         bindings.clone(),
-                        hk.clone(),
                     );
                 }
             }
@@ -346,7 +794,6 @@ fn spawn_bound_native_capability(
     plugins: Arc<RwLock<PluginManager>>,
     terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
     bindings: Arc<RwLock<BindingsList>>,
-    hk: Arc<KeyPair>,
 ) {
     let capid = capid.to_string();
     let binding = binding.to_string();
@@ -381,15 +828,122 @@ fn spawn_bound_native_capability(
                     let _ = bus.unsubscribe(&subscribe_subject);
                     remove_binding(bindings.clone(), &actor, &binding, &capid);
                     terminators.write().unwrap().remove(&subscribe_subject);
-                    #[cfg(feature="lattice")]
-                    let _ = bus.publish_event(BusEvent::ProviderRemoved{ host: hk.public_key(), capid: capid.to_string(), instance_name: binding.to_string()});
+                    bus.emit(HostEvent::ActorBindingRemoved{ actor: actor.to_string(), capid: capid.to_string(), binding: binding.to_string() });
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// This is a thread that handles the private conversations between an actor and a portable capability provider.
+// The provider's guest module can only be used from the thread that created it, so every invocation received
+// on the actor+provider topic is forwarded to the provider's root thread and the response is relayed back
+fn spawn_bound_portable_capability(
+    bus: Arc<MessageBus>,
+    inv: Invocation,
+    capid: &str,
+    binding: &str,
+    guest_s: Sender<PortableRequest>,
+    terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
+    bindings: Arc<RwLock<BindingsList>>,
+) {
+    let capid = capid.to_string();
+    let binding = binding.to_string();
+    let actor = actor_from_config(&inv.msg);
+    let terms = terminators.clone();
+    let wg = WaitGroup::new();
+    let thread_wg = wg.clone();
+
+    thread::spawn(move || {
+        let (inv_s, inv_r): (Sender<Invocation>, Receiver<Invocation>) = channel::unbounded();
+        let (resp_s, resp_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) =
+            channel::unbounded();
+        let subscribe_subject = bus.provider_subject_bound_actor(&capid, &binding, &actor);
+        let (term_s, term_r): (Sender<bool>, Receiver<bool>) = channel::unbounded();
+
+        bus.subscribe(&subscribe_subject, inv_s, resp_r).unwrap();
+        terms
+            .write()
+            .unwrap()
+            .insert(subscribe_subject.to_string(), term_s);
+        drop(thread_wg);
+
+        loop {
+            select! {
+                recv(inv_r) -> inv => {
+                    if let Ok(inv) = inv {
+                        let (reply_s, reply_r): (Sender<InvocationResponse>, Receiver<InvocationResponse>) = channel::unbounded();
+                        let inv_r = match guest_s.send((inv.clone(), reply_s)) {
+                            Ok(_) => reply_r.recv().unwrap_or_else(|_| InvocationResponse::error_with_code(&inv, InvocationErrorCode::Unavailable, "Portable capability provider terminated before responding")),
+                            Err(_) => InvocationResponse::error_with_code(&inv, InvocationErrorCode::Unavailable, "Portable capability provider is no longer running"),
+                        };
+                        resp_s.send(inv_r).unwrap();
+                    }
+                },
+                recv(term_r) -> _term => {
+                    let _ = bus.unsubscribe(&subscribe_subject);
+                    remove_binding(bindings.clone(), &actor, &binding, &capid);
+                    terminators.write().unwrap().remove(&subscribe_subject);
                     break;
                 }
             }
         }
     });
+    wg.wait();
 }
 
-fn spawn_bound_portable_capability() {
-    todo!()
+// Portable capability providers can't rely on the native plugin manager, so re-establishing bindings
+// has to take place on the provider's root thread, which owns the guest module
+#[cfg(feature = "lattice")]
+fn reestablish_portable_bindings(
+    bus: Arc<MessageBus>,
+    mids: Arc<RwLock<Vec<Box<dyn Middleware>>>>,
+    guest: &mut WapcHost,
+    guest_s: Sender<PortableRequest>,
+    terminators: Arc<RwLock<HashMap<String, Sender<bool>>>>,
+    bindings: Arc<RwLock<BindingsList>>,
+    hk: &KeyPair,
+    capid: &str,
+    binding_name: &str,
+) {
+    if let Ok(blist) = bus.query_bindings() {
+        for b in blist {
+            if b.capability_id == capid && b.binding_name == binding_name {
+                let cfgvals = CapabilityConfiguration {
+                    module: b.actor.to_string(),
+                    values: b.configuration.clone(),
+                };
+                let payload = serialize(&cfgvals).unwrap();
+                let inv = Invocation::new(
+                    hk,
+                    WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                    WasccEntity::Capability {
+                        capid: capid.to_string(),
+                        binding: binding_name.to_string(),
+                    },
+                    OP_BIND_ACTOR,
+                    payload,
+                );
+                let inv_r =
+                    middleware::invoke_portable_capability(mids.clone(), inv.clone(), guest)
+                        .unwrap();
+                if inv_r.error.is_none() {
+                    info!(
+                        "Re-establishing binding between {} and {},{}",
+                        &b.actor, &capid, &binding_name
+                    );
+                    spawn_bound_portable_capability(
+                        bus.clone(),
+                        inv,
+                        capid,
+                        binding_name,
+                        guest_s.clone(),
+                        terminators.clone(),
+                        bindings.clone(),
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,177 @@
+// Supervision of guest modules. An actor's thread catches panics raised while handling an
+// invocation and counts consecutive traps; either kind of failure causes the module to be
+// re-instantiated from its bytes, subject to the actor's restart policy
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Governs how an actor is restarted after a failure. A failure is either a panic while
+/// handling an invocation or a run of consecutive trapped guest calls. An actor that needs
+/// more than `max_restarts` restarts within `window` is stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestartPolicy {
+    /// The maximum number of restarts allowed within the window
+    pub max_restarts: u32,
+    /// The sliding window over which restarts are counted
+    pub window: Duration,
+    /// The delay before a restart. The delay doubles with each restart already in the window
+    pub backoff: Duration,
+    /// The number of consecutive trapped guest calls that counts as a failure
+    pub max_consecutive_traps: u32,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            backoff: Duration::from_millis(100),
+            max_consecutive_traps: 3,
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn new() -> RestartPolicy {
+        RestartPolicy::default()
+    }
+
+    /// A policy that stops the actor on its first failure
+    pub fn never() -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: 0,
+            ..RestartPolicy::default()
+        }
+    }
+
+    /// Sets the maximum number of restarts allowed within the window
+    pub fn with_max_restarts(self, max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            ..self
+        }
+    }
+
+    /// Sets the sliding window over which restarts are counted
+    pub fn with_window(self, window: Duration) -> RestartPolicy {
+        RestartPolicy { window, ..self }
+    }
+
+    /// Sets the delay before the first restart within the window
+    pub fn with_backoff(self, backoff: Duration) -> RestartPolicy {
+        RestartPolicy { backoff, ..self }
+    }
+
+    /// Sets the number of consecutive trapped guest calls that counts as a failure
+    pub fn with_max_consecutive_traps(self, traps: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_consecutive_traps: traps.max(1),
+            ..self
+        }
+    }
+}
+
+/// Tracks the failures and restarts of a single actor instance against its policy
+pub(crate) struct RestartTracker {
+    policy: RestartPolicy,
+    restarts: VecDeque<Instant>,
+    traps: u32,
+}
+
+impl RestartTracker {
+    pub(crate) fn new(policy: RestartPolicy) -> RestartTracker {
+        RestartTracker {
+            policy,
+            restarts: VecDeque::new(),
+            traps: 0,
+        }
+    }
+
+    /// Records whether the latest guest call trapped, and returns `true` once the run of
+    /// consecutive traps reaches the policy's limit
+    pub(crate) fn record_call(&mut self, trapped: bool) -> bool {
+        if !trapped {
+            self.traps = 0;
+            return false;
+        }
+        self.traps += 1;
+        if self.traps >= self.policy.max_consecutive_traps {
+            self.traps = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records a restart and returns the delay to wait before performing it, or `None`
+    /// if the policy's restart budget for the current window has been used up
+    pub(crate) fn next_restart(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        while let Some(t) = self.restarts.front() {
+            if now.duration_since(*t) > self.policy.window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        let recent = self.restarts.len() as u32;
+        if recent >= self.policy.max_restarts {
+            return None;
+        }
+        self.restarts.push_back(now);
+        self.traps = 0;
+        Some(
+            self.policy
+                .backoff
+                .checked_mul(1 << recent.min(16))
+                .map_or(self.policy.window, |d| d.min(self.policy.window)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RestartPolicy, RestartTracker};
+    use std::time::Duration;
+
+    #[test]
+    fn consecutive_traps_trigger_failure() {
+        let mut tracker = RestartTracker::new(RestartPolicy::new().with_max_consecutive_traps(2));
+        assert!(!tracker.record_call(true));
+        assert!(!tracker.record_call(false));
+        assert!(!tracker.record_call(true));
+        assert!(tracker.record_call(true));
+        assert!(!tracker.record_call(true));
+    }
+
+    #[test]
+    fn restarts_back_off_until_exhausted() {
+        let policy = RestartPolicy::new()
+            .with_max_restarts(3)
+            .with_backoff(Duration::from_millis(10));
+        let mut tracker = RestartTracker::new(policy);
+        assert_eq!(Some(Duration::from_millis(10)), tracker.next_restart());
+        assert_eq!(Some(Duration::from_millis(20)), tracker.next_restart());
+        assert_eq!(Some(Duration::from_millis(40)), tracker.next_restart());
+        assert_eq!(None, tracker.next_restart());
+    }
+
+    #[test]
+    fn restarts_outside_window_are_forgotten() {
+        let policy = RestartPolicy::new()
+            .with_max_restarts(1)
+            .with_window(Duration::from_millis(20))
+            .with_backoff(Duration::from_millis(1));
+        let mut tracker = RestartTracker::new(policy);
+        assert!(tracker.next_restart().is_some());
+        assert!(tracker.next_restart().is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(tracker.next_restart().is_some());
+    }
+
+    #[test]
+    fn never_policy_does_not_restart() {
+        let mut tracker = RestartTracker::new(RestartPolicy::never());
+        assert!(tracker.next_restart().is_none());
+    }
+}
@@ -0,0 +1,577 @@
+// Replacement of the module run by a live actor. The replacement is validated before any
+// instance sees it, and the optional smoke test is run on a staged copy of it. Every instance
+// of the actor then loads it in place; if an instance can't, the previous module is put back.
+// A replacement can also be trialled as a canary, which every instance runs alongside the
+// current module and routes a share of the actor's invocations to
+
+use crate::errors::{self, ErrorKind};
+use crate::inthost::{ActorGate, Canary};
+use crate::{
+    authz, limits, Actor, ActorSource, BindingsList, Host, HostEvent, Invocation, Result,
+    WasccEntity,
+};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use wascap::jwt::Claims;
+use wascc_codec::SYSTEM_ACTOR;
+
+const DEFAULT_REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options that control how a running actor is replaced
+#[derive(Debug, Clone)]
+pub struct ReplaceOptions {
+    smoke_test: Option<(String, Vec<u8>)>,
+    timeout: Duration,
+}
+
+impl Default for ReplaceOptions {
+    fn default() -> Self {
+        ReplaceOptions {
+            smoke_test: None,
+            timeout: DEFAULT_REPLACE_TIMEOUT,
+        }
+    }
+}
+
+impl ReplaceOptions {
+    pub fn new() -> ReplaceOptions {
+        ReplaceOptions::default()
+    }
+
+    /// Invokes the given operation on a staged copy of the new module before the actor's
+    /// instances switch to it. If the invocation fails, the instances keep the current module.
+    ///
+    /// The smoke test is the only check of the new module's behaviour: without one, a module
+    /// that loads but then fails its invocations is not rolled back automatically (see
+    /// `Host::rollback_actor`)
+    pub fn with_smoke_test(self, operation: &str, payload: &[u8]) -> ReplaceOptions {
+        ReplaceOptions {
+            smoke_test: Some((operation.to_string(), payload.to_vec())),
+            ..self
+        }
+    }
+
+    /// Sets how long to wait for the instances to load a module, and for the smoke test
+    /// to complete
+    pub fn with_timeout(self, timeout: Duration) -> ReplaceOptions {
+        ReplaceOptions { timeout, ..self }
+    }
+}
+
+/// Whether the replacement of an actor took effect
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplaceOutcome {
+    /// Every instance of the actor is running the new module
+    Replaced,
+    /// The replacement failed and every instance is running the previous module
+    RolledBack { reason: String },
+    /// The replacement failed, and so did putting the previous module back. The actor's
+    /// instances may be running either module
+    RollbackFailed { reason: String, error: String },
+}
+
+/// Describes the replacement of an actor's module
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaceReport {
+    /// The public key of the replaced actor
+    pub actor: String,
+    /// The revision claimed by the previous module
+    pub previous_revision: Option<i32>,
+    /// The revision claimed by the new module
+    pub revision: Option<i32>,
+    /// The number of instances of the actor asked to load the new module
+    pub instances: usize,
+    /// Whether the smoke test passed, if one was requested and the instances loaded the module
+    pub smoke_test_passed: Option<bool>,
+    pub outcome: ReplaceOutcome,
+}
+
+impl ReplaceReport {
+    /// Indicates whether the actor is now running the new module
+    pub fn is_replaced(&self) -> bool {
+        self.outcome == ReplaceOutcome::Replaced
+    }
+}
+
+/// Options that control how a canary revision of an actor is run
+#[derive(Debug, Clone)]
+pub struct CanaryOptions {
+    pub(crate) weight: u8,
+    pub(crate) max_error_rate: Option<(f64, u64)>,
+    pub(crate) timeout: Duration,
+}
+
+impl Default for CanaryOptions {
+    fn default() -> Self {
+        CanaryOptions {
+            weight: 10,
+            max_error_rate: None,
+            timeout: DEFAULT_REPLACE_TIMEOUT,
+        }
+    }
+}
+
+impl CanaryOptions {
+    pub fn new() -> CanaryOptions {
+        CanaryOptions::default()
+    }
+
+    /// Sets the percentage (0 to 100) of the actor's invocations routed to the canary
+    pub fn with_weight(self, weight: u8) -> CanaryOptions {
+        CanaryOptions {
+            weight: weight.min(100),
+            ..self
+        }
+    }
+
+    /// Withdraws the canary automatically once it has handled at least `min_invocations`
+    /// invocations and more than `rate` (0.0 to 1.0) of them have failed
+    pub fn with_max_error_rate(self, rate: f64, min_invocations: u64) -> CanaryOptions {
+        CanaryOptions {
+            max_error_rate: Some((rate, min_invocations.max(1))),
+            ..self
+        }
+    }
+
+    /// Sets how long to wait for the instances to load the canary module
+    pub fn with_timeout(self, timeout: Duration) -> CanaryOptions {
+        CanaryOptions { timeout, ..self }
+    }
+}
+
+/// The progress of a canary revision of an actor, compared with the actor's current revision
+/// over the same period
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanaryStatus {
+    /// The public key of the actor
+    pub actor: String,
+    /// The revision claimed by the canary module
+    pub revision: Option<i32>,
+    /// The revision claimed by the actor's current module
+    pub stable_revision: Option<i32>,
+    /// The percentage of invocations routed to the canary
+    pub weight: u8,
+    /// The number of invocations handled by the canary
+    pub invocations: u64,
+    /// The number of invocations handled by the canary that failed
+    pub errors: u64,
+    /// The number of invocations handled by the current module since the canary started
+    pub stable_invocations: u64,
+    /// The number of those invocations that failed
+    pub stable_errors: u64,
+}
+
+impl CanaryStatus {
+    /// The fraction of the canary's invocations that failed
+    pub fn error_rate(&self) -> f64 {
+        rate(self.errors, self.invocations)
+    }
+
+    /// The fraction of the current module's invocations that failed
+    pub fn stable_error_rate(&self) -> f64 {
+        rate(self.stable_errors, self.stable_invocations)
+    }
+}
+
+fn rate(errors: u64, calls: u64) -> f64 {
+    if calls == 0 {
+        0.0
+    } else {
+        errors as f64 / calls as f64
+    }
+}
+
+pub(crate) fn replace_actor(
+    host: &Host,
+    new_actor: Actor,
+    options: ReplaceOptions,
+) -> Result<ReplaceReport> {
+    let pk = new_actor.public_key();
+    let (gate, _) = validate_replacement(host, &new_actor)?;
+    host.bus.emit(HostEvent::ActorUpdating {
+        actor: pk.to_string(),
+    });
+    swap_in(
+        host,
+        &gate,
+        new_actor.token.claims,
+        new_actor.bytes,
+        new_actor.source,
+        &options,
+        None,
+    )
+}
+
+/// Puts an earlier revision of an actor back in place. See `Host::rollback_actor`
+pub(crate) fn rollback_actor(
+    host: &Host,
+    pk: &str,
+    rev: i32,
+    options: ReplaceOptions,
+) -> Result<ReplaceReport> {
+    let (bytes, source) = host.revisions.read().unwrap().module(pk, rev)?;
+    let actor = Actor {
+        source,
+        ..Actor::from_slice(&bytes)?
+    };
+    if actor.public_key() != pk {
+        return Err(errors::new(ErrorKind::MiscHost(format!(
+            "Revision {} does not belong to actor {}",
+            rev, pk
+        ))));
+    }
+    replace_actor(host, actor, options)
+}
+
+/// Starts running a new revision of an actor alongside the current one. See `Host::start_canary`
+pub(crate) fn start_canary(
+    host: &Host,
+    new_actor: Actor,
+    options: CanaryOptions,
+) -> Result<CanaryStatus> {
+    let pk = new_actor.public_key();
+    let (gate, count) = validate_replacement(host, &new_actor)?;
+    host.bus.emit(HostEvent::ActorUpdating {
+        actor: pk.to_string(),
+    });
+    match gate.start_canary(
+        new_actor.token.claims,
+        new_actor.bytes,
+        new_actor.source,
+        &options,
+        count,
+    ) {
+        Ok(canary) => {
+            info!(
+                "Canary of actor {} started, receiving {}% of invocations",
+                pk, options.weight
+            );
+            Ok(status_of(&pk, &gate.module().claims, &canary))
+        }
+        Err(e) => {
+            host.bus.emit(HostEvent::ActorUpdateComplete {
+                actor: pk.to_string(),
+                success: false,
+            });
+            Err(errors::new(ErrorKind::MiscHost(format!(
+                "Failed to start canary of actor {}: {}",
+                pk, e
+            ))))
+        }
+    }
+}
+
+pub(crate) fn canary_status(host: &Host, pk: &str) -> Option<CanaryStatus> {
+    let gate = host.actor_gate(pk).ok()?;
+    gate.canary()
+        .map(|c| status_of(pk, &gate.module().claims, &c))
+}
+
+pub(crate) fn set_canary_weight(host: &Host, pk: &str, weight: u8) -> Result<()> {
+    running_canary(host, pk)?.1.set_weight(weight);
+    Ok(())
+}
+
+/// Makes the canary's module the actor's current module, in every instance
+pub(crate) fn promote_canary(
+    host: &Host,
+    pk: &str,
+    options: ReplaceOptions,
+) -> Result<ReplaceReport> {
+    let (gate, canary) = running_canary(host, pk)?;
+    host.bus.emit(HostEvent::ActorUpdating {
+        actor: pk.to_string(),
+    });
+    let report = swap_in(
+        host,
+        &gate,
+        canary.module.claims.clone(),
+        canary.module.bytes.clone(),
+        canary.source.clone(),
+        &options,
+        Some(canary.clone()),
+    )?;
+    gate.clear_canary(&canary);
+    Ok(report)
+}
+
+/// Withdraws the canary, returning its final statistics
+pub(crate) fn abort_canary(host: &Host, pk: &str) -> Result<CanaryStatus> {
+    let (gate, canary) = running_canary(host, pk)?;
+    let status = status_of(pk, &gate.module().claims, &canary);
+    if gate.clear_canary(&canary) {
+        info!("Canary of actor {} aborted", pk);
+        host.bus.emit(HostEvent::ActorUpdateComplete {
+            actor: pk.to_string(),
+            success: false,
+        });
+    }
+    Ok(status)
+}
+
+// Checks that a new module may replace the running actor with the same public key, returning
+// the actor's shared state and number of instances
+fn validate_replacement(host: &Host, new_actor: &Actor) -> Result<(Arc<ActorGate>, usize)> {
+    let pk = new_actor.public_key();
+    let (gate, count, limits) = match host.instances.read().unwrap().get(&pk) {
+        Some(rec) => (rec.gate.clone(), rec.count, rec.limits.clone()),
+        None => {
+            return Err(errors::new(ErrorKind::MiscHost(format!(
+                "Actor {} is not running in this host, it cannot be replaced",
+                pk
+            ))))
+        }
+    };
+    authz::enforce_validation(&new_actor.token.jwt)?;
+    host.check_trust(&new_actor.token.claims)?;
+    if !host.check_auth(&new_actor.token) {
+        return Err(errors::new(ErrorKind::Authorization(
+            "Authorization hook denied access to module".into(),
+        )));
+    }
+    check_compatible(
+        &gate.module().claims,
+        &new_actor.token.claims,
+        &host.bindings,
+    )?;
+    host.aliases
+        .read()
+        .unwrap()
+        .check_declared(&new_actor.token.claims)?;
+    // Fail before touching the instances if the resource limits can't be applied
+    limits::apply_limits(&new_actor.bytes, &limits)?;
+    Ok((gate, count))
+}
+
+// Runs the smoke test, if there is one, then has every instance load the module, putting the
+// previous module back if any of them fails to. `staged` is a canary already running the module
+fn swap_in(
+    host: &Host,
+    gate: &ActorGate,
+    claims: Claims<wascap::jwt::Actor>,
+    bytes: Vec<u8>,
+    source: ActorSource,
+    options: &ReplaceOptions,
+    staged: Option<Arc<Canary>>,
+) -> Result<ReplaceReport> {
+    let previous = gate.module();
+    let pk = previous.claims.subject.to_string();
+    let count = host.actor_instances(&pk);
+    let mut report = ReplaceReport {
+        actor: pk.to_string(),
+        previous_revision: revision_of(&previous.claims),
+        revision: revision_of(&claims),
+        instances: count,
+        smoke_test_passed: None,
+        outcome: ReplaceOutcome::Replaced,
+    };
+
+    let mut failure = None;
+    if let Some((operation, payload)) = &options.smoke_test {
+        // A module that isn't already running as a canary is staged as one that receives no
+        // other invocations, and withdrawn once the test is over
+        let canary = match &staged {
+            Some(canary) => Ok(canary.clone()),
+            None => gate.start_canary(
+                claims.clone(),
+                bytes.clone(),
+                source.clone(),
+                &CanaryOptions::new()
+                    .with_weight(0)
+                    .with_timeout(options.timeout),
+                count,
+            ),
+        };
+        failure = match canary {
+            Ok(canary) => {
+                let res = smoke_test(host, &canary, operation, payload, options.timeout);
+                if staged.is_none() {
+                    gate.clear_canary(&canary);
+                }
+                report.smoke_test_passed = Some(res.is_ok());
+                res.err().map(|e| format!("Smoke test failed: {}", e))
+            }
+            Err(e) => Some(format!(
+                "Failed to stage the module for its smoke test: {}",
+                e
+            )),
+        };
+    }
+    let mut swapped = false;
+    if failure.is_none() {
+        failure = gate
+            .swap(claims.clone(), bytes.clone(), count, options.timeout)
+            .err();
+        swapped = true;
+    }
+    match failure {
+        None => {
+            host.revisions
+                .write()
+                .unwrap()
+                .record(&claims, &bytes, source);
+            if let Err(e) = host.aliases.write().unwrap().declare(&claims) {
+                warn!("Call aliases of actor {} not updated: {}", pk, e);
+            }
+            host.claims.write().unwrap().insert(pk.to_string(), claims);
+            info!("Actor {} replaced", pk);
+        }
+        Some(reason) if !swapped => {
+            warn!("Replacement of actor {} abandoned: {}", pk, reason);
+            report.outcome = ReplaceOutcome::RolledBack { reason };
+        }
+        Some(reason) => {
+            warn!(
+                "Replacement of actor {} failed, rolling back: {}",
+                pk, reason
+            );
+            report.outcome = match gate.swap(
+                previous.claims.clone(),
+                previous.bytes.clone(),
+                count,
+                options.timeout,
+            ) {
+                Ok(()) => ReplaceOutcome::RolledBack { reason },
+                Err(error) => {
+                    error!("Failed to roll back actor {}: {}", pk, error);
+                    ReplaceOutcome::RollbackFailed { reason, error }
+                }
+            };
+        }
+    }
+    host.bus.emit(HostEvent::ActorUpdateComplete {
+        actor: pk,
+        success: report.is_replaced(),
+    });
+    Ok(report)
+}
+
+// Invokes the smoke test on the canary, whatever its weight
+fn smoke_test(
+    host: &Host,
+    canary: &Canary,
+    operation: &str,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let pk = &canary.module.claims.subject;
+    let inv = Invocation::new(
+        &host.key,
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        WasccEntity::Actor(pk.to_string()),
+        operation,
+        payload.to_vec(),
+    )
+    .with_timeout(timeout);
+    let id = inv.id.to_string();
+    canary.add_trial(&id);
+    let res = host.deliver_to_actor(pk, inv);
+    // Forget the trial if it never reached an instance
+    canary.take_trial(&id);
+    res
+}
+
+fn running_canary(host: &Host, pk: &str) -> Result<(Arc<ActorGate>, Arc<Canary>)> {
+    let gate = host.actor_gate(pk)?;
+    match gate.canary() {
+        Some(canary) => Ok((gate, canary)),
+        None => Err(errors::new(ErrorKind::MiscHost(format!(
+            "Actor {} has no canary running",
+            pk
+        )))),
+    }
+}
+
+fn status_of(pk: &str, stable: &Claims<wascap::jwt::Actor>, canary: &Canary) -> CanaryStatus {
+    CanaryStatus {
+        actor: pk.to_string(),
+        revision: revision_of(&canary.module.claims),
+        stable_revision: revision_of(stable),
+        weight: canary.weight(),
+        invocations: canary.canary.calls(),
+        errors: canary.canary.errors(),
+        stable_invocations: canary.stable.calls(),
+        stable_errors: canary.stable.errors(),
+    }
+}
+
+// A replacement must come from the actor's issuer, and must still claim every capability
+// that the actor is bound to
+fn check_compatible(
+    current: &Claims<wascap::jwt::Actor>,
+    replacement: &Claims<wascap::jwt::Actor>,
+    bindings: &RwLock<BindingsList>,
+) -> Result<()> {
+    if current.issuer != replacement.issuer {
+        return Err(errors::new(ErrorKind::Authorization(format!(
+            "Replacement for actor {} was issued by {}, not by the actor's issuer {}",
+            current.subject, replacement.issuer, current.issuer
+        ))));
+    }
+    let caps = replacement
+        .metadata
+        .as_ref()
+        .and_then(|m| m.caps.clone())
+        .unwrap_or_default();
+    let mut missing: Vec<_> = bindings
+        .read()
+        .unwrap()
+        .keys()
+        .filter(|(actor, capid, _)| *actor == current.subject && !caps.contains(capid))
+        .map(|(_, capid, binding)| format!("{},{}", capid, binding))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        missing.sort();
+        Err(errors::new(ErrorKind::Authorization(format!(
+            "Replacement for actor {} does not claim the capabilities it is bound to: {}",
+            current.subject,
+            missing.join(", ")
+        ))))
+    }
+}
+
+fn revision_of(claims: &Claims<wascap::jwt::Actor>) -> Option<i32> {
+    claims.metadata.as_ref().and_then(|m| m.rev)
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_compatible;
+    use crate::authz::test_claims;
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+    use wascap::prelude::KeyPair;
+    use wascc_codec::core::CapabilityConfiguration;
+
+    #[test]
+    fn replacement_must_cover_bindings() {
+        let (issuer, subject) = (
+            KeyPair::new_account().public_key(),
+            KeyPair::new_module().public_key(),
+        );
+        let claims = |issuer: &str, caps: &[&str]| test_claims(&subject, Some(issuer), caps, &[]);
+        let current = claims(&issuer, &["wascc:http_server", "wascc:keyvalue"]);
+        let mut bindings = HashMap::new();
+        bindings.insert(
+            (
+                subject.to_string(),
+                "wascc:keyvalue".to_string(),
+                "default".to_string(),
+            ),
+            CapabilityConfiguration {
+                module: subject.to_string(),
+                values: HashMap::new(),
+            },
+        );
+        let bindings = RwLock::new(bindings);
+
+        let fewer_caps = claims(&issuer, &["wascc:http_server"]);
+        assert!(check_compatible(&current, &fewer_caps, &bindings).is_err());
+        let same_caps = claims(&issuer, &["wascc:keyvalue"]);
+        assert!(check_compatible(&current, &same_caps, &bindings).is_ok());
+        let other_issuer = claims(&KeyPair::new_account().public_key(), &["wascc:keyvalue"]);
+        assert!(check_compatible(&current, &other_issuer, &bindings).is_err());
+    }
+}
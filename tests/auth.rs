@@ -67,6 +67,41 @@ pub(crate) fn authorizer_blocks_bindings() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[cfg(feature = "manifest")]
+pub(crate) fn policy_authorizer_from_manifest() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostManifest;
+
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let echo2 = Actor::from_file("./examples/.assets/echo2.wasm")?;
+    let policy = format!(
+        r#"{{ "trusted_issuers": ["{}"], "banned_subjects": ["{}"] }}"#,
+        echo.issuer(),
+        echo2.public_key()
+    );
+    let path = std::env::temp_dir().join(format!("wascc-policy-{}.json", echo.public_key()));
+    std::fs::write(&path, policy)?;
+    let manifest = |actor: &str| HostManifest {
+        labels: Default::default(),
+        authorization: Some(path.to_string_lossy().to_string()),
+        actors: vec![actor.to_string()],
+        capabilities: vec![],
+        bindings: vec![],
+    };
+
+    let host = HostBuilder::new().build();
+    host.apply_manifest(manifest("./examples/.assets/echo.wasm"))?;
+    assert!(host.claims_for_actor(&echo.public_key()).is_some());
+    // The policy bans the second actor even though it has the same issuer
+    assert!(host
+        .apply_manifest(manifest("./examples/.assets/echo2.wasm"))
+        .is_err());
+    assert!(host.claims_for_actor(&echo2.public_key()).is_none());
+
+    std::fs::remove_file(&path)?;
+    host.shutdown()?;
+    Ok(())
+}
+
 pub(crate) fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     // Set the authorizer before calling a bind_actor, and bind_actor should
     // return permission denied / Err if the authorizer denies that invocation.
@@ -82,6 +117,275 @@ pub(crate) fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+pub(crate) fn untrusted_issuers_are_rejected() -> Result<(), Box<dyn Error>> {
+    use wascc_host::HostEvent;
+
+    let other = wascap::prelude::KeyPair::new_account();
+    let host = HostBuilder::new()
+        .with_trusted_issuer(&other.public_key())
+        .build();
+    let events = host.events();
+
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let (pk, issuer) = (echo.public_key(), echo.issuer());
+    assert!(host.add_actor(echo).is_err());
+    // The host's own providers may report that they have loaded first
+    let rejected = events
+        .iter()
+        .find(|e| matches!(e, HostEvent::ActorRejected { .. }));
+    match rejected {
+        Some(HostEvent::ActorRejected {
+            actor, issuer: i, ..
+        }) => {
+            assert_eq!(pk, actor);
+            assert_eq!(issuer, i);
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    assert!(host.actors().is_empty());
+    host.shutdown()?;
+
+    let host = HostBuilder::new()
+        .with_trusted_issuer(&other.public_key())
+        .with_trusted_issuer(&issuer)
+        .build();
+    assert_eq!(2, host.trusted_issuers().len());
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    assert_eq!(1, host.actors().len());
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(700));
+    Ok(())
+}
+
+pub(crate) fn revoked_actors_are_terminated() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::{HostEvent, Revocation, RevokedEntity};
+
+    let host = Host::new();
+    let events = host.events();
+    let echo = Actor::from_file("./examples/.assets/echo.wasm")?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+
+    host.revoke(Revocation::actor(&pk))?;
+    let revoked = events
+        .iter()
+        .find(|e| matches!(e, HostEvent::ActorRevoked { .. }));
+    assert_eq!(Some(HostEvent::ActorRevoked { actor: pk.clone() }), revoked);
+    assert_eq!(
+        HostEvent::ActorStopped { actor: pk.clone() },
+        events.recv_timeout(Duration::from_secs(2))?
+    );
+    assert!(host.actors().is_empty());
+    assert_eq!(1, host.revocations().len());
+
+    assert!(host
+        .add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)
+        .is_err());
+    host.remove_revocation(&RevokedEntity::Actor(pk.clone()))?;
+    host.add_actor(Actor::from_file("./examples/.assets/echo.wasm")?)?;
+    assert_eq!(1, host.actors().len());
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(700));
+    Ok(())
+}
+
+pub(crate) fn authorizer_blocks_providers() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{errors::ErrorKind, ProviderProvenance};
+
+    // The host's own providers load regardless of the authorizer
+    let host = HostBuilder::new()
+        .with_authorizer(ProviderAuthorizer {
+            capid: "test:allowed".to_string(),
+        })
+        .build();
+
+    let denied = NativeCapability::from_instance(TestProvider::new("test:denied"), None)?;
+    assert_eq!(&ProviderProvenance::default(), denied.provenance());
+    assert!(host.add_native_capability(denied).is_err());
+    host.add_native_capability(NativeCapability::from_instance(
+        TestProvider::new("test:allowed"),
+        None,
+    )?)?;
+    let caps = host.capabilities();
+    assert!(caps.values().any(|d| d.id == "test:allowed"));
+    assert!(!caps.values().any(|d| d.id == "test:denied"));
+
+    // Provider files are vetted before they are opened: the refused file is never handed to
+    // the dynamic loader, while the other one gets as far as failing to load
+    let dir = std::env::temp_dir();
+    let (denied, other) = (dir.join("wascc-denied.so"), dir.join("wascc-other.so"));
+    for path in &[&denied, &other] {
+        std::fs::write(path, b"not a library")?;
+    }
+    let err = host.add_native_capability_file(&denied, None).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Authorization(_)));
+    let err = host.add_native_capability_file(&other, None).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Plugin(_)));
+    for path in &[&denied, &other] {
+        std::fs::remove_file(path)?;
+    }
+
+    // A denied portable provider fails the caller too, and is never registered
+    let err = host
+        .add_capability(
+            Actor::from_file("./examples/.assets/wasi_provider.wasm")?,
+            None,
+            wascc_host::WasiParams::default(),
+        )
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Authorization(_)));
+    assert!(!host
+        .capabilities()
+        .values()
+        .any(|d| d.id == "wascc:wasidemo"));
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(700));
+    Ok(())
+}
+
+#[cfg(feature = "provider_archive")]
+pub(crate) fn provider_archives_are_verified() -> Result<(), Box<dyn Error>> {
+    use wascap::prelude::KeyPair;
+    use wascc_host::ProviderArchive;
+
+    let (issuer, subject) = (KeyPair::new_account(), KeyPair::new_module());
+    let host = HostBuilder::new()
+        .with_trusted_issuer(&issuer.public_key())
+        .build();
+    let path = std::env::temp_dir().join(format!("wascc-archive-{}.par", subject.public_key()));
+
+    // Signed by an untrusted issuer
+    let archive =
+        ProviderArchive::new("wascc:test", "Test", "Acme").with_library("wasm32-none", b"library");
+    std::fs::write(&path, archive.sign(&KeyPair::new_account(), &subject)?)?;
+    assert!(host.add_provider_archive(&path, None).is_err());
+
+    // No library for this host's target
+    let signed = archive.sign(&issuer, &subject)?;
+    std::fs::write(&path, &signed)?;
+    assert_eq!("wascc:test", ProviderArchive::from_file(&path)?.capid());
+    assert!(host.add_provider_archive(&path, None).is_err());
+
+    // Tampered
+    let mut tampered = signed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    std::fs::write(&path, &tampered)?;
+    assert!(ProviderArchive::from_file(&path).is_err());
+    assert!(host.add_provider_archive(&path, None).is_err());
+
+    std::fs::remove_file(&path)?;
+    host.shutdown()?;
+    Ok(())
+}
+
+struct ProviderAuthorizer {
+    capid: String,
+}
+
+impl Authorizer for ProviderAuthorizer {
+    fn can_load(&self, _claims: &wascap::prelude::Claims<wascap::prelude::Actor>) -> bool {
+        true
+    }
+    fn can_invoke(
+        &self,
+        _claims: &wascap::prelude::Claims<wascap::prelude::Actor>,
+        _target: &wascc_host::WasccEntity,
+        _operation: &str,
+    ) -> bool {
+        true
+    }
+    fn can_load_provider(
+        &self,
+        descriptor: &wascc_codec::capabilities::CapabilityDescriptor,
+        _provenance: &wascc_host::ProviderProvenance,
+    ) -> bool {
+        descriptor.id == self.capid
+    }
+    fn can_load_provider_file(&self, provenance: &wascc_host::ProviderProvenance) -> bool {
+        !matches!(&provenance.path, Some(p) if p.to_string_lossy().ends_with("denied.so"))
+    }
+}
+
+struct TestProvider {
+    capid: String,
+}
+
+impl TestProvider {
+    fn new(capid: &str) -> TestProvider {
+        TestProvider {
+            capid: capid.to_string(),
+        }
+    }
+}
+
+impl wascc_codec::capabilities::CapabilityProvider for TestProvider {
+    fn configure_dispatch(
+        &self,
+        _dispatcher: Box<dyn wascc_codec::capabilities::Dispatcher>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        _actor: &str,
+        op: &str,
+        _msg: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if op == wascc_codec::capabilities::OP_GET_CAPABILITY_DESCRIPTOR {
+            let descriptor = wascc_codec::capabilities::CapabilityDescriptor::builder()
+                .id(&self.capid)
+                .name("Test Provider")
+                .build();
+            wascc_codec::serialize(descriptor)
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+pub(crate) fn actor_calls_need_claims() -> Result<(), Box<dyn Error>> {
+    use crate::common::{sign_actor_with_caps, ANSWERING_GUEST, CALLING_GUEST};
+    use wascc_host::ActorCallPolicy;
+
+    // Callers invoke the target through its `target` call alias
+    let target = || sign_actor_with_caps(ANSWERING_GUEST, &[], &["call_alias:target"]);
+
+    // Under the default policy a caller must still claim its target
+    let host = Host::new();
+    let callee = target()?;
+    let callee_pk = callee.public_key();
+    host.add_actor(callee)?;
+    let unclaimed = sign_actor_with_caps(CALLING_GUEST, &[], &[])?;
+    let unclaimed_pk = unclaimed.public_key();
+    host.add_actor(unclaimed)?;
+    assert!(host.call_actor(&unclaimed_pk, "Call", &[]).is_err());
+    let claimed = sign_actor_with_caps(CALLING_GUEST, &[&callee_pk], &[])?;
+    let claimed_pk = claimed.public_key();
+    host.add_actor(claimed)?;
+    host.call_actor(&claimed_pk, "Call", &[])?;
+    host.shutdown()?;
+
+    // A restricted policy also requires the call to be on its allowlist
+    let host = HostBuilder::new()
+        .with_actor_call_policy(ActorCallPolicy::restricted())
+        .build();
+    let callee = target()?;
+    let callee_pk = callee.public_key();
+    host.add_actor(callee)?;
+    let claimed = sign_actor_with_caps(CALLING_GUEST, &[&callee_pk], &[])?;
+    let claimed_pk = claimed.public_key();
+    host.add_actor(claimed)?;
+    assert!(host.call_actor(&claimed_pk, "Call", &[]).is_err());
+    host.allow_actor_call(&claimed_pk, &callee_pk);
+    host.call_actor(&claimed_pk, "Call", &[])?;
+    host.revoke_actor_call(&claimed_pk, &callee_pk)?;
+    assert!(host.call_actor(&claimed_pk, "Call", &[]).is_err());
+    host.shutdown()?;
+    Ok(())
+}
+
 struct DenyAuthorizer {
     deny_load: bool,
     deny_invoke: bool,
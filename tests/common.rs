@@ -82,9 +82,55 @@ pub fn generate_port_config(port: u16) -> HashMap<String, String> {
 }
 
 pub fn generate_resigned_actor(bytes: &[u8]) -> Result<Actor, Box<dyn Error>> {
+    use wascap::prelude::KeyPair;
+
+    sign_actor(bytes, &KeyPair::new_account(), &KeyPair::new_module(), 1)
+}
+
+// Signs a module as a given revision of the actor identified by the module key
+pub fn sign_actor(
+    bytes: &[u8],
+    issuer: &wascap::prelude::KeyPair,
+    module: &wascap::prelude::KeyPair,
+    rev: i32,
+) -> Result<Actor, Box<dyn Error>> {
+    sign_actor_with_tags(bytes, issuer, module, rev, &[])
+}
+
+// Signs a module with new keys, claiming the given capabilities and tags
+pub fn sign_actor_with_caps(
+    bytes: &[u8],
+    caps: &[&str],
+    tags: &[&str],
+) -> Result<Actor, Box<dyn Error>> {
+    use wascap::prelude::*;
+
+    let issuer = KeyPair::new_account();
+    let claims = ClaimsBuilder::<Actor>::new()
+        .issuer(&issuer.public_key())
+        .subject(&KeyPair::new_module().public_key())
+        .with_metadata(Actor {
+            name: Some("test".to_string()),
+            caps: Some(caps.iter().map(|c| c.to_string()).collect()),
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..Default::default()
+        })
+        .build();
+    let embedded = wasm::embed_claims(&bytes, &claims, &issuer)?;
+
+    Ok(wascc_host::Actor::from_slice(&embedded)?)
+}
+
+// Signs a module as `sign_actor` does, with the given tags in its claims
+pub fn sign_actor_with_tags(
+    bytes: &[u8],
+    issuer: &wascap::prelude::KeyPair,
+    module: &wascap::prelude::KeyPair,
+    rev: i32,
+    tags: &[&str],
+) -> Result<Actor, Box<dyn Error>> {
     use wascap::prelude::*;
 
-    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
     let claims = ClaimsBuilder::<Actor>::new()
         .issuer(&issuer.public_key())
         .subject(&module.public_key())
@@ -94,6 +140,8 @@ pub fn generate_resigned_actor(bytes: &[u8]) -> Result<Actor, Box<dyn Error>> {
                 caps::HTTP_SERVER.to_string(),
                 caps::KEY_VALUE.to_string(),
             ]),
+            rev: Some(rev),
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
             ..Default::default()
         })
         .build();
@@ -101,3 +149,122 @@ pub fn generate_resigned_actor(bytes: &[u8]) -> Result<Actor, Box<dyn Error>> {
 
     Ok(wascc_host::Actor::from_slice(&embedded)?)
 }
+
+pub fn empty_http_request() -> Vec<u8> {
+    wascc_codec::serialize(wascc_codec::http::Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: vec![],
+    })
+    .unwrap()
+}
+
+// A minimal waPC guest whose `__guest_call` never returns:
+// (module (memory (export "memory") 1)
+//   (func (export "__guest_call") (param i32 i32) (result i32) (loop (br 0)) (i32.const 0)))
+pub const SPINNING_GUEST: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // types
+    0x03, 0x02, 0x01, 0x00, // functions
+    0x05, 0x03, 0x01, 0x00, 0x01, // memory
+    0x07, 0x19, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0c, 0x5f, 0x5f, 0x67,
+    0x75, 0x65, 0x73, 0x74, 0x5f, 0x63, 0x61, 0x6c, 0x6c, 0x00, 0x00, // exports
+    0x0a, 0x0b, 0x01, 0x09, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x41, 0x00, 0x0b, // code
+];
+
+// A module that imports a function the waPC host doesn't provide:
+// (module (import "wapc" "__bogus" (func)))
+pub const BOGUS_IMPORT_GUEST: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+    0x02, 0x10, 0x01, 0x04, 0x77, 0x61, 0x70, 0x63, 0x07, 0x5f, 0x5f, 0x62, 0x6f, 0x67, 0x75, 0x73,
+    0x00, 0x00, // imports
+];
+
+// A waPC guest that answers every operation with an empty response:
+// (module
+//   (import "wapc" "__guest_response" (func (param i32 i32)))
+//   (memory (export "memory") 1)
+//   (func (export "__guest_call") (param i32 i32) (result i32)
+//     (call 0 (i32.const 0) (i32.const 0)) (i32.const 1)))
+pub const ANSWERING_GUEST: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x0c, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x00, 0x60, 0x02, 0x7f, 0x7f, 0x01,
+    0x7f, // types
+    0x02, 0x19, 0x01, 0x04, 0x77, 0x61, 0x70, 0x63, 0x10, 0x5f, 0x5f, 0x67, 0x75, 0x65, 0x73, 0x74,
+    0x5f, 0x72, 0x65, 0x73, 0x70, 0x6f, 0x6e, 0x73, 0x65, 0x00, 0x00, // imports
+    0x03, 0x02, 0x01, 0x01, // functions
+    0x05, 0x03, 0x01, 0x00, 0x01, // memory
+    0x07, 0x19, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0c, 0x5f, 0x5f, 0x67,
+    0x75, 0x65, 0x73, 0x74, 0x5f, 0x63, 0x61, 0x6c, 0x6c, 0x00, 0x01, // exports
+    0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x41, 0x00, 0x41, 0x00, 0x10, 0x00, 0x41, 0x01,
+    0x0b, // code
+];
+
+// A waPC guest whose `__guest_call` calls operation `Ping` on the `target` namespace, which
+// can be an actor's call alias, and fails if the host call does:
+// (module
+//   (import "wapc" "__host_call" (func (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+//   (import "wapc" "__guest_response" (func (param i32 i32)))
+//   (memory (export "memory") 1)
+//   (data (i32.const 0) "default") (data (i32.const 8) "target") (data (i32.const 16) "Ping")
+//   (func (export "__guest_call") (param i32 i32) (result i32)
+//     (call 1 (i32.const 0) (i32.const 0))
+//     (call 0 (i32.const 0) (i32.const 7) (i32.const 8) (i32.const 6)
+//       (i32.const 16) (i32.const 4) (i32.const 0) (i32.const 0))))
+pub const CALLING_GUEST: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x18, 0x03, 0x60, 0x08, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60,
+    0x02, 0x7f, 0x7f, 0x00, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // types
+    0x02, 0x2c, 0x02, 0x04, 0x77, 0x61, 0x70, 0x63, 0x0b, 0x5f, 0x5f, 0x68, 0x6f, 0x73, 0x74, 0x5f,
+    0x63, 0x61, 0x6c, 0x6c, 0x00, 0x00, 0x04, 0x77, 0x61, 0x70, 0x63, 0x10, 0x5f, 0x5f, 0x67, 0x75,
+    0x65, 0x73, 0x74, 0x5f, 0x72, 0x65, 0x73, 0x70, 0x6f, 0x6e, 0x73, 0x65, 0x00,
+    0x01, // imports
+    0x03, 0x02, 0x01, 0x02, // functions
+    0x05, 0x03, 0x01, 0x00, 0x01, // memory
+    0x07, 0x19, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0c, 0x5f, 0x5f, 0x67,
+    0x75, 0x65, 0x73, 0x74, 0x5f, 0x63, 0x61, 0x6c, 0x6c, 0x00, 0x02, // exports
+    0x0a, 0x1c, 0x01, 0x1a, 0x00, 0x41, 0x00, 0x41, 0x00, 0x10, 0x01, 0x41, 0x00, 0x41, 0x07, 0x41,
+    0x08, 0x41, 0x06, 0x41, 0x10, 0x41, 0x04, 0x41, 0x00, 0x41, 0x00, 0x10, 0x00,
+    0x0b, // code
+    0x0b, 0x21, 0x03, 0x00, 0x41, 0x00, 0x0b, 0x07, 0x64, 0x65, 0x66, 0x61, 0x75, 0x6c, 0x74, 0x00,
+    0x41, 0x08, 0x0b, 0x06, 0x74, 0x61, 0x72, 0x67, 0x65, 0x74, 0x00, 0x41, 0x10, 0x0b, 0x04, 0x50,
+    0x69, 0x6e, 0x67, // data
+];
+
+// A waPC guest whose `__guest_call` calls operation `Get` on the `wascc:keyvalue` provider
+// bound to the name `hot`, and fails if the host call does:
+// (module
+//   (import "wapc" "__host_call" (func (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+//   (import "wapc" "__guest_response" (func (param i32 i32)))
+//   (memory (export "memory") 1)
+//   (data (i32.const 0) "hot") (data (i32.const 8) "wascc:keyvalue") (data (i32.const 24) "Get")
+//   (func (export "__guest_call") (param i32 i32) (result i32)
+//     (call 1 (i32.const 0) (i32.const 0))
+//     (call 0 (i32.const 0) (i32.const 3) (i32.const 8) (i32.const 14)
+//       (i32.const 24) (i32.const 3) (i32.const 0) (i32.const 0))))
+pub const KEYVALUE_GUEST: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x18, 0x03, 0x60, 0x08, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60,
+    0x02, 0x7f, 0x7f, 0x00, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // types
+    0x02, 0x2c, 0x02, 0x04, 0x77, 0x61, 0x70, 0x63, 0x0b, 0x5f, 0x5f, 0x68, 0x6f, 0x73, 0x74, 0x5f,
+    0x63, 0x61, 0x6c, 0x6c, 0x00, 0x00, 0x04, 0x77, 0x61, 0x70, 0x63, 0x10, 0x5f, 0x5f, 0x67, 0x75,
+    0x65, 0x73, 0x74, 0x5f, 0x72, 0x65, 0x73, 0x70, 0x6f, 0x6e, 0x73, 0x65, 0x00,
+    0x01, // imports
+    0x03, 0x02, 0x01, 0x02, // functions
+    0x05, 0x03, 0x01, 0x00, 0x01, // memory
+    0x07, 0x19, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0c, 0x5f, 0x5f, 0x67,
+    0x75, 0x65, 0x73, 0x74, 0x5f, 0x63, 0x61, 0x6c, 0x6c, 0x00, 0x02, // exports
+    0x0a, 0x1c, 0x01, 0x1a, 0x00, 0x41, 0x00, 0x41, 0x00, 0x10, 0x01, 0x41, 0x00, 0x41, 0x03, 0x41,
+    0x08, 0x41, 0x0e, 0x41, 0x18, 0x41, 0x03, 0x41, 0x00, 0x41, 0x00, 0x10, 0x00,
+    0x0b, // code
+    0x0b, 0x24, 0x03, 0x00, 0x41, 0x00, 0x0b, 0x03, 0x68, 0x6f, 0x74, 0x00, 0x41, 0x08, 0x0b, 0x0e,
+    0x77, 0x61, 0x73, 0x63, 0x63, 0x3a, 0x6b, 0x65, 0x79, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x00, 0x41,
+    0x18, 0x0b, 0x03, 0x47, 0x65, 0x74, // data
+];
+
+pub fn get_spinning_actor() -> Result<Actor, Box<dyn Error>> {
+    generate_resigned_actor(SPINNING_GUEST)
+}
@@ -1,4 +1,5 @@
 use reqwest;
+use std::collections::HashMap;
 use std::error::Error;
 use wascc_host::Host;
 
@@ -50,3 +51,980 @@ pub(crate) fn kv_host() -> Result<(), Box<dyn Error>> {
     let _: () = con.del(&rkey)?;
     Ok(())
 }
+
+pub(crate) fn portable_provider_bindings() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{Actor, WasiParams};
+
+    let consumer = "MDNPIQOU5EEHTP4TKY2APFOJTTEYYARN3ZIJTRWRYWHX6B4MFSO6ZCRT";
+    let host = Host::new();
+    host.add_actor(Actor::from_file("./examples/.assets/wasi_consumer.wasm")?)?;
+    host.add_capability(
+        Actor::from_file("./examples/.assets/wasi_provider.wasm")?,
+        None,
+        WasiParams::default(),
+    )?;
+    assert!(host
+        .capabilities()
+        .contains_key(&("default".to_string(), "wascc:wasidemo".to_string())));
+
+    host.set_binding(consumer, "wascc:wasidemo", None, HashMap::new())?;
+    let res = host.call_actor(
+        consumer,
+        "HandleRequest",
+        &crate::common::empty_http_request(),
+    )?;
+    let resp: wascc_codec::http::Response = wascc_codec::deserialize(&res).unwrap();
+    assert_eq!(200, resp.status_code);
+
+    // Removing the provider tears down the private actor-provider subject
+    host.remove_native_capability("wascc:wasidemo", None)?;
+    std::thread::sleep(::std::time::Duration::from_millis(100));
+    assert!(!host
+        .capabilities()
+        .contains_key(&("default".to_string(), "wascc:wasidemo".to_string())));
+    let res = host.call_actor(
+        consumer,
+        "HandleRequest",
+        &crate::common::empty_http_request(),
+    );
+    assert!(res.map(|r| r.is_empty()).unwrap_or(true));
+
+    host.shutdown()?;
+    std::thread::sleep(::std::time::Duration::from_millis(500));
+    Ok(())
+}
+
+pub(crate) fn scaled_actor_instances() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+    assert_eq!(1, host.actor_instances(&pk));
+    assert!(host.scale_actor(&pk, 0).is_err());
+
+    host.scale_actor(&pk, 3)?;
+    assert_eq!(3, host.actor_instances(&pk));
+
+    let handles: Vec<_> = (0..6)
+        .map(|_| {
+            let h = host.clone();
+            let pk = pk.clone();
+            std::thread::spawn(move || {
+                h.call_actor(&pk, "HandleRequest", &crate::common::empty_http_request())
+                    .is_ok()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap());
+    }
+
+    host.scale_actor(&pk, 1)?;
+    std::thread::sleep(::std::time::Duration::from_millis(100));
+    assert_eq!(1, host.actor_instances(&pk));
+    assert_eq!(1, host.actors().len());
+    host.call_actor(&pk, "HandleRequest", &crate::common::empty_http_request())?;
+
+    host.remove_actor(&pk)?;
+    std::thread::sleep(::std::time::Duration::from_millis(100));
+    assert_eq!(0, host.actor_instances(&pk));
+    assert!(host.claims_for_actor(&pk).is_none());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_execution_timeout() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::InvocationErrorCode;
+
+    let host = Host::new();
+    let spinner = crate::common::get_spinning_actor()?.with_timeout(Duration::from_millis(200));
+    let pk = spinner.public_key();
+    host.add_actor(spinner)?;
+
+    // The actor's own deadline applies to every call
+    let start = Instant::now();
+    let res = host.call_actor(&pk, "Spin", &[]);
+    assert!(res.is_err());
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // A per-call deadline shorter than the actor's wins, and the interrupted
+    // instance keeps serving requests afterwards
+    let start = Instant::now();
+    let res = host.call_actor_with_timeout(&pk, "Spin", &[], Duration::from_millis(50));
+    match res.unwrap_err().kind() {
+        ErrorKind::Invocation { code, .. } => assert_eq!(InvocationErrorCode::Timeout, *code),
+        _ => panic!("Expected an invocation error"),
+    }
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    let echo = crate::common::get_hello_actor()?;
+    let echo_pk = echo.public_key();
+    host.add_actor(echo)?;
+    host.call_actor_with_timeout(
+        &echo_pk,
+        "HandleRequest",
+        &crate::common::empty_http_request(),
+        Duration::from_secs(5),
+    )?;
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn unknown_wapc_import() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    let actor = crate::common::generate_resigned_actor(crate::common::BOGUS_IMPORT_GUEST)?;
+    let pk = actor.public_key();
+    let err = host.add_actor(actor).unwrap_err();
+    assert!(err.to_string().contains("__bogus"), "{}", err);
+    assert_eq!(0, host.actor_instances(&pk));
+    assert!(host.claims_for_actor(&pk).is_none());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn graceful_shutdown() -> Result<(), Box<dyn Error>> {
+    use std::thread;
+    use std::time::Duration;
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::InvocationErrorCode;
+
+    let host = Host::new();
+    let spinner = crate::common::get_spinning_actor()?.with_timeout(Duration::from_millis(500));
+    let pk = spinner.public_key();
+    host.add_actor(spinner)?;
+    let echo = crate::common::get_hello_actor()?;
+    let echo_pk = echo.public_key();
+    host.add_actor(echo)?;
+
+    // An invocation already in flight is allowed to finish
+    let h = host.clone();
+    let spin = thread::spawn(move || h.call_actor(&pk, "Spin", &[]));
+    thread::sleep(Duration::from_millis(100));
+    let h = host.clone();
+    let shutdown = thread::spawn(move || h.shutdown_graceful(Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(100));
+
+    // ...while new invocations are refused
+    let res = host.call_actor(
+        &echo_pk,
+        "HandleRequest",
+        &crate::common::empty_http_request(),
+    );
+    match res.unwrap_err().kind() {
+        ErrorKind::Invocation { code, .. } => assert_eq!(InvocationErrorCode::Unavailable, *code),
+        _ => panic!("Expected an invocation error"),
+    }
+
+    match spin.join().unwrap().unwrap_err().kind() {
+        ErrorKind::Invocation { code, .. } => assert_eq!(InvocationErrorCode::Timeout, *code),
+        _ => panic!("Expected an invocation error"),
+    }
+    let report = shutdown.join().unwrap()?;
+    assert!(report.is_clean(), "{:?}", report);
+    assert!(host.actors().is_empty());
+    Ok(())
+}
+
+pub(crate) fn pause_resume_drain() -> Result<(), Box<dyn Error>> {
+    use std::thread;
+    use std::time::Duration;
+
+    let host = Host::new();
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+
+    // Invocations are held while the actor is paused and run once it resumes
+    host.pause_actor(&pk)?;
+    let (h, p) = (host.clone(), pk.clone());
+    let (done_s, done_r) = std::sync::mpsc::channel();
+    let call = thread::spawn(move || {
+        let res = h.call_actor(&p, "HandleRequest", &crate::common::empty_http_request());
+        let _ = done_s.send(());
+        res
+    });
+    assert!(done_r.recv_timeout(Duration::from_millis(200)).is_err());
+    host.resume_actor(&pk)?;
+    call.join().unwrap()?;
+
+    // Draining finishes the held invocations before removing the actor
+    host.pause_actor(&pk)?;
+    let (h, p) = (host.clone(), pk.clone());
+    let call = thread::spawn(move || {
+        h.call_actor(&p, "HandleRequest", &crate::common::empty_http_request())
+    });
+    thread::sleep(Duration::from_millis(100));
+    host.drain_actor(&pk, Duration::from_secs(5))?;
+    call.join().unwrap()?;
+    assert_eq!(0, host.actor_instances(&pk));
+    assert!(host.pause_actor(&pk).is_err());
+
+    // A drain that runs out of time still removes the actor but reports what was outstanding
+    let spinner = crate::common::get_spinning_actor()?.with_timeout(Duration::from_millis(500));
+    let pk = spinner.public_key();
+    host.add_actor(spinner)?;
+    let (h, p) = (host.clone(), pk.clone());
+    let call = thread::spawn(move || h.call_actor(&p, "Spin", &[]));
+    thread::sleep(Duration::from_millis(100));
+    let err = host
+        .drain_actor(&pk, Duration::from_millis(50))
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("1 instance(s) still running"),
+        "{}",
+        err
+    );
+    assert!(call.join().unwrap().is_err());
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(0, host.actor_instances(&pk));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn scale_down_during_calls() -> Result<(), Box<dyn Error>> {
+    let host = Host::new();
+    let echo = crate::common::get_hello_actor()?;
+    let pk = echo.public_key();
+    host.add_actor(echo)?;
+    host.scale_actor(&pk, 4)?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let h = host.clone();
+            let pk = pk.clone();
+            std::thread::spawn(move || {
+                (0..25)
+                    .map(|_| {
+                        h.call_actor(&pk, "HandleRequest", &crate::common::empty_http_request())
+                    })
+                    .filter(|r| r.is_err())
+                    .count()
+            })
+        })
+        .collect();
+    // Invocations queued on the stopped instances are handed to the remaining one
+    std::thread::sleep(::std::time::Duration::from_millis(20));
+    host.scale_actor(&pk, 1)?;
+    for handle in handles {
+        assert_eq!(0, handle.join().unwrap());
+    }
+    assert_eq!(1, host.actor_instances(&pk));
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn replace_actor_rollback() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use wascap::prelude::KeyPair;
+    use wascc_host::{ReplaceOptions, ReplaceOutcome, ResourceLimits};
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let echo = std::fs::read("./examples/.assets/echo.wasm")?;
+    let echo2 = std::fs::read("./examples/.assets/echo2.wasm")?;
+    let request = crate::common::empty_http_request();
+    let smoke_test = ReplaceOptions::new().with_smoke_test("HandleRequest", &request);
+
+    let host = Host::new();
+    let actor = crate::common::sign_actor(&echo, &issuer, &module, 1)?
+        .with_limits(ResourceLimits::new().with_fuel(50_000_000));
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    host.scale_actor(&pk, 2)?;
+
+    let report = host.replace_actor_with(
+        crate::common::sign_actor(&echo2, &issuer, &module, 2)?,
+        smoke_test.clone(),
+    )?;
+    assert!(report.is_replaced(), "{:?}", report);
+    assert_eq!(
+        (Some(1), Some(2)),
+        (report.previous_revision, report.revision)
+    );
+    assert_eq!(2, report.instances);
+    assert_eq!(Some(true), report.smoke_test_passed);
+    let rev = host.claims_for_actor(&pk).unwrap().metadata.unwrap().rev;
+    assert_eq!(Some(2), rev);
+
+    // A module that traps fails the smoke test and is rolled back. The test runs on a staged
+    // copy of the module, so the instances keep serving the current module meanwhile
+    let done = Arc::new(AtomicBool::new(false));
+    let (h, p, r, d) = (host.clone(), pk.clone(), request.clone(), done.clone());
+    let traffic = std::thread::spawn(move || {
+        let mut failed = 0;
+        while !d.load(Ordering::SeqCst) {
+            if h.call_actor(&p, "HandleRequest", &r).is_err() {
+                failed += 1;
+            }
+        }
+        failed
+    });
+    let spinner = crate::common::sign_actor(crate::common::SPINNING_GUEST, &issuer, &module, 3)?;
+    let report = host.replace_actor_with(spinner, smoke_test)?;
+    done.store(true, Ordering::SeqCst);
+    assert_eq!(0, traffic.join().unwrap());
+    match report.outcome {
+        ReplaceOutcome::RolledBack { .. } => {}
+        o => panic!("Unexpected outcome {:?}", o),
+    }
+    assert_eq!(Some(false), report.smoke_test_passed);
+    let rev = host.claims_for_actor(&pk).unwrap().metadata.unwrap().rev;
+    assert_eq!(Some(2), rev);
+    host.call_actor(&pk, "HandleRequest", &request)?;
+    host.call_actor(&pk, "HandleRequest", &request)?;
+
+    // Only the actor's own issuer can replace it
+    let other = crate::common::sign_actor(&echo2, &KeyPair::new_account(), &module, 4)?;
+    assert!(host.replace_actor(other).is_err());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn canary_actor_upgrade() -> Result<(), Box<dyn Error>> {
+    use wascap::prelude::KeyPair;
+    use wascc_host::{CanaryOptions, ReplaceOptions, ResourceLimits};
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let echo = std::fs::read("./examples/.assets/echo.wasm")?;
+    let echo2 = std::fs::read("./examples/.assets/echo2.wasm")?;
+    let request = crate::common::empty_http_request();
+
+    let host = Host::new();
+    let actor = crate::common::sign_actor(&echo, &issuer, &module, 1)?
+        .with_limits(ResourceLimits::new().with_fuel(50_000_000));
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+
+    // Half of the invocations go to the canary
+    let v2 = crate::common::sign_actor(&echo2, &issuer, &module, 2)?;
+    host.start_canary(v2, CanaryOptions::new().with_weight(50))?;
+    for _ in 0..10 {
+        host.call_actor(&pk, "HandleRequest", &request)?;
+    }
+    let status = host.canary_status(&pk).unwrap();
+    assert_eq!(
+        (Some(2), Some(1)),
+        (status.revision, status.stable_revision)
+    );
+    assert_eq!((5, 5), (status.invocations, status.stable_invocations));
+    assert_eq!(0.0, status.error_rate());
+    let status = host.abort_canary(&pk)?;
+    assert_eq!(5, status.invocations);
+    assert!(host.canary_status(&pk).is_none());
+
+    // A failing canary is withdrawn once its error rate crosses the threshold
+    let spinner = crate::common::sign_actor(crate::common::SPINNING_GUEST, &issuer, &module, 3)?;
+    host.start_canary(
+        spinner,
+        CanaryOptions::new()
+            .with_weight(100)
+            .with_max_error_rate(0.5, 2),
+    )?;
+    assert!(host.call_actor(&pk, "HandleRequest", &request).is_err());
+    assert!(host.canary_status(&pk).is_some());
+    assert!(host.call_actor(&pk, "HandleRequest", &request).is_err());
+    assert!(host.canary_status(&pk).is_none());
+    host.call_actor(&pk, "HandleRequest", &request)?;
+
+    // Promoting a canary makes it the actor's current revision
+    let v2 = crate::common::sign_actor(&echo2, &issuer, &module, 2)?;
+    host.start_canary(v2, CanaryOptions::new().with_weight(0))?;
+    host.set_canary_weight(&pk, 100)?;
+    let report = host.promote_canary(&pk, ReplaceOptions::new())?;
+    assert!(report.is_replaced(), "{:?}", report);
+    assert!(host.canary_status(&pk).is_none());
+    let rev = host.claims_for_actor(&pk).unwrap().metadata.unwrap().rev;
+    assert_eq!(Some(2), rev);
+    host.call_actor(&pk, "HandleRequest", &request)?;
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_revision_history() -> Result<(), Box<dyn Error>> {
+    use wascap::prelude::KeyPair;
+    use wascc_host::{ActorSource, ReplaceOptions};
+
+    let (issuer, module) = (KeyPair::new_account(), KeyPair::new_module());
+    let echo = std::fs::read("./examples/.assets/echo.wasm")?;
+    let echo2 = std::fs::read("./examples/.assets/echo2.wasm")?;
+
+    let host = Host::new();
+    let actor = crate::common::sign_actor(&echo, &issuer, &module, 1)?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    host.replace_actor(crate::common::sign_actor(&echo2, &issuer, &module, 2)?)?;
+
+    let revisions = host.actor_revisions(&pk);
+    assert_eq!(2, revisions.len());
+    assert_eq!(
+        vec![(Some(1), false), (Some(2), true)],
+        revisions
+            .iter()
+            .map(|r| (r.rev, r.current))
+            .collect::<Vec<_>>()
+    );
+    assert!(revisions.iter().all(|r| r.cached));
+    assert_eq!(ActorSource::Slice, revisions[0].source);
+
+    let report = host.rollback_actor(&pk, 1, ReplaceOptions::new())?;
+    assert!(report.is_replaced(), "{:?}", report);
+    let rev = host.claims_for_actor(&pk).unwrap().metadata.unwrap().rev;
+    assert_eq!(Some(1), rev);
+    let revisions = host.actor_revisions(&pk);
+    assert_eq!(3, revisions.len());
+    assert!(revisions[2].current);
+    assert_eq!(Some(1), revisions[2].rev);
+    assert!(host.rollback_actor(&pk, 7, ReplaceOptions::new()).is_err());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn call_aliases() -> Result<(), Box<dyn Error>> {
+    use wascap::prelude::KeyPair;
+
+    let issuer = KeyPair::new_account();
+    let echo = std::fs::read("./examples/.assets/echo.wasm")?;
+    let first = crate::common::sign_actor_with_tags(
+        &echo,
+        &issuer,
+        &KeyPair::new_module(),
+        1,
+        &["call_alias:echo"],
+    )?;
+    let second = crate::common::sign_actor_with_tags(
+        &echo,
+        &issuer,
+        &KeyPair::new_module(),
+        1,
+        &["call_alias:echo"],
+    )?;
+    let (first_pk, second_pk) = (first.public_key(), second.public_key());
+
+    let host = Host::new();
+    host.add_actor(first)?;
+    // The second actor declares an alias that is already taken
+    assert!(host.add_actor(second).is_err());
+    assert!(host.claims_for_actor(&second_pk).is_none());
+    assert!(host.register_call_alias("echo", &second_pk).is_err());
+    assert!(host.register_call_alias("wascc:echo", &first_pk).is_err());
+
+    host.register_call_alias("greeter", &first_pk)?;
+    let aliases: Vec<_> = host
+        .call_aliases()
+        .into_iter()
+        .map(|a| (a.alias, a.actor, a.declared))
+        .collect();
+    assert_eq!(
+        vec![
+            ("echo".to_string(), first_pk.to_string(), true),
+            ("greeter".to_string(), first_pk.to_string(), false),
+        ],
+        aliases
+    );
+
+    // Declared aliases go away with the actor, registered ones stay until removed
+    host.remove_actor(&first_pk)?;
+    std::thread::sleep(::std::time::Duration::from_millis(100));
+    let aliases: Vec<_> = host.call_aliases().into_iter().map(|a| a.alias).collect();
+    assert_eq!(vec!["greeter".to_string()], aliases);
+    host.remove_call_alias("greeter")?;
+    assert!(host.remove_call_alias("greeter").is_err());
+    assert!(host.call_aliases().is_empty());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_resource_limits() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::{InvocationErrorCode, ResourceLimits};
+
+    let host = Host::new();
+
+    // The echo actor needs more than a single page of memory to start
+    let echo =
+        crate::common::get_hello_actor()?.with_limits(ResourceLimits::new().with_max_memory(65536));
+    let echo_pk = echo.public_key();
+    assert!(host.add_actor(echo).is_err());
+    assert!(host.claims_for_actor(&echo_pk).is_none());
+
+    let echo = crate::common::get_hello_actor()?.with_limits(
+        ResourceLimits::new()
+            .with_max_memory(32 * 1024 * 1024)
+            .with_max_table_elements(1024)
+            .with_fuel(10_000_000),
+    );
+    host.add_actor(echo)?;
+    host.call_actor(
+        &echo_pk,
+        "HandleRequest",
+        &crate::common::empty_http_request(),
+    )?;
+
+    // Running out of fuel ends the invocation without needing a deadline
+    let spinner =
+        crate::common::get_spinning_actor()?.with_limits(ResourceLimits::new().with_fuel(100_000));
+    let spinner_pk = spinner.public_key();
+    host.add_actor(spinner)?;
+    let start = Instant::now();
+    let res = host.call_actor(&spinner_pk, "Spin", &[]);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    match res.unwrap_err().kind() {
+        ErrorKind::Invocation {
+            invocation_id,
+            code,
+            reason,
+        } => {
+            assert!(!invocation_id.is_empty());
+            assert_eq!(InvocationErrorCode::GuestTrap, *code);
+            assert!(reason.contains("trapped"));
+        }
+        _ => panic!("Expected an invocation error"),
+    }
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn actor_restart_on_trap() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::{ResourceLimits, RestartPolicy};
+
+    let host = Host::new();
+
+    // Every call runs out of fuel and traps, so each call is a failure under this policy
+    let spinner = crate::common::get_spinning_actor()?
+        .with_limits(ResourceLimits::new().with_fuel(100_000))
+        .with_restart_policy(
+            RestartPolicy::new()
+                .with_max_restarts(1)
+                .with_max_consecutive_traps(1)
+                .with_backoff(Duration::from_millis(10)),
+        );
+    let spinner_pk = spinner.public_key();
+    host.add_actor(spinner)?;
+
+    // The first failure restarts the actor, which then keeps serving requests
+    assert!(host.call_actor(&spinner_pk, "Spin", &[]).is_err());
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(1, host.actor_instances(&spinner_pk));
+
+    // The second failure exhausts the policy and the actor is stopped
+    assert!(host.call_actor(&spinner_pk, "Spin", &[]).is_err());
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(0, host.actor_instances(&spinner_pk));
+    assert!(host.claims_for_actor(&spinner_pk).is_none());
+    assert!(host.call_actor(&spinner_pk, "Spin", &[]).is_err());
+
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+pub(crate) async fn async_host_calls() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+    use wascc_host::AsyncHost;
+
+    let host = AsyncHost::new(Host::new());
+    let echo = crate::common::get_hello_actor()?;
+    let echo_pk = echo.public_key();
+    let spinner = crate::common::get_spinning_actor()?;
+    let spinner_pk = spinner.public_key();
+    host.add_actor(echo).await?;
+    host.add_actor(spinner).await?;
+    assert_eq!(2, host.host().actors().len());
+
+    // A long-running call doesn't hold up other calls, even on a single-threaded runtime
+    let start = Instant::now();
+    let (spun, echoed) = tokio::join!(
+        async {
+            let res = host
+                .call_actor_with_timeout(&spinner_pk, "Spin", &[], Duration::from_millis(500))
+                .await;
+            (res, start.elapsed())
+        },
+        async {
+            let res = host
+                .call_actor(
+                    &echo_pk,
+                    "HandleRequest",
+                    &crate::common::empty_http_request(),
+                )
+                .await;
+            (res, start.elapsed())
+        }
+    );
+    assert!(spun.0.is_err());
+    assert!(echoed.0.is_ok());
+    assert!(echoed.1 < spun.1);
+
+    host.remove_actor(&spinner_pk).await?;
+    host.shutdown().await?;
+    Ok(())
+}
+
+// Requires the isolated_provider example, which `cargo test` builds
+#[cfg(unix)]
+pub(crate) fn isolated_provider_restarts() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+    use wascc_host::{NativeCapability, ProcessIsolation};
+
+    let runner = env!("CARGO_BIN_EXE_wascc-provider-runner");
+    let library = Path::new(runner).parent().unwrap().join(format!(
+        "examples/{}isolated_provider{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    let host = Host::new();
+    host.add_native_capability(NativeCapability::from_file_isolated(
+        &library,
+        Some("isolated".to_string()),
+        ProcessIsolation::new().with_runner(runner),
+    )?)?;
+    let pid = host
+        .capabilities()
+        .values()
+        .find(|d| d.name == "Isolated Provider")
+        .map(|d| d.long_description.to_string())
+        .unwrap();
+    assert_ne!(std::process::id().to_string(), pid);
+
+    let actor =
+        crate::common::generate_resigned_actor(&std::fs::read("./examples/.assets/echo.wasm")?)?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let log = std::env::temp_dir().join(format!("wascc-isolated-{}.log", pk));
+    let mut config = HashMap::new();
+    config.insert("log".to_string(), log.to_string_lossy().to_string());
+    host.set_binding(&pk, "wascc:keyvalue", Some("isolated".to_string()), config)?;
+
+    // Kill the provider process, the host replaces it and re-establishes the binding
+    std::process::Command::new("kill")
+        .args(&["-9", &pid])
+        .status()?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let lines = loop {
+        let lines: Vec<String> = std::fs::read_to_string(&log)?
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        if lines.len() >= 2 || Instant::now() > deadline {
+            break lines;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(2, lines.len());
+    assert_eq!(format!("{} {}", pid, pk), lines[0]);
+    assert!(lines[1].ends_with(&pk));
+    assert!(!lines[1].starts_with(&pid));
+
+    std::fs::remove_file(&log)?;
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn native_provider_hot_replacement() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::time::Duration;
+    use wascc_host::{HostEvent, NativeCapability, ProcessIsolation};
+
+    let runner = env!("CARGO_BIN_EXE_wascc-provider-runner");
+    let library = Path::new(runner).parent().unwrap().join(format!(
+        "examples/{}isolated_provider{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    let host = Host::new();
+    host.add_native_capability(NativeCapability::from_file(
+        &library,
+        Some("hot".to_string()),
+    )?)?;
+
+    let actor =
+        crate::common::generate_resigned_actor(&std::fs::read("./examples/.assets/echo.wasm")?)?;
+    let pk = actor.public_key();
+    host.add_actor(actor)?;
+    let log = std::env::temp_dir().join(format!("wascc-hot-{}.log", pk));
+    let mut config = HashMap::new();
+    config.insert("log".to_string(), log.to_string_lossy().to_string());
+    host.set_binding(&pk, "wascc:keyvalue", Some("hot".to_string()), config)?;
+
+    // Replace the in-process provider with one running in a child process
+    let events = host.events();
+    host.replace_native_capability(NativeCapability::from_file_isolated(
+        &library,
+        Some("hot".to_string()),
+        ProcessIsolation::new().with_runner(runner),
+    )?)?;
+    assert_eq!(
+        HostEvent::ProviderReplaced {
+            capid: "wascc:keyvalue".to_string(),
+            binding: "hot".to_string()
+        },
+        events.recv_timeout(Duration::from_secs(1))?
+    );
+    let pid = host
+        .capabilities()
+        .values()
+        .find(|d| d.name == "Isolated Provider")
+        .map(|d| d.long_description.to_string())
+        .unwrap();
+    assert_ne!(std::process::id().to_string(), pid);
+
+    // The binding was re-sent to the new provider before it took the old one's place
+    let lines: Vec<String> = std::fs::read_to_string(&log)?
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            format!("{} {}", std::process::id(), pk),
+            format!("{} {}", pid, pk)
+        ],
+        lines
+    );
+
+    // Replacing a provider that isn't running fails
+    assert!(host
+        .replace_native_capability(NativeCapability::from_file(
+            &library,
+            Some("cold".to_string())
+        )?)
+        .is_err());
+
+    std::fs::remove_file(&log)?;
+    host.shutdown()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn native_provider_replacement_under_load() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascc_host::{NativeCapability, ProcessIsolation};
+
+    let runner = env!("CARGO_BIN_EXE_wascc-provider-runner");
+    let library = Path::new(runner).parent().unwrap().join(format!(
+        "examples/{}isolated_provider{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    let host = Host::new();
+    host.add_native_capability(NativeCapability::from_file(
+        &library,
+        Some("hot".to_string()),
+    )?)?;
+
+    // Two actors that call the provider, in the order the replacements bind them
+    let mut pks = vec![];
+    for _ in 0..2 {
+        let actor = crate::common::sign_actor_with_caps(
+            crate::common::KEYVALUE_GUEST,
+            &["wascc:keyvalue"],
+            &[],
+        )?;
+        pks.push(actor.public_key());
+        host.add_actor(actor)?;
+    }
+    pks.sort();
+    let (first, second) = (pks[0].to_string(), pks[1].to_string());
+    let log = std::env::temp_dir().join(format!("wascc-load-{}.log", first));
+    let pid = std::process::id().to_string();
+    let config = |key: &str, value: &str| {
+        let mut config = HashMap::new();
+        config.insert("log".to_string(), log.to_string_lossy().to_string());
+        config.insert(key.to_string(), value.to_string());
+        config
+    };
+    let hot = Some("hot".to_string());
+    host.set_binding(
+        &first,
+        "wascc:keyvalue",
+        hot.clone(),
+        config("callback", "Call"),
+    )?;
+    // Only a provider running in this process accepts the second binding
+    host.set_binding(&second, "wascc:keyvalue", hot.clone(), config("pid", &pid))?;
+    host.call_actor(&first, "Call", &[])?;
+    let read_log = || -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(std::fs::read_to_string(&log)?
+            .lines()
+            .map(|l| l.to_string())
+            .collect())
+    };
+    let bound = read_log()?.len();
+
+    // A replacement running in a child process accepts the first binding and refuses the
+    // second, so it is unbound from the first one again before it is discarded
+    assert!(host
+        .replace_native_capability(NativeCapability::from_file_isolated(
+            &library,
+            hot.clone(),
+            ProcessIsolation::new().with_runner(runner),
+        )?)
+        .is_err());
+    let lines = read_log()?.split_off(bound);
+    let child = lines[0].split(' ').next().unwrap().to_string();
+    assert_ne!(pid, child);
+    assert_eq!(
+        vec![
+            format!("{} callback {}", child, first),
+            format!("{} {}", child, first),
+            format!("{} unbind {}", child, first),
+        ],
+        lines
+    );
+    host.call_actor(&first, "Call", &[])?;
+
+    // Invocations keep being served while a replacement is bound and swapped in, including
+    // the one it makes itself through the first actor while binding it
+    let stop = Arc::new(AtomicBool::new(false));
+    let traffic = {
+        let (host, first, stop) = (host.clone(), first.to_string(), stop.clone());
+        std::thread::spawn(move || {
+            let (mut calls, mut failures) = (0, 0);
+            while !stop.load(Ordering::SeqCst) {
+                calls += 1;
+                if host.call_actor(&first, "Call", &[]).is_err() {
+                    failures += 1;
+                }
+            }
+            (calls, failures)
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    host.replace_native_capability(NativeCapability::from_file(&library, hot)?)?;
+    std::thread::sleep(Duration::from_millis(50));
+    stop.store(true, Ordering::SeqCst);
+    let (calls, failures) = traffic.join().unwrap();
+    assert!(calls > 0);
+    assert_eq!(0, failures);
+    assert_eq!(
+        vec![
+            format!("{} callback {}", pid, first),
+            format!("{} {}", pid, first),
+            format!("{} {}", pid, second),
+        ],
+        read_log()?.split_off(bound + 3)
+    );
+
+    std::fs::remove_file(&log)?;
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn invocation_error_codes() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use wascc_host::errors::ErrorKind;
+    use wascc_host::{HostEvent, InvocationErrorCode, WasccEntity};
+
+    let host = Host::new();
+    match host
+        .call_actor("MNOSUCHACTOR", "Ping", &[])
+        .unwrap_err()
+        .kind()
+    {
+        ErrorKind::Invocation { code, .. } => assert_eq!(InvocationErrorCode::NotFound, *code),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    // The caller has no claim on the `target` capability, so its host call is refused
+    let caller = crate::common::generate_resigned_actor(crate::common::CALLING_GUEST)?;
+    let pk = caller.public_key();
+    host.add_actor(caller)?;
+    let events = host.events();
+    assert!(host.call_actor(&pk, "Call", &[]).is_err());
+    // The refused host call is reported, then the failure of the call to the caller
+    match events.recv_timeout(Duration::from_secs(1))? {
+        HostEvent::InvocationFailed { origin, code, .. } => {
+            assert_eq!(WasccEntity::Actor(pk.clone()), origin);
+            assert_eq!(InvocationErrorCode::Unauthorized, code);
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    match events.recv_timeout(Duration::from_secs(1))? {
+        HostEvent::InvocationFailed { target, code, .. } => {
+            assert_eq!(WasccEntity::Actor(pk.clone()), target);
+            assert_eq!(InvocationErrorCode::GuestFailure, code);
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+
+    host.shutdown()?;
+    Ok(())
+}
+
+pub(crate) fn host_events() -> Result<(), Box<dyn Error>> {
+    use wascc_host::{HostEvent, InvocationErrorCode, ResourceLimits, WasccEntity};
+
+    let host = Host::new();
+    let events = host.events();
+
+    let spinner =
+        crate::common::get_spinning_actor()?.with_limits(ResourceLimits::new().with_fuel(100_000));
+    let pk = spinner.public_key();
+    host.add_actor(spinner)?;
+    // The host's own providers report that they have loaded along the way, so each check
+    // skips to the next event of the kind it expects
+    assert_eq!(
+        Some(HostEvent::ActorStarting { actor: pk.clone() }),
+        events
+            .iter()
+            .find(|e| matches!(e, HostEvent::ActorStarting { .. }))
+    );
+    // Published before add_actor returns
+    assert_eq!(
+        Some(HostEvent::ActorStarted { actor: pk.clone() }),
+        events
+            .try_iter()
+            .find(|e| matches!(e, HostEvent::ActorStarted { .. }))
+    );
+
+    assert!(host.call_actor(&pk, "Spin", &[]).is_err());
+    let failed = events
+        .iter()
+        .find(|e| matches!(e, HostEvent::InvocationFailed { .. }));
+    match failed {
+        Some(HostEvent::InvocationFailed {
+            target,
+            operation,
+            code,
+            ..
+        }) => {
+            assert_eq!(WasccEntity::Actor(pk.clone()), target);
+            assert_eq!("Spin", operation);
+            assert_eq!(InvocationErrorCode::GuestTrap, code);
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+
+    host.remove_actor(&pk)?;
+    assert_eq!(
+        Some(HostEvent::ActorStopped { actor: pk }),
+        events
+            .iter()
+            .find(|e| matches!(e, HostEvent::ActorStopped { .. }))
+    );
+
+    host.shutdown()?;
+    assert_eq!(
+        Some(HostEvent::HostStopped),
+        events.iter().find(|e| matches!(e, HostEvent::HostStopped))
+    );
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use latticeclient::Client;
 use std::error::Error;
+use std::sync::Arc;
 
 pub(crate) fn lattice_single_host() -> Result<(), Box<dyn Error>> {
     use std::time::Duration;
@@ -414,3 +415,63 @@ pub(crate) fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error
 
     Ok(())
 }
+
+pub(crate) fn remote_invocations_are_drained() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+    use wascap::prelude::KeyPair;
+    use wascc_host::{
+        HostBuilder, Invocation, InvocationErrorCode, InvocationResponse, WasccEntity,
+    };
+
+    let host = HostBuilder::new()
+        .with_lattice_namespace("remotedrain")
+        .build();
+    let spinner = crate::common::get_spinning_actor()?.with_timeout(Duration::from_millis(500));
+    let pk = spinner.public_key();
+    host.add_actor(spinner)?;
+
+    // Invocations sent to the host's actor by another host on the lattice
+    let remote = Arc::new(KeyPair::new_server());
+    let subject = format!("remotedrain.wasmbus.actor.{}", pk);
+    let invoke = move || -> InvocationResponse {
+        let nc = nats::connect("127.0.0.1").unwrap();
+        let inv = Invocation::new(
+            &remote,
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor(pk.to_string()),
+            "Spin",
+            vec![],
+        );
+        let resp = nc
+            .request_timeout(
+                &subject,
+                &wascc_codec::serialize(&inv).unwrap(),
+                Duration::from_secs(2),
+            )
+            .unwrap();
+        wascc_codec::deserialize(&resp.data).unwrap()
+    };
+
+    let running = std::thread::spawn({
+        let invoke = invoke.clone();
+        move || invoke()
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    let shutdown = std::thread::spawn({
+        let host = host.clone();
+        move || host.shutdown_graceful(Duration::from_secs(5)).unwrap()
+    });
+    std::thread::sleep(Duration::from_millis(100));
+
+    // The host refuses new remote invocations while it waits for the running one
+    assert_eq!(Some(InvocationErrorCode::Unavailable), invoke().error_code);
+    assert_eq!(
+        Some(InvocationErrorCode::Timeout),
+        running.join().unwrap().error_code
+    );
+    let report = shutdown.join().unwrap();
+    assert_eq!(0, report.pending_invocations);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    Ok(())
+}
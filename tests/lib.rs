@@ -22,6 +22,38 @@ fn authorizer_blocks_load() -> Result<(), Box<dyn Error>> {
     auth::authorizer_blocks_load()
 }
 
+#[test]
+fn untrusted_issuers_are_rejected() -> Result<(), Box<dyn Error>> {
+    auth::untrusted_issuers_are_rejected()
+}
+
+#[test]
+fn revoked_actors_are_terminated() -> Result<(), Box<dyn Error>> {
+    auth::revoked_actors_are_terminated()
+}
+
+#[test]
+fn authorizer_blocks_providers() -> Result<(), Box<dyn Error>> {
+    auth::authorizer_blocks_providers()
+}
+
+#[test]
+fn actor_calls_need_claims() -> Result<(), Box<dyn Error>> {
+    auth::actor_calls_need_claims()
+}
+
+#[cfg(feature = "provider_archive")]
+#[test]
+fn provider_archives_are_verified() -> Result<(), Box<dyn Error>> {
+    auth::provider_archives_are_verified()
+}
+
+#[cfg(feature = "manifest")]
+#[test]
+fn policy_authorizer_from_manifest() -> Result<(), Box<dyn Error>> {
+    auth::policy_authorizer_from_manifest()
+}
+
 #[test]
 fn stock_host() -> Result<(), Box<dyn Error>> {
     core::stock_host()
@@ -32,6 +64,105 @@ fn kv_host() -> Result<(), Box<dyn Error>> {
     core::kv_host()
 }
 
+#[test]
+fn portable_provider_bindings() -> Result<(), Box<dyn Error>> {
+    core::portable_provider_bindings()
+}
+
+#[test]
+fn scaled_actor_instances() -> Result<(), Box<dyn Error>> {
+    core::scaled_actor_instances()
+}
+
+#[test]
+fn actor_execution_timeout() -> Result<(), Box<dyn Error>> {
+    core::actor_execution_timeout()
+}
+
+#[test]
+fn actor_resource_limits() -> Result<(), Box<dyn Error>> {
+    core::actor_resource_limits()
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_host_calls() -> Result<(), Box<dyn Error>> {
+    core::async_host_calls().await
+}
+
+#[test]
+fn unknown_wapc_import() -> Result<(), Box<dyn Error>> {
+    core::unknown_wapc_import()
+}
+
+#[test]
+fn graceful_shutdown() -> Result<(), Box<dyn Error>> {
+    core::graceful_shutdown()
+}
+
+#[test]
+fn pause_resume_drain() -> Result<(), Box<dyn Error>> {
+    core::pause_resume_drain()
+}
+
+#[test]
+fn scale_down_during_calls() -> Result<(), Box<dyn Error>> {
+    core::scale_down_during_calls()
+}
+
+#[test]
+fn replace_actor_rollback() -> Result<(), Box<dyn Error>> {
+    core::replace_actor_rollback()
+}
+
+#[test]
+fn canary_actor_upgrade() -> Result<(), Box<dyn Error>> {
+    core::canary_actor_upgrade()
+}
+
+#[test]
+fn actor_revision_history() -> Result<(), Box<dyn Error>> {
+    core::actor_revision_history()
+}
+
+#[test]
+fn call_aliases() -> Result<(), Box<dyn Error>> {
+    core::call_aliases()
+}
+
+#[cfg(unix)]
+#[test]
+fn isolated_provider_restarts() -> Result<(), Box<dyn Error>> {
+    core::isolated_provider_restarts()
+}
+
+#[cfg(unix)]
+#[test]
+fn native_provider_hot_replacement() -> Result<(), Box<dyn Error>> {
+    core::native_provider_hot_replacement()
+}
+
+#[cfg(unix)]
+#[test]
+fn native_provider_replacement_under_load() -> Result<(), Box<dyn Error>> {
+    core::native_provider_replacement_under_load()
+}
+
+#[test]
+fn invocation_error_codes() -> Result<(), Box<dyn Error>> {
+    core::invocation_error_codes()
+}
+
+#[test]
+fn host_events() -> Result<(), Box<dyn Error>> {
+    core::host_events()
+}
+
+#[test]
+fn actor_restart_on_trap() -> Result<(), Box<dyn Error>> {
+    core::actor_restart_on_trap()
+}
+
 #[test]
 #[cfg(feature = "lattice")]
 fn unload_reload_actor_retains_bindings() -> Result<(), Box<dyn Error>> {
@@ -50,6 +181,12 @@ fn instance_count() -> Result<(), Box<dyn Error>> {
     lattice::instance_count()
 }
 
+#[test]
+#[cfg(feature = "lattice")]
+fn remote_invocations_are_drained() -> Result<(), Box<dyn Error>> {
+    lattice::remote_invocations_are_drained()
+}
+
 #[test]
 #[cfg(feature = "lattice")]
 fn lattice_single_host() -> Result<(), Box<dyn Error>> {